@@ -0,0 +1,595 @@
+use std::str::FromStr;
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    post::{create_pipeline, fullscreen_pass},
+    texture::Texture,
+};
+
+/// Anti-aliasing done as a post pass on the tonemapped image, much cheaper
+/// than MSAA where fill rate is scarce, like on WebGL2. Unlike MSAA it also
+/// smooths edges inside textures and shaders, but it can only guess at
+/// detail smaller than a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Antialiasing {
+    #[default]
+    None,
+    /// Fast approximate anti-aliasing: a single pass that blurs along edges
+    /// it finds by contrast, softening the image a little overall.
+    Fxaa,
+    /// Subpixel morphological anti-aliasing: finds edges and the shapes
+    /// they form in three passes, for sharper results than FXAA at a
+    /// somewhat higher cost.
+    Smaa,
+}
+
+impl Antialiasing {
+    pub const ALL: [Self; 3] = [Self::None, Self::Fxaa, Self::Smaa];
+
+    /// The next one in `ALL`, wrapping around, to cycle through at runtime.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&a| a == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+impl FromStr for Antialiasing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "fxaa" => Ok(Self::Fxaa),
+            "smaa" => Ok(Self::Smaa),
+            _ => bail!("Unknown anti-aliasing {s:?}, expected none, fxaa or smaa"),
+        }
+    }
+}
+
+/// Format of the tonemapped image the passes read. It holds sRGB-encoded
+/// values without being an sRGB format, so edges are found by perceived
+/// brightness.
+pub(crate) const LDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const EDGES_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg8Unorm;
+const WEIGHTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// Texels per side of one pattern in the area texture, a pixel's distance
+/// to either end of its edge being the square of the coordinate.
+const AREA_MAX_DISTANCE: usize = 16;
+/// Patterns per side of the area texture, for crossing edges of 0, 1, 3 and 4
+/// quarters; 2 never occurs.
+const AREA_PATTERNS: usize = 5;
+const AREA_SIZE: usize = AREA_MAX_DISTANCE * AREA_PATTERNS;
+/// Edges longer than this aren't smoothed towards sharp corners.
+const SMOOTH_MAX_DISTANCE: f32 = 32.0;
+/// Left and right search halves of 33 entries each, see `search_texture`.
+const SEARCH_WIDTH: usize = 66;
+const SEARCH_HEIGHT: usize = 33;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AntialiasUniform {
+    decode_srgb: u32,
+    _padding: [u32; 3],
+}
+
+/// Intermediate targets, only allocated for the mode in use
+struct Targets {
+    /// The tonemapped image
+    ldr: Texture,
+    /// SMAA's edges and blending weights
+    smaa: Option<(Texture, Texture)>,
+    /// Reading the LDR image, and the weights for SMAA
+    color_bind_group: wgpu::BindGroup,
+    /// Reading the edges to calculate the weights
+    edges_bind_group: Option<wgpu::BindGroup>,
+}
+
+/// The anti-aliasing passes at the end of [`crate::post::PostProcess`].
+pub(crate) struct AntialiasingPass {
+    mode: Antialiasing,
+    width: u32,
+    height: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    linear_sampler: wgpu::Sampler,
+    point_sampler: wgpu::Sampler,
+    area: Texture,
+    search: Texture,
+    /// Stands in for the weights while FXAA runs, and for the texture being
+    /// written while calculating the weights
+    placeholder: Texture,
+    fxaa_pipeline: wgpu::RenderPipeline,
+    smaa_edges_pipeline: wgpu::RenderPipeline,
+    smaa_weights_pipeline: wgpu::RenderPipeline,
+    smaa_blend_pipeline: wgpu::RenderPipeline,
+    targets: Option<Targets>,
+}
+
+impl AntialiasingPass {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                sampler_entry(5),
+                sampler_entry(6),
+            ],
+            label: Some("antialiasing_bind_group_layout"),
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Antialiasing Buffer"),
+            contents: bytemuck::bytes_of(&AntialiasUniform {
+                decode_srgb: output_format.is_srgb() as u32,
+                _padding: [0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let linear_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Antialiasing Linear Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let point_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Antialiasing Point Sampler"),
+            ..Default::default()
+        });
+
+        let area = create_lookup(
+            device,
+            queue,
+            "smaa_area",
+            AREA_SIZE,
+            AREA_SIZE,
+            wgpu::TextureFormat::Rg8Unorm,
+            &area_texture(),
+        );
+        let search = create_lookup(
+            device,
+            queue,
+            "smaa_search",
+            SEARCH_WIDTH,
+            SEARCH_HEIGHT,
+            wgpu::TextureFormat::R8Unorm,
+            &search_texture(),
+        );
+        let placeholder = create_lookup(
+            device,
+            queue,
+            "antialiasing_placeholder",
+            1,
+            1,
+            WEIGHTS_FORMAT,
+            &[0; 4],
+        );
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Antialiasing Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("antialiasing.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Antialiasing Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point, format| {
+            create_pipeline(device, &layout, &shader, entry_point, format, None)
+        };
+        let fxaa_pipeline = pipeline("fs_fxaa", output_format);
+        let smaa_edges_pipeline = pipeline("fs_smaa_edges", EDGES_FORMAT);
+        let smaa_weights_pipeline = pipeline("fs_smaa_weights", WEIGHTS_FORMAT);
+        let smaa_blend_pipeline = pipeline("fs_smaa_blend", output_format);
+
+        Self {
+            mode: Antialiasing::None,
+            width,
+            height,
+            bind_group_layout,
+            uniform_buffer,
+            linear_sampler,
+            point_sampler,
+            area,
+            search,
+            placeholder,
+            fxaa_pipeline,
+            smaa_edges_pipeline,
+            smaa_weights_pipeline,
+            smaa_blend_pipeline,
+            targets: None,
+        }
+    }
+
+    pub(crate) fn set_mode(&mut self, device: &wgpu::Device, mode: Antialiasing) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.rebuild(device);
+        }
+    }
+
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.rebuild(device);
+    }
+
+    /// Where the tonemapped image goes while anti-aliasing is on.
+    pub(crate) fn target(&self) -> Option<&wgpu::TextureView> {
+        self.targets.as_ref().map(|targets| &targets.ldr.view)
+    }
+
+    /// Records the passes from [`AntialiasingPass::target`] to `output`,
+    /// if anti-aliasing is on.
+    pub(crate) fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let Some(targets) = &self.targets else {
+            return;
+        };
+        match (&targets.smaa, &targets.edges_bind_group) {
+            (Some((edges, weights)), Some(edges_bind_group)) => {
+                fullscreen_pass(
+                    encoder,
+                    "SMAA Edges",
+                    &edges.view,
+                    false,
+                    &self.smaa_edges_pipeline,
+                    &targets.color_bind_group,
+                );
+                fullscreen_pass(
+                    encoder,
+                    "SMAA Weights",
+                    &weights.view,
+                    false,
+                    &self.smaa_weights_pipeline,
+                    edges_bind_group,
+                );
+                fullscreen_pass(
+                    encoder,
+                    "SMAA Blend",
+                    output,
+                    false,
+                    &self.smaa_blend_pipeline,
+                    &targets.color_bind_group,
+                );
+            }
+            _ => fullscreen_pass(
+                encoder,
+                "FXAA",
+                output,
+                false,
+                &self.fxaa_pipeline,
+                &targets.color_bind_group,
+            ),
+        }
+    }
+
+    fn rebuild(&mut self, device: &wgpu::Device) {
+        if self.mode == Antialiasing::None {
+            self.targets = None;
+            return;
+        }
+        let target = |format, label| create_target(device, self.width, self.height, format, label);
+        let ldr = target(LDR_FORMAT, "antialiasing_ldr");
+        let smaa = (self.mode == Antialiasing::Smaa).then(|| {
+            (
+                target(EDGES_FORMAT, "smaa_edges"),
+                target(WEIGHTS_FORMAT, "smaa_weights"),
+            )
+        });
+        let weights = smaa
+            .as_ref()
+            .map_or(&self.placeholder, |(_, weights)| weights);
+        let color_bind_group = self.bind_group(device, &ldr, weights);
+        let edges_bind_group = smaa
+            .as_ref()
+            .map(|(edges, _)| self.bind_group(device, edges, &self.placeholder));
+        self.targets = Some(Targets {
+            ldr,
+            smaa,
+            color_bind_group,
+            edges_bind_group,
+        });
+    }
+
+    fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        input: &Texture,
+        blend: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&blend.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.area.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&self.search.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&self.linear_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&self.point_sampler),
+                },
+            ],
+            label: Some("antialiasing_bind_group"),
+        })
+    }
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    label: &str,
+) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn create_lookup(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    width: usize,
+    height: usize,
+    format: wgpu::TextureFormat,
+    data: &[u8],
+) -> Texture {
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width as u32,
+                height: height as u32,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        data,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+/// Area between the edge, along the x axis, and the line from `p1` to `p2`
+/// within the pixel from `x` to `x + 1`: below the edge, which the pixel
+/// under it blends over from the one above, and above it, the other way
+/// around.
+fn area(p1: (f32, f32), p2: (f32, f32), x: f32) -> [f32; 2] {
+    let (x1, x2) = (x.max(p1.0), (x + 1.0).min(p2.0));
+    if x1 >= x2 {
+        return [0.0; 2];
+    }
+    let y = |x: f32| p1.1 + (p2.1 - p1.1) * (x - p1.0) / (p2.0 - p1.0);
+    let (y1, y2) = (y(x1), y(x2));
+    // Two triangles if the line crosses the edge within the pixel
+    let parts = if y1 * y2 < 0.0 {
+        let crossing = x1 + (x2 - x1) * y1 / (y1 - y2);
+        [(x1, y1, crossing, 0.0), (crossing, 0.0, x2, y2)]
+    } else {
+        [(x1, y1, x2, y2), (x2, 0.0, x2, 0.0)]
+    };
+    let mut areas = [0.0; 2];
+    for (xa, ya, xb, yb) in parts {
+        let signed = (ya + yb) / 2.0 * (xb - xa);
+        if signed < 0.0 {
+            areas[0] -= signed;
+        } else {
+            areas[1] += signed;
+        }
+    }
+    areas
+}
+
+/// Rounds off the corners of short U shapes, which are more likely meant to
+/// be sharp than long ones.
+fn smooth_area(d: f32, a1: [f32; 2], a2: [f32; 2]) -> [f32; 2] {
+    let p = (d / SMOOTH_MAX_DISTANCE).clamp(0.0, 1.0);
+    let smooth = |a: f32| {
+        let b = (a * 2.0).sqrt() * 0.5;
+        b + (a - b) * p
+    };
+    [smooth(a1[0]) + smooth(a2[0]), smooth(a1[1]) + smooth(a2[1])]
+}
+
+/// Blending weights of the pixel `left` pixels from the left end and `right`
+/// from the right end of an edge, whose ends have crossing edges `e1` and
+/// `e2`: 1 above the edge, 3 below it, 4 both and 0 none, in quarters as
+/// SMAA fetches them. The line through the edge is reconstructed from the
+/// crossing edges, going through the middle of the step at either end.
+fn ortho_area(e1: usize, e2: usize, left: f32, right: f32) -> [f32; 2] {
+    let d = left + right + 1.0;
+    let (up, down) = (0.5, -0.5);
+    match (e1, e2) {
+        // Steps at one end only
+        (3, 0) if left <= right => area((0.0, down), (d / 2.0, 0.0), left),
+        (0, 3) if left >= right => area((d / 2.0, 0.0), (d, down), left),
+        (1, 0) if left <= right => area((0.0, up), (d / 2.0, 0.0), left),
+        (0, 1) if left >= right => area((d / 2.0, 0.0), (d, up), left),
+        // U shapes
+        (3, 3) => smooth_area(
+            d,
+            area((0.0, down), (d / 2.0, 0.0), left),
+            area((d / 2.0, 0.0), (d, down), left),
+        ),
+        (1, 1) => smooth_area(
+            d,
+            area((0.0, up), (d / 2.0, 0.0), left),
+            area((d / 2.0, 0.0), (d, up), left),
+        ),
+        // Z shapes, going by the one unambiguous end if the other has
+        // crossing edges on both sides
+        (1, 3) | (4, 3) | (1, 4) => area((0.0, up), (d, down), left),
+        (3, 1) | (3, 4) | (4, 1) => area((0.0, down), (d, up), left),
+        _ => [0.0; 2],
+    }
+}
+
+/// SMAA's area texture, as Rg8Unorm: a block of `AREA_MAX_DISTANCE`
+/// texels squared per combination of crossing edges at the two ends,
+/// indexed by the square roots of the distances to them.
+fn area_texture() -> Vec<u8> {
+    let mut data = vec![0; AREA_SIZE * AREA_SIZE * 2];
+    for e1 in [0, 1, 3, 4] {
+        for e2 in [0, 1, 3, 4] {
+            for y in 0..AREA_MAX_DISTANCE {
+                for x in 0..AREA_MAX_DISTANCE {
+                    let (left, right) = ((x * x) as f32, (y * y) as f32);
+                    let areas = ortho_area(e1, e2, left, right);
+                    let texel =
+                        (e2 * AREA_MAX_DISTANCE + y) * AREA_SIZE + e1 * AREA_MAX_DISTANCE + x;
+                    for (channel, area) in areas.into_iter().enumerate() {
+                        data[texel * 2 + channel] = (area.clamp(0.0, 1.0) * 255.0).round() as u8;
+                    }
+                }
+            }
+        }
+    }
+    data
+}
+
+/// SMAA's search texture, as R8Unorm. The searches fetch the edges of two
+/// pixels and the ones above them in one bilinear sample, at a quarter pixel
+/// towards the further pixel and an eighth up. That mixes the four in 32nds:
+///
+/// ```text
+///  1  3
+///  7 21 <- the nearer pixel
+/// ```
+///
+/// The left half of the texture, indexed by the mixed left edges along x and
+/// the mixed top edges along y, holds how many of the two pixels still
+/// belong to the edge when searching left, times 127; the right half, the
+/// same for searching right.
+fn search_texture() -> Vec<u8> {
+    const WEIGHTS: [usize; 4] = [1, 3, 7, 21];
+    let bits = |value: usize| [0, 1, 2, 3].map(|bit| value >> bit & 1 == 1);
+    let fetch = |edges: [bool; 4]| {
+        (0..4)
+            .filter(|&i| edges[i])
+            .map(|i| WEIGHTS[i])
+            .sum::<usize>()
+    };
+
+    let mut data = vec![0; SEARCH_WIDTH * SEARCH_HEIGHT];
+    for left in 0..16 {
+        for top in 0..16 {
+            let (left, top) = (bits(left), bits(top));
+            let row = (SEARCH_HEIGHT - 1 - fetch(top)) * SEARCH_WIDTH;
+            data[row + fetch(left)] = 127 * delta_left(left, top);
+            data[row + SEARCH_WIDTH / 2 + fetch(left)] = 127 * delta_right(left, top);
+        }
+    }
+    data
+}
+
+/// Going left, the nearer pixel belongs to the edge if it has a top edge,
+/// and the further one too if it has one and nothing crosses between them.
+fn delta_left(left: [bool; 4], top: [bool; 4]) -> u8 {
+    let mut d = 0;
+    if top[3] {
+        d += 1;
+        if top[2] && !left[1] && !left[3] {
+            d += 1;
+        }
+    }
+    d
+}
+
+/// Going right, crossing edges on the left of the nearer pixel end the
+/// edge before it, and those of the further one before that.
+fn delta_right(left: [bool; 4], top: [bool; 4]) -> u8 {
+    let mut d = 0;
+    if top[3] && !left[1] && !left[3] {
+        d += 1;
+        if top[2] && !left[0] && !left[2] {
+            d += 1;
+        }
+    }
+    d
+}
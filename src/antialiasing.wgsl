@@ -0,0 +1,412 @@
+// Post-process anti-aliasing of the tonemapped image: FXAA in one pass, or
+// SMAA 1x in three. Both read the sRGB-encoded LDR image, so edges are found
+// by perceived brightness.
+
+struct Antialias {
+    // Whether the output is an sRGB target, which encodes by itself
+    decode_srgb: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+}
+@group(0) @binding(0)
+var<uniform> antialias: Antialias;
+// The LDR image, or SMAA's edges while calculating blending weights
+@group(0) @binding(1)
+var t_input: texture_2d<f32>;
+// SMAA's blending weights
+@group(0) @binding(2)
+var t_blend: texture_2d<f32>;
+@group(0) @binding(3)
+var t_area: texture_2d<f32>;
+@group(0) @binding(4)
+var t_search: texture_2d<f32>;
+@group(0) @binding(5)
+var s_linear: sampler;
+@group(0) @binding(6)
+var s_point: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0 to 1 across the target, from the top left
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn srgb_decode(encoded: vec3<f32>) -> vec3<f32> {
+    let low = encoded / 12.92;
+    let high = pow((encoded + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, encoded <= vec3<f32>(0.04045));
+}
+
+fn output(color: vec4<f32>) -> vec4<f32> {
+    if antialias.decode_srgb != 0u {
+        return vec4<f32>(srgb_decode(color.rgb), color.a);
+    }
+    return color;
+}
+
+// Size of a texel and of the target: 1 / width, 1 / height, width, height
+fn metrics() -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_input));
+    return vec4<f32>(1.0 / size, size);
+}
+
+// FXAA 3.11 by Timothy Lottes, at the quality of its 12 step preset
+
+const FXAA_EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const FXAA_EDGE_THRESHOLD_MAX: f32 = 0.125;
+const FXAA_ITERATIONS: i32 = 12;
+const FXAA_SUBPIXEL_QUALITY: f32 = 0.75;
+
+// How far each step along an edge goes, in pixels
+fn fxaa_step(i: i32) -> f32 {
+    switch i {
+        case 5: { return 1.5; }
+        case 6, 7, 8, 9: { return 2.0; }
+        case 10: { return 4.0; }
+        case 11: { return 8.0; }
+        default: { return 1.0; }
+    }
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn fxaa_luma(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(t_input, s_linear, uv, 0.0).rgb);
+}
+
+@fragment
+fn fs_fxaa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = metrics().xy;
+    let uv = in.uv;
+    let center = textureSampleLevel(t_input, s_linear, uv, 0.0);
+    let luma_center = luma(center.rgb);
+    let luma_up = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(0, -1)).rgb);
+    let luma_down = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(0, 1)).rgb);
+    let luma_left = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(-1, 0)).rgb);
+    let luma_right = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(1, 0)).rgb);
+
+    // Leave pixels without enough contrast around them alone
+    let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    let luma_range = luma_max - luma_min;
+    if luma_range < max(FXAA_EDGE_THRESHOLD_MIN, luma_max * FXAA_EDGE_THRESHOLD_MAX) {
+        return output(center);
+    }
+
+    let luma_up_left = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(-1, -1)).rgb);
+    let luma_up_right = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(1, -1)).rgb);
+    let luma_down_left = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(-1, 1)).rgb);
+    let luma_down_right = luma(textureSampleLevel(t_input, s_linear, uv, 0.0, vec2<i32>(1, 1)).rgb);
+
+    let luma_up_down = luma_up + luma_down;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_up_left + luma_down_left;
+    let luma_right_corners = luma_up_right + luma_down_right;
+    let luma_up_corners = luma_up_left + luma_up_right;
+    let luma_down_corners = luma_down_left + luma_down_right;
+
+    // Whether the edge runs along x or along y
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_up_down) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let horizontal = edge_horizontal >= edge_vertical;
+
+    // Which side of the pixel the edge is on
+    let luma1 = select(luma_left, luma_up, horizontal);
+    let luma2 = select(luma_right, luma_down, horizontal);
+    let gradient1 = luma1 - luma_center;
+    let gradient2 = luma2 - luma_center;
+    let steepest1 = abs(gradient1) >= abs(gradient2);
+    let gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    var step_length = select(texel.x, texel.y, horizontal);
+    var luma_local_average = 0.5 * (luma2 + luma_center);
+    if steepest1 {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma1 + luma_center);
+    }
+
+    // Walk along the edge, half a pixel over, in both directions until the
+    // contrast changes
+    var edge_uv = uv;
+    if horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), horizontal);
+    var uv1 = edge_uv - offset;
+    var uv2 = edge_uv + offset;
+    var luma_end1 = fxaa_luma(uv1) - luma_local_average;
+    var luma_end2 = fxaa_luma(uv2) - luma_local_average;
+    var reached1 = abs(luma_end1) >= gradient_scaled;
+    var reached2 = abs(luma_end2) >= gradient_scaled;
+    if !reached1 {
+        uv1 -= offset * fxaa_step(1);
+    }
+    if !reached2 {
+        uv2 += offset * fxaa_step(1);
+    }
+    for (var i = 2; i < FXAA_ITERATIONS && !(reached1 && reached2); i++) {
+        if !reached1 {
+            luma_end1 = fxaa_luma(uv1) - luma_local_average;
+        }
+        if !reached2 {
+            luma_end2 = fxaa_luma(uv2) - luma_local_average;
+        }
+        reached1 = abs(luma_end1) >= gradient_scaled;
+        reached2 = abs(luma_end2) >= gradient_scaled;
+        if !reached1 {
+            uv1 -= offset * fxaa_step(i);
+        }
+        if !reached2 {
+            uv2 += offset * fxaa_step(i);
+        }
+    }
+
+    // Shift towards the nearer end, if the edge actually ends there
+    let distance1 = select(uv.x - uv1.x, uv.y - uv1.y, !horizontal);
+    let distance2 = select(uv2.x - uv.x, uv2.y - uv.y, !horizontal);
+    let nearer1 = distance1 < distance2;
+    let distance = min(distance1, distance2);
+    let edge_length = distance1 + distance2;
+    let center_smaller = luma_center < luma_local_average;
+    let correct1 = (luma_end1 < 0.0) != center_smaller;
+    let correct2 = (luma_end2 < 0.0) != center_smaller;
+    let correct = select(correct2, correct1, nearer1);
+    var final_offset = select(0.0, 0.5 - distance / edge_length, correct);
+
+    // Thin lines and single pixels, which the edge walk misses
+    let luma_average = (1.0 / 12.0) * (2.0 * (luma_up_down + luma_left_right)
+        + luma_left_corners + luma_right_corners);
+    let subpixel1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    let subpixel2 = (-2.0 * subpixel1 + 3.0) * subpixel1 * subpixel1;
+    final_offset = max(final_offset, subpixel2 * subpixel2 * FXAA_SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if horizontal {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    let color = textureSampleLevel(t_input, s_linear, final_uv, 0.0).rgb;
+    return output(vec4<f32>(color, center.a));
+}
+
+// SMAA 1x by Jorge Jimenez et al., with the threshold, search distance and
+// corner rounding of its high preset. Diagonal patterns aren't detected, so
+// the area texture only holds the orthogonal ones.
+
+const SMAA_THRESHOLD: f32 = 0.1;
+const SMAA_MAX_SEARCH_STEPS: f32 = 16.0;
+const SMAA_CORNER_ROUNDING: f32 = 0.25;
+const SMAA_LOCAL_CONTRAST_ADAPTATION_FACTOR: f32 = 2.0;
+// Layout of the lookup textures, see `antialiasing.rs`
+const SMAA_AREA_MAX_DISTANCE: f32 = 16.0;
+const SMAA_AREA_SIZE: vec2<f32> = vec2<f32>(80.0, 80.0);
+const SMAA_SEARCH_SIZE: vec2<f32> = vec2<f32>(66.0, 33.0);
+
+// Marks where the luma changes across the left (red) and top (green) edges
+// of each pixel, unless a much stronger change nearby dominates it
+@fragment
+fn fs_smaa_edges(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.uv;
+    let weights = vec3<f32>(0.2126, 0.7152, 0.0722);
+    let l = dot(textureSampleLevel(t_input, s_point, uv, 0.0).rgb, weights);
+    let l_left = dot(textureSampleLevel(t_input, s_point, uv, 0.0, vec2<i32>(-1, 0)).rgb, weights);
+    let l_top = dot(textureSampleLevel(t_input, s_point, uv, 0.0, vec2<i32>(0, -1)).rgb, weights);
+    let delta = abs(l - vec2<f32>(l_left, l_top));
+    var edges = step(vec2<f32>(SMAA_THRESHOLD), delta);
+    if dot(edges, vec2<f32>(1.0)) == 0.0 {
+        return vec4<f32>(0.0);
+    }
+
+    let l_right = dot(textureSampleLevel(t_input, s_point, uv, 0.0, vec2<i32>(1, 0)).rgb, weights);
+    let l_bottom = dot(textureSampleLevel(t_input, s_point, uv, 0.0, vec2<i32>(0, 1)).rgb, weights);
+    var max_delta = max(delta, abs(l - vec2<f32>(l_right, l_bottom)));
+    let l_left_left = dot(textureSampleLevel(t_input, s_point, uv, 0.0, vec2<i32>(-2, 0)).rgb, weights);
+    let l_top_top = dot(textureSampleLevel(t_input, s_point, uv, 0.0, vec2<i32>(0, -2)).rgb, weights);
+    max_delta = max(max_delta, abs(vec2<f32>(l_left, l_top) - vec2<f32>(l_left_left, l_top_top)));
+    let final_delta = max(max_delta.x, max_delta.y);
+    edges *= step(vec2<f32>(final_delta), SMAA_LOCAL_CONTRAST_ADAPTATION_FACTOR * delta);
+    return vec4<f32>(edges, 0.0, 0.0);
+}
+
+// How many more pixels the edge goes on for past where a search stopped,
+// from the two edge values fetched there at once
+fn smaa_search_length(e: vec2<f32>, offset: f32) -> f32 {
+    let scale = (SMAA_SEARCH_SIZE * vec2<f32>(0.5, -1.0) + vec2<f32>(-1.0, 1.0)) / SMAA_SEARCH_SIZE;
+    let bias = (SMAA_SEARCH_SIZE * vec2<f32>(offset, 1.0) + vec2<f32>(0.5, -0.5)) / SMAA_SEARCH_SIZE;
+    return textureSampleLevel(t_search, s_point, scale * e + bias, 0.0).r;
+}
+
+// The searches go two pixels at a time, fetching the edges of both with a
+// single bilinear sample between them
+
+fn smaa_search_x_left(start: vec2<f32>, end: f32) -> f32 {
+    let texel = metrics().xy;
+    var uv = start;
+    var e = vec2<f32>(0.0, 1.0);
+    while uv.x > end && e.g > 0.8281 && e.r == 0.0 {
+        e = textureSampleLevel(t_input, s_linear, uv, 0.0).rg;
+        uv.x -= 2.0 * texel.x;
+    }
+    let offset = -(255.0 / 127.0) * smaa_search_length(e, 0.0) + 3.25;
+    return texel.x * offset + uv.x;
+}
+
+fn smaa_search_x_right(start: vec2<f32>, end: f32) -> f32 {
+    let texel = metrics().xy;
+    var uv = start;
+    var e = vec2<f32>(0.0, 1.0);
+    while uv.x < end && e.g > 0.8281 && e.r == 0.0 {
+        e = textureSampleLevel(t_input, s_linear, uv, 0.0).rg;
+        uv.x += 2.0 * texel.x;
+    }
+    let offset = -(255.0 / 127.0) * smaa_search_length(e, 0.5) + 3.25;
+    return -texel.x * offset + uv.x;
+}
+
+fn smaa_search_y_up(start: vec2<f32>, end: f32) -> f32 {
+    let texel = metrics().xy;
+    var uv = start;
+    var e = vec2<f32>(1.0, 0.0);
+    while uv.y > end && e.r > 0.8281 && e.g == 0.0 {
+        e = textureSampleLevel(t_input, s_linear, uv, 0.0).rg;
+        uv.y -= 2.0 * texel.y;
+    }
+    let offset = -(255.0 / 127.0) * smaa_search_length(e.gr, 0.0) + 3.25;
+    return texel.y * offset + uv.y;
+}
+
+fn smaa_search_y_down(start: vec2<f32>, end: f32) -> f32 {
+    let texel = metrics().xy;
+    var uv = start;
+    var e = vec2<f32>(1.0, 0.0);
+    while uv.y < end && e.r > 0.8281 && e.g == 0.0 {
+        e = textureSampleLevel(t_input, s_linear, uv, 0.0).rg;
+        uv.y += 2.0 * texel.y;
+    }
+    let offset = -(255.0 / 127.0) * smaa_search_length(e.gr, 0.5) + 3.25;
+    return -texel.y * offset + uv.y;
+}
+
+// Blending weights for a pixel `sqrt_d` from both ends of an edge, with the
+// crossing edges `e1` and `e2` at its ends
+fn smaa_area(sqrt_d: vec2<f32>, e1: f32, e2: f32) -> vec2<f32> {
+    let texel = SMAA_AREA_MAX_DISTANCE * round(4.0 * vec2<f32>(e1, e2)) + sqrt_d;
+    return textureSampleLevel(t_area, s_linear, (texel + 0.5) / SMAA_AREA_SIZE, 0.0).rg;
+}
+
+// Keeps sharp corners from being rounded off entirely
+fn smaa_horizontal_corners(weights: vec2<f32>, uv: vec4<f32>, d: vec2<f32>) -> vec2<f32> {
+    let left_right = step(d, d.yx);
+    let rounding = (1.0 - SMAA_CORNER_ROUNDING) * left_right / (left_right.x + left_right.y);
+    var factor = vec2<f32>(1.0);
+    factor.x -= rounding.x * textureSampleLevel(t_input, s_linear, uv.xy, 0.0, vec2<i32>(0, 1)).r;
+    factor.x -= rounding.y * textureSampleLevel(t_input, s_linear, uv.zw, 0.0, vec2<i32>(1, 1)).r;
+    factor.y -= rounding.x * textureSampleLevel(t_input, s_linear, uv.xy, 0.0, vec2<i32>(0, -2)).r;
+    factor.y -= rounding.y * textureSampleLevel(t_input, s_linear, uv.zw, 0.0, vec2<i32>(1, -2)).r;
+    return weights * saturate(factor);
+}
+
+fn smaa_vertical_corners(weights: vec2<f32>, uv: vec4<f32>, d: vec2<f32>) -> vec2<f32> {
+    let left_right = step(d, d.yx);
+    let rounding = (1.0 - SMAA_CORNER_ROUNDING) * left_right / (left_right.x + left_right.y);
+    var factor = vec2<f32>(1.0);
+    factor.x -= rounding.x * textureSampleLevel(t_input, s_linear, uv.xy, 0.0, vec2<i32>(1, 0)).g;
+    factor.x -= rounding.y * textureSampleLevel(t_input, s_linear, uv.zw, 0.0, vec2<i32>(1, 1)).g;
+    factor.y -= rounding.x * textureSampleLevel(t_input, s_linear, uv.xy, 0.0, vec2<i32>(-2, 0)).g;
+    factor.y -= rounding.y * textureSampleLevel(t_input, s_linear, uv.zw, 0.0, vec2<i32>(-2, 1)).g;
+    return weights * saturate(factor);
+}
+
+// Reads the edges from `t_input`. Finds the ends of the edges along the top
+// and left of each pixel and the shape they form, and from that how much of
+// the pixels on either side to blend: red and green across the top edge,
+// blue and alpha across the left one.
+@fragment
+fn fs_smaa_weights(in: VertexOutput) -> @location(0) vec4<f32> {
+    let rt = metrics();
+    let uv = in.uv;
+    let pixel = uv * rt.zw;
+    let offset0 = uv.xyxy + rt.xyxy * vec4<f32>(-0.25, -0.125, 1.25, -0.125);
+    let offset1 = uv.xyxy + rt.xyxy * vec4<f32>(-0.125, -0.25, -0.125, 1.25);
+    let end = rt.xxyy * vec4<f32>(-2.0, 2.0, -2.0, 2.0) * SMAA_MAX_SEARCH_STEPS
+        + vec4<f32>(offset0.xz, offset1.yw);
+
+    var weights = vec4<f32>(0.0);
+    // Linear like the searches, since GL allows only one sampler per texture
+    let e = textureSampleLevel(t_input, s_linear, uv, 0.0).rg;
+    if e.g > 0.0 {
+        let left = smaa_search_x_left(offset0.xy, end.x);
+        let right = smaa_search_x_right(offset0.zw, end.y);
+        // A quarter pixel up, so the crossing edges above and below the ends
+        // can be told apart
+        let e1 = textureSampleLevel(t_input, s_linear, vec2<f32>(left, offset1.y), 0.0).r;
+        let e2 = textureSampleLevel(t_input, s_linear, vec2<f32>(right, offset1.y), 0.0, vec2<i32>(1, 0)).r;
+        let d = abs(round(rt.zz * vec2<f32>(left, right) - pixel.xx));
+        let area = smaa_area(sqrt(d), e1, e2);
+        let rg = smaa_horizontal_corners(area, vec4<f32>(left, uv.y, right, uv.y), d);
+        weights = vec4<f32>(rg, weights.ba);
+    }
+    if e.r > 0.0 {
+        let up = smaa_search_y_up(offset1.xy, end.z);
+        let down = smaa_search_y_down(offset1.zw, end.w);
+        let e1 = textureSampleLevel(t_input, s_linear, vec2<f32>(offset0.x, up), 0.0).g;
+        let e2 = textureSampleLevel(t_input, s_linear, vec2<f32>(offset0.x, down), 0.0, vec2<i32>(0, 1)).g;
+        let d = abs(round(rt.ww * vec2<f32>(up, down) - pixel.yy));
+        let area = smaa_area(sqrt(d), e1, e2);
+        let ba = smaa_vertical_corners(area, vec4<f32>(uv.x, up, uv.x, down), d);
+        weights = vec4<f32>(weights.rg, ba);
+    }
+    return weights;
+}
+
+// Blends each pixel with its neighbors by the weights, with a single
+// bilinear fetch per direction
+@fragment
+fn fs_smaa_blend(in: VertexOutput) -> @location(0) vec4<f32> {
+    let rt = metrics();
+    let uv = in.uv;
+    let own = textureSampleLevel(t_blend, s_point, uv, 0.0);
+    // Right, bottom, left and top
+    let a = vec4<f32>(
+        textureSampleLevel(t_blend, s_point, uv, 0.0, vec2<i32>(1, 0)).a,
+        textureSampleLevel(t_blend, s_point, uv, 0.0, vec2<i32>(0, 1)).g,
+        own.b,
+        own.r,
+    );
+    if dot(a, vec4<f32>(1.0)) < 1e-5 {
+        return output(textureSampleLevel(t_input, s_linear, uv, 0.0));
+    }
+
+    // Only blend along the stronger direction
+    var offset = vec4<f32>(0.0, a.y, 0.0, a.w);
+    var weight = a.yw;
+    if max(a.x, a.z) > max(a.y, a.w) {
+        offset = vec4<f32>(a.x, 0.0, a.z, 0.0);
+        weight = a.xz;
+    }
+    weight /= dot(weight, vec2<f32>(1.0));
+    let coords = uv.xyxy + offset * vec4<f32>(rt.xy, -rt.xy);
+    let color = weight.x * textureSampleLevel(t_input, s_linear, coords.xy, 0.0)
+        + weight.y * textureSampleLevel(t_input, s_linear, coords.zw, 0.0);
+    return output(color);
+}
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// Timing of one frame, as handed out by [`FrameClock::tick`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameTime {
+    /// Wall-clock seconds since the previous frame, clamped to
+    /// [`FrameClock::max_delta`]. Unaffected by pausing and time scaling,
+    /// so things like camera movement keep working while paused.
+    pub real_delta: f32,
+    /// Simulated seconds since the previous frame: `real_delta` times the
+    /// time scale, or 0 while paused.
+    pub delta: f32,
+    /// How many fixed-size simulation steps to run this frame.
+    pub fixed_steps: u32,
+    /// Length of each fixed step in seconds.
+    pub fixed_delta: f32,
+    /// How far the simulation is between its last step and the next one,
+    /// from 0 to 1, for interpolating what gets drawn.
+    pub alpha: f32,
+    /// Total simulated seconds so far.
+    pub elapsed: f64,
+}
+
+/// Measures frame times and splits simulated time into fixed steps.
+///
+/// Variable-rate updates scale by [`FrameTime::delta`], while anything that
+/// must be deterministic runs [`FrameTime::fixed_steps`] steps of
+/// [`FrameTime::fixed_delta`] each. Leftover time is carried over to the
+/// next frame, so the steps add up exactly to the simulated time.
+#[derive(Debug, Clone)]
+pub struct FrameClock {
+    /// Length of a fixed simulation step.
+    pub fixed_step: Duration,
+    /// Longest frame accounted for. Anything longer, like a breakpoint or
+    /// dragging the window, is cut short so the simulation doesn't have to
+    /// catch up with hundreds of steps at once.
+    pub max_delta: Duration,
+    time_scale: f64,
+    paused: bool,
+    pending_steps: u32,
+    last: Option<Instant>,
+    /// Simulated time not yet consumed by fixed steps
+    accumulator: Duration,
+    elapsed: Duration,
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self {
+            fixed_step: Duration::from_secs(1) / 60,
+            max_delta: Duration::from_millis(250),
+            time_scale: 1.0,
+            paused: false,
+            pending_steps: 0,
+            last: None,
+            accumulator: Duration::ZERO,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Measures the time since the previous call and advances by it. The
+    /// first call reports no time passing.
+    pub fn tick(&mut self) -> FrameTime {
+        let now = Instant::now();
+        let delta = self.last.map_or(Duration::ZERO, |last| now - last);
+        self.last = Some(now);
+        self.advance(delta)
+    }
+
+    /// Advances by `real_delta` of wall-clock time. Unlike [`Self::tick`]
+    /// this doesn't look at the system clock, so it's deterministic.
+    pub fn advance(&mut self, real_delta: Duration) -> FrameTime {
+        let real_delta = real_delta.min(self.max_delta);
+        let fixed_step = self.fixed_step.max(Duration::from_micros(100));
+
+        let (delta, fixed_steps) = if self.paused {
+            // Single steps run exactly one fixed step each, regardless of
+            // the frame time
+            let steps = std::mem::take(&mut self.pending_steps);
+            (fixed_step * steps, steps)
+        } else {
+            let delta = real_delta.mul_f64(self.time_scale);
+            self.accumulator += delta;
+            let steps = (self.accumulator.as_nanos() / fixed_step.as_nanos()) as u32;
+            self.accumulator -= fixed_step * steps;
+            (delta, steps)
+        };
+        self.elapsed += delta;
+
+        FrameTime {
+            real_delta: real_delta.as_secs_f32(),
+            delta: delta.as_secs_f32(),
+            fixed_steps,
+            fixed_delta: fixed_step.as_secs_f32(),
+            alpha: self.accumulator.as_secs_f32() / fixed_step.as_secs_f32(),
+            elapsed: self.elapsed.as_secs_f64(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.pending_steps = 0;
+    }
+
+    pub fn toggle_paused(&mut self) {
+        self.set_paused(!self.paused);
+    }
+
+    /// While paused, advances the simulation by one fixed step on the next
+    /// frame. Does nothing while running.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.pending_steps += 1;
+        }
+    }
+
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    /// Makes simulated time run `scale` times as fast as real time. Negative
+    /// scales are treated as 0.
+    pub fn set_time_scale(&mut self, scale: f64) {
+        self.time_scale = scale.max(0.0);
+    }
+
+    /// Total simulated time so far.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
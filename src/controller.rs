@@ -0,0 +1,496 @@
+use cgmath::{InnerSpace, Rad, Vector2, Vector3};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+use crate::Camera;
+
+/// Orbits the camera around its `target` with the mouse.
+///
+/// Left-drag rotates, right- or middle-drag pans, and the scroll wheel
+/// dollies towards or away from the target. Input isn't applied at once but
+/// accumulated and eased in over the following updates, so the camera keeps
+/// gliding for a moment after the mouse stops.
+#[derive(Debug, Clone)]
+pub struct OrbitController {
+    /// Radians turned per pixel dragged.
+    pub rotate_speed: f32,
+    /// Distance panned per pixel dragged, as a fraction of the distance to
+    /// the target, so panning feels the same at any zoom level.
+    pub pan_speed: f32,
+    /// Fraction of the distance to the target covered per scroll wheel line.
+    pub zoom_speed: f32,
+    /// Fraction of the pending motion left over after a 60th of a second,
+    /// from 0 (no easing) to just below 1 (very sluggish).
+    pub damping: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Largest angle between the view direction and the plane
+    /// perpendicular to `Camera::up`. Below 90° the camera never flips over.
+    pub max_pitch: Rad<f32>,
+    cursor: Option<PhysicalPosition<f64>>,
+    is_rotating: bool,
+    is_panning: bool,
+    /// Yaw and pitch in radians not yet applied
+    pending_rotation: Vector2<f32>,
+    /// Pan in pixels not yet applied
+    pending_pan: Vector2<f32>,
+    /// Scroll wheel lines not yet applied
+    pending_zoom: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            rotate_speed: 0.005,
+            pan_speed: 0.002,
+            zoom_speed: 0.1,
+            damping: 0.75,
+            min_distance: 0.1,
+            max_distance: 1000.0,
+            max_pitch: Rad(89f32.to_radians()),
+            cursor: None,
+            is_rotating: false,
+            is_panning: false,
+            pending_rotation: Vector2::new(0.0, 0.0),
+            pending_pan: Vector2::new(0.0, 0.0),
+            pending_zoom: 0.0,
+        }
+    }
+}
+
+impl OrbitController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles mouse input. Returns whether the event was used.
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let delta = self
+                    .cursor
+                    .map(|last| Vector2::new(position.x - last.x, position.y - last.y));
+                self.cursor = Some(*position);
+                let Some(delta) = delta.map(|d| d.cast::<f32>().unwrap()) else {
+                    return false;
+                };
+                if self.is_rotating {
+                    self.rotate(delta.x, delta.y);
+                } else if self.is_panning {
+                    self.pan(delta.x, delta.y);
+                }
+                self.is_rotating || self.is_panning
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => {
+                        self.is_rotating = is_pressed;
+                        true
+                    }
+                    MouseButton::Right | MouseButton::Middle => {
+                        self.is_panning = is_pressed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Roughly one line per notch of a typical wheel
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 40.0,
+                };
+                self.zoom(lines);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Orbits as if the mouse was dragged by `dx`, `dy` pixels. Dragging
+    /// down looks at the target from further above.
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        self.pending_rotation += Vector2::new(-dx, dy) * self.rotate_speed;
+    }
+
+    /// Pans as if the mouse was dragged by `dx`, `dy` pixels, moving the
+    /// scene along with the cursor.
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        self.pending_pan += Vector2::new(dx, dy);
+    }
+
+    /// Dollies towards the target by `lines` scroll wheel lines, or away
+    /// from it if negative.
+    pub fn zoom(&mut self, lines: f32) {
+        self.pending_zoom += lines;
+    }
+
+    /// Whether some input hasn't been fully applied to the camera yet.
+    pub fn is_moving(&self) -> bool {
+        const EPSILON: f32 = 1e-4;
+        self.pending_rotation.magnitude2() > EPSILON * EPSILON
+            || self.pending_pan.magnitude2() > EPSILON
+            || self.pending_zoom.abs() > EPSILON
+    }
+
+    /// Drops input that hasn't been applied yet and releases the buttons.
+    pub fn stop(&mut self) {
+        self.is_rotating = false;
+        self.is_panning = false;
+        self.pending_rotation = Vector2::new(0.0, 0.0);
+        self.pending_pan = Vector2::new(0.0, 0.0);
+        self.pending_zoom = 0.0;
+    }
+
+    /// Applies the part of the pending input due after `dt` seconds to
+    /// `camera`, as set by `damping`.
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        let step = 1.0 - self.damping.clamp(0.0, 0.99).powf(dt * 60.0);
+        let rotation = self.pending_rotation * step;
+        let pan = self.pending_pan * step;
+        let zoom = self.pending_zoom * step;
+        self.pending_rotation -= rotation;
+        self.pending_pan -= pan;
+        self.pending_zoom -= zoom;
+        if !self.is_moving() {
+            self.pending_rotation = Vector2::new(0.0, 0.0);
+            self.pending_pan = Vector2::new(0.0, 0.0);
+            self.pending_zoom = 0.0;
+        }
+
+        let up = camera.up.normalize();
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude();
+        if distance <= f32::EPSILON {
+            return;
+        }
+        let direction = offset / distance;
+
+        // Pan first, in the plane facing the camera
+        let right = (-direction).cross(up);
+        if right.magnitude2() > f32::EPSILON {
+            let right = right.normalize();
+            let screen_up = right.cross(-direction);
+            let scale = distance * self.pan_speed;
+            let shift = (-right * pan.x + screen_up * pan.y) * scale;
+            camera.target += shift;
+            camera.eye += shift;
+        }
+
+        // Then orbit, in spherical coordinates around `up`
+        let direction = turn(direction, up, rotation.x, rotation.y, self.max_pitch);
+
+        let distance = (distance * (-zoom * self.zoom_speed).exp())
+            .clamp(self.min_distance, self.max_distance);
+        camera.eye = camera.target + direction * distance;
+    }
+}
+
+/// Flies the camera around freely, first-person style.
+///
+/// Raw mouse motion turns the view, WASD or the arrow keys move relative to
+/// where it's looking, and Space/ShiftLeft move straight up or down along
+/// `Camera::up`. Meant to be used with the cursor grabbed, since mouse
+/// motion comes from `DeviceEvent`s rather than the cursor position.
+#[derive(Debug, Clone)]
+pub struct FlyController {
+    /// Distance moved per second while a key is held.
+    pub speed: f32,
+    /// Radians turned per unit of raw mouse motion.
+    pub sensitivity: f32,
+    /// Largest angle between the view direction and the plane
+    /// perpendicular to `Camera::up`.
+    pub max_pitch: Rad<f32>,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    /// Yaw and pitch in radians not yet applied
+    pending_look: Vector2<f32>,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            speed: 4.0,
+            sensitivity: 0.003,
+            max_pitch: Rad(89f32.to_radians()),
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            pending_look: Vector2::new(0.0, 0.0),
+        }
+    }
+}
+
+impl FlyController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles WASD, the arrow keys, Space and ShiftLeft. Returns whether
+    /// the event was used.
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state,
+                        physical_key: PhysicalKey::Code(keycode),
+                        ..
+                    },
+                ..
+            } => self.process_key(*keycode, *state == ElementState::Pressed),
+            _ => false,
+        }
+    }
+
+    /// Presses or releases a movement key. Returns whether `keycode` is one.
+    pub fn process_key(&mut self, keycode: KeyCode, is_pressed: bool) -> bool {
+        match keycode {
+            KeyCode::Space => {
+                self.is_up_pressed = is_pressed;
+                true
+            }
+            KeyCode::ShiftLeft => {
+                self.is_down_pressed = is_pressed;
+                true
+            }
+            KeyCode::KeyW | KeyCode::ArrowUp => {
+                self.is_forward_pressed = is_pressed;
+                true
+            }
+            KeyCode::KeyA | KeyCode::ArrowLeft => {
+                self.is_left_pressed = is_pressed;
+                true
+            }
+            KeyCode::KeyS | KeyCode::ArrowDown => {
+                self.is_backward_pressed = is_pressed;
+                true
+            }
+            KeyCode::KeyD | KeyCode::ArrowRight => {
+                self.is_right_pressed = is_pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Handles raw mouse motion, which keeps coming while the cursor is
+    /// grabbed. Returns whether the event was used.
+    pub fn process_device_events(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
+                self.look(*dx as f32, *dy as f32);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Turns as if the mouse moved by `dx`, `dy`. Moving right turns right
+    /// and moving down looks down.
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.pending_look += Vector2::new(-dx, -dy) * self.sensitivity;
+    }
+
+    /// Forgets held keys and unapplied mouse motion.
+    pub fn reset(&mut self) {
+        *self = Self {
+            speed: self.speed,
+            sensitivity: self.sensitivity,
+            max_pitch: self.max_pitch,
+            ..Self::default()
+        };
+    }
+
+    /// Turns `camera` and moves it for `dt` seconds. The target stays at the
+    /// same distance in front of the eye, so switching back to orbiting
+    /// doesn't jump.
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        let look = self.pending_look;
+        self.pending_look = Vector2::new(0.0, 0.0);
+
+        let up = camera.up.normalize();
+        let offset = camera.target - camera.eye;
+        let distance = offset.magnitude();
+        if distance <= f32::EPSILON {
+            return;
+        }
+        let forward = turn(offset / distance, up, look.x, look.y, self.max_pitch);
+        let right = forward.cross(up).normalize();
+
+        let mut velocity = Vector3::new(0.0, 0.0, 0.0);
+        let axes = [
+            (self.is_forward_pressed, forward),
+            (self.is_backward_pressed, -forward),
+            (self.is_right_pressed, right),
+            (self.is_left_pressed, -right),
+            (self.is_up_pressed, up),
+            (self.is_down_pressed, -up),
+        ];
+        for (is_pressed, axis) in axes {
+            if is_pressed {
+                velocity += axis;
+            }
+        }
+        if velocity.magnitude2() > f32::EPSILON {
+            camera.eye += velocity.normalize() * self.speed * dt;
+        }
+        camera.target = camera.eye + forward * distance;
+    }
+}
+
+/// Which controller drives the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CameraMode {
+    #[default]
+    Orbit,
+    Fly,
+}
+
+/// Switches between an [`OrbitController`] and a [`FlyController`] with a
+/// hotkey, forwarding input to whichever is active.
+///
+/// Both work on the camera's eye and target directly, so switching picks up
+/// where the other left off. Grabbing the cursor is up to the caller, see
+/// [`CameraController::mode_changed`].
+#[derive(Debug, Clone)]
+pub struct CameraController {
+    pub orbit: OrbitController,
+    pub fly: FlyController,
+    /// Switches modes when pressed.
+    pub toggle_key: KeyCode,
+    mode: CameraMode,
+    mode_changed: bool,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            orbit: OrbitController::default(),
+            fly: FlyController::default(),
+            toggle_key: KeyCode::Tab,
+            mode: CameraMode::default(),
+            mode_changed: false,
+        }
+    }
+}
+
+impl CameraController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == self.mode {
+            return;
+        }
+        self.mode = mode;
+        self.mode_changed = true;
+        // Don't let the previous mode keep moving the camera behind our back
+        self.orbit.stop();
+        self.fly.reset();
+    }
+
+    /// Whether the mode changed since the last call, e.g. to grab or
+    /// release the cursor.
+    pub fn mode_changed(&mut self) -> bool {
+        std::mem::take(&mut self.mode_changed)
+    }
+
+    /// Handles the toggle key and forwards everything else to the active
+    /// controller. Returns whether the event was used.
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(keycode),
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            if *keycode == self.toggle_key {
+                self.set_mode(match self.mode {
+                    CameraMode::Orbit => CameraMode::Fly,
+                    CameraMode::Fly => CameraMode::Orbit,
+                });
+                return true;
+            }
+        }
+        match self.mode {
+            CameraMode::Orbit => self.orbit.process_events(event),
+            CameraMode::Fly => self.fly.process_events(event),
+        }
+    }
+
+    /// Forwards raw device input to the active controller. Returns whether
+    /// the event was used.
+    pub fn process_device_events(&mut self, event: &DeviceEvent) -> bool {
+        match self.mode {
+            CameraMode::Orbit => false,
+            CameraMode::Fly => self.fly.process_device_events(event),
+        }
+    }
+
+    /// Updates `camera` with the active controller for `dt` seconds.
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        match self.mode {
+            CameraMode::Orbit => self.orbit.update_camera(camera, dt),
+            CameraMode::Fly => self.fly.update_camera(camera, dt),
+        }
+    }
+}
+
+/// Turns `direction` by `yaw` around `up` and tilts it by `pitch` towards
+/// `up`, keeping it within `max_pitch` of the plane perpendicular to `up`.
+fn turn(
+    direction: Vector3<f32>,
+    up: Vector3<f32>,
+    yaw: f32,
+    pitch: f32,
+    max_pitch: Rad<f32>,
+) -> Vector3<f32> {
+    let current_pitch = direction.dot(up).clamp(-1.0, 1.0).asin();
+    let horizontal = direction - up * direction.dot(up);
+    let horizontal = if horizontal.magnitude2() > f32::EPSILON {
+        horizontal.normalize()
+    } else {
+        // Looking straight along `up`: any horizontal direction will do
+        any_perpendicular(up)
+    };
+    let (sin_yaw, cos_yaw) = yaw.sin_cos();
+    let horizontal = horizontal * cos_yaw + up.cross(horizontal) * sin_yaw;
+    let max_pitch = max_pitch.0.min(89.9f32.to_radians());
+    let pitch = (current_pitch + pitch).clamp(-max_pitch, max_pitch);
+    horizontal * pitch.cos() + up * pitch.sin()
+}
+
+fn any_perpendicular(v: Vector3<f32>) -> Vector3<f32> {
+    let other = if v.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    v.cross(other).normalize()
+}
@@ -0,0 +1,57 @@
+// Renders one face of a cube map from an equirectangular panorama
+
+struct Face {
+    // Which face, in wgpu's order: +X, -X, +Y, -Y, +Z, -Z
+    index: u32,
+    // Mip level of the panorama matching the face's texel size
+    lod: f32,
+    _padding0: u32,
+    _padding1: u32,
+}
+@group(0) @binding(0)
+var<uniform> face: Face;
+@group(0) @binding(1)
+var t_panorama: texture_2d<f32>;
+@group(0) @binding(2)
+var s_panorama: sampler;
+
+const PI: f32 = 3.14159265359;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1 to 1 across the face, from the top left
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the whole face
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = vec2<f32>(ndc.x, -ndc.y);
+    return out;
+}
+
+// Direction a texel of a cube map face is sampled with
+fn face_direction(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    switch index {
+        case 0u: { return vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { return vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { return vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { return vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { return vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { return vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let cube = normalize(face_direction(face.index, in.uv));
+    // Cube maps are left-handed, see skybox.wgsl
+    let world = vec3<f32>(cube.xy, -cube.z);
+    // Straight ahead (-Z) is the middle of the panorama, with +X to its right
+    let u = 0.5 + atan2(world.x, -world.z) / (2.0 * PI);
+    let v = acos(clamp(world.y, -1.0, 1.0)) / PI;
+    return textureSampleLevel(t_panorama, s_panorama, vec2<f32>(u, v), face.lod);
+}
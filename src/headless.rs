@@ -0,0 +1,180 @@
+use std::{iter, path::Path, sync::mpsc};
+
+use anyhow::*;
+
+use crate::{post, renderer::OPTIONAL_FEATURES, supported_sample_counts, Renderer};
+
+/// Renders the scene into an offscreen texture instead of a window surface.
+/// Frames are copied back to the CPU, so this runs on CI machines without a
+/// display and, through the fallback adapter, without a GPU.
+pub struct Headless {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub width: u32,
+    pub height: u32,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    output_buffer: wgpu::Buffer,
+    /// Bytes per row in `output_buffer`, padded to `COPY_BYTES_PER_ROW_ALIGNMENT`
+    padded_bytes_per_row: u32,
+    sample_counts: Vec<u32>,
+    pub renderer: Renderer,
+}
+
+impl Headless {
+    /// Color format of the offscreen target, matching the sRGB surface `State` prefers.
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    /// Creates a headless renderer of the given size. A software/fallback
+    /// adapter is preferred so the output doesn't depend on the host GPU;
+    /// if none is available any adapter is used instead.
+    pub async fn new(width: u32, height: u32) -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all()),
+            ..Default::default()
+        });
+
+        let mut adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: true,
+            })
+            .await;
+        if adapter.is_none() {
+            log::warn!("No fallback adapter found, using the default adapter");
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions::default())
+                .await;
+        }
+        let adapter = adapter.context("No suitable adapter for headless rendering")?;
+        log::info!("Headless adapter: {:?}", adapter.get_info());
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Headless Device"),
+                    required_features: adapter.features() & OPTIONAL_FEATURES,
+                    // Software adapters rarely reach `Limits::default()`, so
+                    // ask for whatever this one actually supports.
+                    required_limits: adapter.limits(),
+                    memory_hints: Default::default(),
+                },
+                None,
+            )
+            .await?;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Headless Output Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let renderer = Renderer::new(&device, &queue, Self::FORMAT, width, height);
+        let sample_counts = supported_sample_counts(
+            &adapter,
+            &device,
+            &[post::HDR_FORMAT, renderer.depth_settings().format],
+        );
+
+        Ok(Self {
+            device,
+            queue,
+            width,
+            height,
+            texture,
+            view,
+            output_buffer,
+            padded_bytes_per_row,
+            sample_counts,
+            renderer,
+        })
+    }
+
+    /// MSAA sample counts the target supports, for
+    /// [`Renderer::set_sample_count`].
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
+    /// Renders one frame and reads it back as an RGBA image.
+    pub fn render(&mut self) -> Result<image::RgbaImage> {
+        self.renderer.update(&self.device, &self.queue);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Headless Encoder"),
+            });
+
+        self.renderer.render(&mut encoder, &self.view);
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.output_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            self.texture.size(),
+        );
+
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = self.output_buffer.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let unpadded_bytes_per_row = (self.width * 4) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.output_buffer.unmap();
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .context("Readback buffer has the wrong size")
+    }
+
+    /// Renders one frame and writes it to `path` as a PNG.
+    pub fn save_png(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let frame = self.render()?;
+        frame.save_with_format(path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+}
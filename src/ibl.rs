@@ -0,0 +1,744 @@
+use std::{
+    iter,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::texture::{self, Texture};
+
+/// Resolution and sample counts of the precomputed lighting. More samples
+/// mean less noise at the cost of a slower first start; cached results load
+/// instantly whatever the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IblSettings {
+    /// Width of the irradiance cube's faces. Diffuse lighting has no detail,
+    /// so this can be tiny.
+    pub irradiance_size: u32,
+    pub irradiance_samples: u32,
+    /// Width of the sharpest level of the specular cube's faces.
+    pub specular_size: u32,
+    /// Mip levels of the specular cube, from roughness 0 to 1. Limited by
+    /// `specular_size`.
+    pub specular_levels: u32,
+    pub specular_samples: u32,
+    /// Width and height of the BRDF lookup table.
+    pub brdf_lut_size: u32,
+    pub brdf_samples: u32,
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        Self {
+            irradiance_size: 32,
+            irradiance_samples: 512,
+            specular_size: 128,
+            specular_levels: 6,
+            specular_samples: 512,
+            brdf_lut_size: 128,
+            brdf_samples: 512,
+        }
+    }
+}
+
+impl IblSettings {
+    fn specular_levels(&self) -> u32 {
+        self.specular_levels.clamp(
+            1,
+            texture::mip_level_count(self.specular_size, self.specular_size),
+        )
+    }
+
+    fn to_words(self) -> [u32; 7] {
+        [
+            self.irradiance_size,
+            self.irradiance_samples,
+            self.specular_size,
+            self.specular_levels,
+            self.specular_samples,
+            self.brdf_lut_size,
+            self.brdf_samples,
+        ]
+    }
+}
+
+/// Lighting from an environment cube map, for the ambient light of PBR
+/// materials: how much of it reaches a surface diffusely, how it reflects at
+/// each roughness, and the BRDF lookup table of the split-sum approximation.
+/// See [`crate::Renderer::set_environment`].
+pub struct Environment {
+    /// Cosine-weighted average of the environment around each direction
+    pub irradiance: Texture,
+    /// The environment blurred for roughness 0 at mip 0 up to 1 at the last
+    /// level
+    pub specular: Texture,
+    /// Scale (red) and bias (green) of F0, by N·V across and roughness down
+    pub brdf_lut: Texture,
+    settings: IblSettings,
+}
+
+impl Environment {
+    /// Format of the cube maps, with room for HDR.
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+    /// Start of cache files, followed by the version of the shader and the
+    /// settings they were made with.
+    const MAGIC: &'static [u8; 8] = b"RGLIBL01";
+
+    pub fn settings(&self) -> IblSettings {
+        self.settings
+    }
+
+    /// Highest mip level of `specular`, used for roughness 1.
+    pub fn specular_max_lod(&self) -> f32 {
+        (self.specular.texture.mip_level_count() - 1) as f32
+    }
+
+    /// Precomputes everything from `cube` on the GPU, with render passes so
+    /// it works on WebGL2 as well. Give `cube` mipmaps: blurrier results read
+    /// smaller mip levels, which keeps noise down with few samples.
+    pub fn compute(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cube: &Texture,
+        settings: IblSettings,
+    ) -> Self {
+        let environment = Self::create(device, settings);
+        let passes = Passes::new(device);
+        let irradiance_pipeline = passes.pipeline(device, "fs_irradiance", Self::FORMAT);
+        let specular_pipeline = passes.pipeline(device, "fs_specular", Self::FORMAT);
+        let brdf_pipeline = passes.pipeline(device, "fs_brdf", Self::LUT_FORMAT);
+
+        // Every pass to render, with its target view and uniform
+        let source_size = cube.texture.width() as f32;
+        let uniform = PassUniform {
+            source_size,
+            ..Default::default()
+        };
+        let mut targets = Vec::new();
+        for face in 0..6 {
+            targets.push((
+                environment.irradiance.face_view(face, 0),
+                PassUniform {
+                    face,
+                    sample_count: settings.irradiance_samples.max(1),
+                    roughness: 1.0,
+                    target_size: settings.irradiance_size as f32,
+                    ..uniform
+                },
+                &irradiance_pipeline,
+            ));
+        }
+        let levels = settings.specular_levels();
+        for level in 0..levels {
+            for face in 0..6 {
+                targets.push((
+                    environment.specular.face_view(face, level),
+                    PassUniform {
+                        face,
+                        sample_count: settings.specular_samples.max(1),
+                        roughness: level as f32 / (levels - 1).max(1) as f32,
+                        target_size: (settings.specular_size >> level).max(1) as f32,
+                        ..uniform
+                    },
+                    &specular_pipeline,
+                ));
+            }
+        }
+        targets.push((
+            environment
+                .brdf_lut
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+            PassUniform {
+                sample_count: settings.brdf_samples.max(1),
+                target_size: settings.brdf_lut_size as f32,
+                ..uniform
+            },
+            &brdf_pipeline,
+        ));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Source Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniforms: Vec<_> = targets.iter().map(|(_, uniform, _)| *uniform).collect();
+        let (bind_group, stride) = passes.bind_group(device, &uniforms, cube, &sampler);
+
+        // Each pass goes in its own submission, so slow GPUs don't stall on
+        // one huge command buffer
+        for (i, (view, _, pipeline)) in targets.iter().enumerate() {
+            passes.render(
+                device,
+                queue,
+                view,
+                pipeline,
+                &bind_group,
+                stride * i as u32,
+            );
+        }
+
+        environment
+    }
+
+    /// Loads the results of an earlier [`Environment::save`] made with the
+    /// same settings, or computes them from `cube` and saves them to `path`.
+    /// Failing to save is only logged: the environment works all the same.
+    ///
+    /// Nothing checks that the cache was made from `cube`, so give every
+    /// environment its own path, e.g. named after a hash of its images.
+    pub fn load_or_compute(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cube: &Texture,
+        settings: IblSettings,
+        path: impl AsRef<Path>,
+    ) -> Self {
+        let path = path.as_ref();
+        if path.exists() {
+            match Self::load(device, queue, path, settings) {
+                Result::Ok(environment) => return environment,
+                Err(e) => log::warn!("Recomputing image-based lighting: {e:#}"),
+            }
+        }
+        let environment = Self::compute(device, queue, cube, settings);
+        if let Err(e) = environment.save(device, queue, path) {
+            log::warn!("Couldn't cache image-based lighting: {e:#}");
+        }
+        environment
+    }
+
+    /// Writes every texel to `path`, to be read back with
+    /// [`Environment::load`]. Waits for the GPU.
+    pub fn save(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let mut bytes = Self::MAGIC.to_vec();
+        bytes.extend_from_slice(&Passes::SHADER_VERSION.to_le_bytes());
+        for word in self.settings.to_words() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        for texture in self.textures() {
+            bytes.extend(read_texture(device, queue, texture)?);
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Reads a file written by [`Environment::save`], which must have been
+    /// made with `settings`.
+    pub fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+        settings: IblSettings,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to load {}", path.display()))?;
+        let magic = Self::MAGIC.len();
+        let header = magic + 4 + 4 * settings.to_words().len();
+        ensure!(
+            bytes.len() >= header && bytes.starts_with(Self::MAGIC),
+            "{} isn't an image-based lighting cache",
+            path.display()
+        );
+        let words: Vec<u32> = bytes[magic..header]
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        ensure!(
+            words[0] == Passes::SHADER_VERSION,
+            "{} was made by another version of the shader",
+            path.display()
+        );
+        ensure!(
+            words[1..] == settings.to_words(),
+            "{} was made with other settings",
+            path.display()
+        );
+
+        let environment = Self::create(device, settings);
+        let mut data = &bytes[header..];
+        for texture in environment.textures() {
+            data = write_texture(queue, &texture.texture, data)
+                .with_context(|| format!("{} is truncated", path.display()))?;
+        }
+        ensure!(data.is_empty(), "{} is too long", path.display());
+        Ok(environment)
+    }
+
+    /// Black 1x1 maps, to bind while there's no environment.
+    pub(crate) fn placeholder(device: &wgpu::Device) -> Self {
+        Self::create(
+            device,
+            IblSettings {
+                irradiance_size: 1,
+                specular_size: 1,
+                specular_levels: 1,
+                brdf_lut_size: 1,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Textures of the right size for `settings`, still empty.
+    fn create(device: &wgpu::Device, settings: IblSettings) -> Self {
+        let irradiance = Texture::create_cube(
+            device,
+            settings.irradiance_size.max(1),
+            Self::FORMAT,
+            1,
+            "Irradiance Cube",
+        );
+        let specular = Texture::create_cube(
+            device,
+            settings.specular_size.max(1),
+            Self::FORMAT,
+            settings.specular_levels(),
+            "Specular Cube",
+        );
+        let size = settings.brdf_lut_size.max(1);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::LUT_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("BRDF LUT Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            irradiance,
+            specular,
+            brdf_lut: Texture {
+                texture,
+                view,
+                sampler,
+            },
+            settings,
+        }
+    }
+
+    fn textures(&self) -> [&Texture; 3] {
+        [&self.irradiance, &self.specular, &self.brdf_lut]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PassUniform {
+    face: u32,
+    sample_count: u32,
+    roughness: f32,
+    source_size: f32,
+    target_size: f32,
+    lod: f32,
+    _padding: [u32; 2],
+}
+
+/// The shader and layouts shared by every pass that reads a cube map.
+struct Passes {
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+}
+
+impl Passes {
+    /// Bump whenever `ibl.wgsl` changes what it computes, so cached results
+    /// from the old shader are recomputed.
+    const SHADER_VERSION: u32 = 1;
+
+    fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<PassUniform>() as _
+                        ),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("ibl_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("IBL Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ibl.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        Self {
+            bind_group_layout,
+            layout,
+            shader,
+        }
+    }
+
+    fn pipeline(
+        &self,
+        device: &wgpu::Device,
+        entry_point: &str,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&self.layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point,
+                targets: &[Some(format.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Binds `cube` with every uniform in one buffer, and returns the
+    /// dynamic offset between them.
+    fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        uniforms: &[PassUniform],
+        cube: &Texture,
+        sampler: &wgpu::Sampler,
+    ) -> (wgpu::BindGroup, u32) {
+        let stride = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let mut contents = vec![0; stride * uniforms.len()];
+        for (i, uniform) in uniforms.iter().enumerate() {
+            contents[stride * i..stride * i + std::mem::size_of::<PassUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(uniform));
+        }
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("IBL Pass Buffer"),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<PassUniform>() as _),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cube.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("ibl_bind_group"),
+        });
+        (bind_group, stride as u32)
+    }
+
+    /// Draws one fullscreen triangle into `view`, in a submission of its own.
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        offset: u32,
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("IBL Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[offset]);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(iter::once(encoder.finish()));
+    }
+}
+
+/// Each mip level and layer of `texture`, one after the other, tightly packed.
+fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &Texture) -> Result<Vec<u8>> {
+    let layers = texture.texture.depth_or_array_layers();
+    if layers == 1 {
+        return read_2d(device, queue, &texture.texture, 0);
+    }
+    let reader = FaceReader::new(device, texture.texture.format());
+    let mut bytes = Vec::new();
+    for mip_level in 0..texture.texture.mip_level_count() {
+        for face in 0..layers {
+            bytes.extend(reader.read(device, queue, texture, face, mip_level)?);
+        }
+    }
+    Ok(bytes)
+}
+
+/// The texels of one face and mip level of `cube`, tightly packed. Waits for
+/// the GPU.
+pub fn read_cube_face(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    cube: &Texture,
+    face: u32,
+    mip_level: u32,
+) -> Result<Vec<u8>> {
+    FaceReader::new(device, cube.texture.format()).read(device, queue, cube, face, mip_level)
+}
+
+/// Reads faces of cube maps of one format back to the CPU. Each face is drawn
+/// into a plain 2D texture first, since the GL backend can't copy out of cube
+/// maps.
+struct FaceReader {
+    passes: Passes,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl FaceReader {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let passes = Passes::new(device);
+        let pipeline = passes.pipeline(device, "fs_read", format);
+        // Nearest sampling at texel centers copies every texel exactly
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Readback Sampler"),
+            ..Default::default()
+        });
+        Self {
+            passes,
+            pipeline,
+            sampler,
+        }
+    }
+
+    fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cube: &Texture,
+        face: u32,
+        mip_level: u32,
+    ) -> Result<Vec<u8>> {
+        let size = (cube.texture.width() >> mip_level).max(1);
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("IBL Readback Texture"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: cube.texture.format(),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let uniform = PassUniform {
+            face,
+            lod: mip_level as f32,
+            ..Default::default()
+        };
+        let (bind_group, _) = self
+            .passes
+            .bind_group(device, &[uniform], cube, &self.sampler);
+        self.passes
+            .render(device, queue, &view, &self.pipeline, &bind_group, 0);
+        read_2d(device, queue, &target, 0)
+    }
+}
+
+/// The texels of one mip level of a 2D texture, tightly packed.
+fn read_2d(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+) -> Result<Vec<u8>> {
+    let size = texture
+        .size()
+        .mip_level_size(mip_level, texture.dimension());
+    let row = size.width * texture.format().block_copy_size(None).unwrap();
+    let padded_row = row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("IBL Readback Buffer"),
+        size: (padded_row * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("IBL Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit(iter::once(encoder.finish()));
+
+    let mapped = Arc::new(Mutex::new(None));
+    let result = mapped.clone();
+    buffer.slice(..).map_async(wgpu::MapMode::Read, move |r| {
+        *result.lock().unwrap() = Some(r);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    mapped
+        .lock()
+        .unwrap()
+        .take()
+        .context("Readback never finished")??;
+    let data = buffer.slice(..).get_mapped_range();
+    Ok(data
+        .chunks(padded_row as usize)
+        .flat_map(|chunk| &chunk[..row as usize])
+        .copied()
+        .collect())
+}
+
+/// Fills `texture` from the start of `data` as laid out by `read_texture`,
+/// and returns the rest.
+fn write_texture<'a>(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mut data: &'a [u8],
+) -> Result<&'a [u8]> {
+    let block_size = texture.format().block_copy_size(None).unwrap();
+    for mip_level in 0..texture.mip_level_count() {
+        let size = texture
+            .size()
+            .mip_level_size(mip_level, texture.dimension());
+        let len = (size.width * size.height * size.depth_or_array_layers * block_size) as usize;
+        ensure!(data.len() >= len, "Expected {len} more bytes");
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &data[..len],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width * block_size),
+                rows_per_image: Some(size.height),
+            },
+            size,
+        );
+        data = &data[len..];
+    }
+    Ok(data)
+}
+
+/// A stable 64-bit FNV-1a hash, for naming cache files after their sources.
+/// Each source is preceded by its length, so moving bytes from one source to
+/// the next changes the key.
+pub fn cache_key(sources: &[&[u8]], settings: IblSettings) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut write = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+    write(&Passes::SHADER_VERSION.to_le_bytes());
+    for source in sources {
+        write(&(source.len() as u64).to_le_bytes());
+        write(source);
+    }
+    for word in settings.to_words() {
+        write(&word.to_le_bytes());
+    }
+    hash
+}
@@ -0,0 +1,198 @@
+// Precomputes image-based lighting from an environment cube map: diffuse
+// irradiance, GGX prefiltered specular and the split-sum BRDF lookup table.
+// Everything here works in the cube map's own, left-handed space; the main
+// shader flips z when it samples the results.
+
+struct Pass {
+    // Cube face being rendered, in wgpu's order: +X, -X, +Y, -Y, +Z, -Z
+    face: u32,
+    sample_count: u32,
+    // Perceptual roughness of this specular level
+    roughness: f32,
+    // Width of the environment's faces at mip 0
+    source_size: f32,
+    // Width of the target at the mip level being rendered
+    target_size: f32,
+    // Mip level to read back
+    lod: f32,
+    _padding0: u32,
+    _padding1: u32,
+}
+@group(0) @binding(0)
+var<uniform> pass_info: Pass;
+@group(0) @binding(1)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(2)
+var s_environment: sampler;
+
+const PI: f32 = 3.14159265359;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1 to 1 across the target, from the top left
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = vec2<f32>(ndc.x, -ndc.y);
+    return out;
+}
+
+// Direction a texel of a cube map face is sampled with
+fn face_direction(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    switch index {
+        case 0u: { return vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { return vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { return vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { return vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { return vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { return vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+}
+
+// Van der Corput sequence, with the bits reversed by hand since WebGL2 has
+// no bitfieldReverse
+fn radical_inverse(index: u32) -> f32 {
+    var bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+// Evenly spread points in the unit square
+fn hammersley(index: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(index) / f32(count), radical_inverse(index));
+}
+
+// Turns a direction around +Z into one around `n`
+fn to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if abs(n.z) > 0.999 {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return tangent * v.x + bitangent * v.y + n * v.z;
+}
+
+// Half vector distributed like GGX normals, around `n`
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, alpha: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// The same height-correlated Smith term as the main shader
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(ggx_v + ggx_l, 1e-5);
+}
+
+// Mip level of the environment whose texels cover about the solid angle of
+// a sample with probability density `pdf`, which keeps few samples from
+// turning into noise
+fn source_lod(pdf: f32) -> f32 {
+    let sample_angle = 1.0 / (f32(pass_info.sample_count) * pdf + 1e-4);
+    let texel_angle = 4.0 * PI / (6.0 * pass_info.source_size * pass_info.source_size);
+    return max(0.5 * log2(sample_angle / texel_angle) + 1.0, 0.0);
+}
+
+// Cosine-weighted average of the environment over the hemisphere around
+// each direction. Multiplied by the albedo this is the diffuse reflection.
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = normalize(face_direction(pass_info.face, in.uv));
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < pass_info.sample_count; i++) {
+        let xi = hammersley(i, pass_info.sample_count);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let l = to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+        let lod = source_lod(cos_theta / PI);
+        sum += textureSampleLevel(t_environment, s_environment, l, lod).rgb;
+    }
+    return vec4<f32>(sum / f32(pass_info.sample_count), 1.0);
+}
+
+// The environment blurred by a GGX lobe of this level's roughness, assuming
+// the view direction along the normal as in the split-sum approximation
+@fragment
+fn fs_specular(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = normalize(face_direction(pass_info.face, in.uv));
+    if pass_info.roughness <= 0.0 {
+        let lod = max(log2(pass_info.source_size / pass_info.target_size), 0.0);
+        return vec4<f32>(textureSampleLevel(t_environment, s_environment, n, lod).rgb, 1.0);
+    }
+    let alpha = pass_info.roughness * pass_info.roughness;
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < pass_info.sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, pass_info.sample_count), n, alpha);
+        let n_dot_h = max(dot(n, h), 0.0);
+        let l = 2.0 * n_dot_h * h - n;
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            // With the view along the normal, pdf = D * N·H / (4 V·H) = D / 4
+            let lod = source_lod(distribution_ggx(n_dot_h, alpha) / 4.0);
+            sum += textureSampleLevel(t_environment, s_environment, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 1e-4), 1.0);
+}
+
+// Scale and bias applied to F0 by the specular BRDF integrated over the
+// hemisphere, by N·V across and roughness down
+@fragment
+fn fs_brdf(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = in.uv * 0.5 + 0.5;
+    let n_dot_v = max(coords.x, 1e-3);
+    let alpha = coords.y * coords.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < pass_info.sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, pass_info.sample_count), n, alpha);
+        let v_dot_h = max(dot(v, h), 0.0);
+        let l = 2.0 * v_dot_h * h - v;
+        let n_dot_l = l.z;
+        let n_dot_h = max(h.z, 0.0);
+        if n_dot_l > 0.0 {
+            // Visibility times the inverse pdf, 4 V·H / (D N·H), and N·L
+            let visibility = visibility_smith_ggx(n_dot_v, n_dot_l, alpha)
+                * 4.0 * n_dot_l * v_dot_h / max(n_dot_h, 1e-4);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    let count = f32(pass_info.sample_count);
+    return vec4<f32>(scale / count, bias / count, 0.0, 1.0);
+}
+
+// One face and mip level of the environment as it is, to read back on
+// backends that can't copy out of cube maps
+@fragment
+fn fs_read(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(pass_info.face, in.uv);
+    return textureSampleLevel(t_environment, s_environment, direction, pass_info.lod);
+}
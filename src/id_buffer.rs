@@ -0,0 +1,448 @@
+use std::{
+    iter,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    instance::InstanceRaw,
+    model::{DrawModel, Model},
+    picking::{GpuPick, PickId, PickRect, PickRequest},
+    renderer::DepthSettings,
+    texture, Vertex,
+};
+
+/// Outcome of `map_async`, filled in by its callback.
+type MapResult = Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>;
+
+/// A copy of part of the ID target on its way back to the CPU.
+struct Readback {
+    request: PickRequest,
+    rect: PickRect,
+    buffer: wgpu::Buffer,
+    /// Bytes per row in `buffer`, padded to `COPY_BYTES_PER_ROW_ALIGNMENT`
+    padded_bytes_per_row: u32,
+    /// ID of each model's first instance when the IDs were rendered, followed
+    /// by one past the last ID
+    first_ids: Vec<u32>,
+    mapped: MapResult,
+}
+
+impl Readback {
+    fn resolve(&self) -> GpuPick {
+        let mut pixels = Vec::with_capacity((self.rect.width * self.rect.height) as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                let row: &[u32] = bytemuck::cast_slice(row);
+                pixels.extend(
+                    row[..self.rect.width as usize]
+                        .iter()
+                        .map(|&id| resolve_id(&self.first_ids, id)),
+                );
+            }
+        }
+        self.buffer.unmap();
+        GpuPick {
+            request: self.request,
+            rect: self.rect,
+            pixels,
+        }
+    }
+}
+
+/// The instance an ID was rendered for, given each model's first ID.
+fn resolve_id(first_ids: &[u32], id: u32) -> Option<PickId> {
+    let model = first_ids
+        .partition_point(|&first| first <= id)
+        .checked_sub(1)?;
+    (model + 1 < first_ids.len()).then(|| PickId {
+        model,
+        instance: (id - first_ids[model]) as usize,
+    })
+}
+
+/// Renders model instance IDs into an `R32Uint` target on request, and reads
+/// back the pixels asked for without waiting for the GPU.
+pub(crate) struct IdBuffer {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    depth_texture: texture::Texture,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    model_layout: wgpu::BindGroupLayout,
+    /// First instance ID of each model, one model per `model_stride` bytes
+    model_buffer: wgpu::Buffer,
+    model_bind_group: wgpu::BindGroup,
+    model_stride: u32,
+    /// Models `model_buffer` has room for
+    model_capacity: usize,
+    next_request: u64,
+    /// Rectangles to read back after the next ID pass
+    requests: Vec<(PickRequest, PickRect)>,
+    in_flight: Vec<Readback>,
+    /// Picks that didn't need the GPU, like those outside the view
+    ready: Vec<GpuPick>,
+}
+
+impl IdBuffer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        depth: &DepthSettings,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let model_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    // One bind group covers every model
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(16),
+                },
+                count: None,
+            }],
+            label: Some("pick_model_bind_group_layout"),
+        });
+        let model_stride = device.limits().min_uniform_buffer_offset_alignment;
+        let model_capacity = 16;
+        let (model_buffer, model_bind_group) =
+            create_model_buffer(device, &model_layout, model_stride, model_capacity);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Picking Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("picking.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Picking Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &model_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, &shader, depth);
+        let (texture, view, depth_texture) = create_targets(device, depth, width, height);
+
+        Self {
+            texture,
+            view,
+            depth_texture,
+            shader,
+            pipeline_layout,
+            pipeline,
+            model_layout,
+            model_buffer,
+            model_bind_group,
+            model_stride,
+            model_capacity,
+            next_request: 0,
+            requests: Vec::new(),
+            in_flight: Vec::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    /// Follows a change of the main pass's depth settings, so the IDs hide
+    /// each other the same way the models do on screen.
+    pub fn set_depth_settings(&mut self, device: &wgpu::Device, depth: &DepthSettings) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, depth);
+        let size = self.texture.size();
+        (self.texture, self.view, self.depth_texture) =
+            create_targets(device, depth, size.width, size.height);
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth: &DepthSettings,
+        width: u32,
+        height: u32,
+    ) {
+        (self.texture, self.view, self.depth_texture) =
+            create_targets(device, depth, width, height);
+    }
+
+    /// Queues `rect` to be read back after the next ID pass.
+    pub fn request(&mut self, rect: PickRect) -> PickRequest {
+        let request = PickRequest(self.next_request);
+        self.next_request += 1;
+        self.requests.push((request, rect));
+        request
+    }
+
+    /// Renders the IDs and starts reading back the requested rectangles.
+    /// Does nothing if no picks were requested since the last call.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        models: &[Model],
+        camera_bind_group: &wgpu::BindGroup,
+        depth: &DepthSettings,
+    ) {
+        if self.requests.is_empty() {
+            return;
+        }
+
+        if models.len() > self.model_capacity {
+            self.model_capacity = models.len().next_power_of_two();
+            (self.model_buffer, self.model_bind_group) = create_model_buffer(
+                device,
+                &self.model_layout,
+                self.model_stride,
+                self.model_capacity,
+            );
+        }
+        let mut first_ids = Vec::with_capacity(models.len() + 1);
+        let mut next_id = 1u32;
+        let mut contents = vec![0u8; self.model_stride as usize * models.len()];
+        for (model, slot) in models
+            .iter()
+            .zip(contents.chunks_mut(self.model_stride as usize))
+        {
+            first_ids.push(next_id);
+            slot[..4].copy_from_slice(&next_id.to_ne_bytes());
+            next_id += model.instances.len() as u32;
+        }
+        first_ids.push(next_id);
+        if !contents.is_empty() {
+            queue.write_buffer(&self.model_buffer, 0, &contents);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Picking Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Picking Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(depth.clear_value),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, camera_bind_group, &[]);
+            for (i, model) in models.iter().enumerate() {
+                let offset = i as u32 * self.model_stride;
+                pass.set_bind_group(1, &self.model_bind_group, &[offset]);
+                pass.draw_model_depth(model);
+            }
+        }
+
+        let size = self.texture.size();
+        let mut readbacks = Vec::new();
+        for (request, rect) in self.requests.drain(..) {
+            let rect = rect.clamp(size.width, size.height);
+            if rect.is_empty() {
+                self.ready.push(GpuPick {
+                    request,
+                    rect,
+                    pixels: Vec::new(),
+                });
+                continue;
+            }
+            let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+            let padded_bytes_per_row = (rect.width * 4).div_ceil(align) * align;
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Picking Readback Buffer"),
+                size: (padded_bytes_per_row * rect.height) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: rect.x,
+                        y: rect.y,
+                        z: 0,
+                    },
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_bytes_per_row),
+                        rows_per_image: Some(rect.height),
+                    },
+                },
+                wgpu::Extent3d {
+                    width: rect.width,
+                    height: rect.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+            readbacks.push(Readback {
+                request,
+                rect,
+                buffer,
+                padded_bytes_per_row,
+                first_ids: first_ids.clone(),
+                mapped: Arc::default(),
+            });
+        }
+
+        queue.submit(iter::once(encoder.finish()));
+
+        // Buffers can only be mapped once the copies into them are submitted
+        for readback in readbacks {
+            let mapped = readback.mapped.clone();
+            readback
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    *mapped.lock().unwrap() = Some(result);
+                });
+            self.in_flight.push(readback);
+        }
+    }
+
+    /// Picks whose pixels have arrived, in the order they were requested.
+    /// Never blocks; the rest stay queued for a later call.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Vec<GpuPick> {
+        if !self.in_flight.is_empty() {
+            device.poll(wgpu::Maintain::Poll);
+        }
+        let mut done = std::mem::take(&mut self.ready);
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            let result = self.in_flight[i].mapped.lock().unwrap().take();
+            match result {
+                None => i += 1,
+                Some(result) => {
+                    let readback = self.in_flight.remove(i);
+                    match result {
+                        Ok(()) => done.push(readback.resolve()),
+                        Err(e) => log::warn!("Couldn't read back pick {:?}: {e}", readback.request),
+                    }
+                }
+            }
+        }
+        done.sort_by_key(|pick| pick.request.0);
+        done
+    }
+}
+
+fn create_model_buffer(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    stride: u32,
+    capacity: usize,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Pick Model Buffer"),
+        size: stride as wgpu::BufferAddress * capacity as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(16),
+            }),
+        }],
+        label: Some("pick_model_bind_group"),
+    });
+    (buffer, bind_group)
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    depth: &DepthSettings,
+    width: u32,
+    height: u32,
+) -> (wgpu::Texture, wgpu::TextureView, texture::Texture) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Picking Target"),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: IdBuffer::FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_texture = texture::Texture::create_depth_texture(
+        device,
+        width,
+        height,
+        depth.format,
+        1,
+        "picking_depth",
+    );
+    (texture, view, depth_texture)
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    depth: &DepthSettings,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Picking Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: IdBuffer::FORMAT,
+                // Integer targets can't blend
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        // Culled like the main pass, so only what's on screen can be picked
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth.format,
+            depth_write_enabled: true,
+            depth_compare: depth.compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
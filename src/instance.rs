@@ -0,0 +1,279 @@
+use std::ops::Range;
+
+use cgmath::{InnerSpace, Matrix3, Matrix4, One, Quaternion, Rad, Rotation3, Vector3, Zero};
+use wgpu::util::DeviceExt;
+
+/// Placement and tint of one copy of a model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    /// Multiplied with the material color. White leaves it unchanged.
+    pub color: [f32; 4],
+    /// Units per second the instance moves by in [`Instance::step`].
+    pub velocity: Vector3<f32>,
+    /// Axis the instance turns about in [`Instance::step`], scaled by its
+    /// speed in radians per second.
+    pub angular_velocity: Vector3<f32>,
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            color: [1.0; 4],
+            velocity: Vector3::zero(),
+            angular_velocity: Vector3::zero(),
+        }
+    }
+}
+
+impl Instance {
+    pub fn is_moving(&self) -> bool {
+        !self.velocity.is_zero() || !self.angular_velocity.is_zero()
+    }
+
+    /// Moves and turns the instance by `dt` seconds of its velocities.
+    pub fn step(&mut self, dt: f32) {
+        self.position += self.velocity * dt;
+        let speed = self.angular_velocity.magnitude();
+        if speed > 0.0 {
+            let turn = Quaternion::from_axis_angle(self.angular_velocity / speed, Rad(speed * dt));
+            self.rotation = (turn * self.rotation).normalize();
+        }
+    }
+
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    pub fn to_raw(&self) -> InstanceRaw {
+        // Inverse transpose of rotation * scale, so normals stay perpendicular
+        // to surfaces under non-uniform scaling
+        let s = self.scale;
+        #[rustfmt::skip]
+        let inverse_scale = Matrix3::new(
+            1.0 / s.x, 0.0, 0.0,
+            0.0, 1.0 / s.y, 0.0,
+            0.0, 0.0, 1.0 / s.z,
+        );
+        let normal = Matrix3::from(self.rotation) * inverse_scale;
+        InstanceRaw {
+            model: self.model_matrix().into(),
+            normal: normal.into(),
+            color: self.color,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+    color: [f32; 4],
+}
+
+impl InstanceRaw {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            // We need to switch from using a step mode of Vertex to Instance
+            // This means that our shaders will only change to use the next
+            // instance when the shader starts processing a new instance
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
+                // for each vec4. We'll have to reassemble the mat4 in the shader.
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    // Locations below 5 are left for per-vertex attributes
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// CPU copy of a model's instances mirrored into a GPU vertex buffer.
+///
+/// Edits only mark the touched range dirty; `sync` uploads just that range.
+/// The GPU buffer is reallocated only when the instances outgrow it, and then
+/// grows to the next power of two so repeated `push`es stay cheap.
+pub struct InstanceBuffer {
+    instances: Vec<Instance>,
+    buffer: wgpu::Buffer,
+    /// Number of instances `buffer` has room for
+    capacity: usize,
+    dirty: Option<Range<usize>>,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, instances: Vec<Instance>) -> Self {
+        let data: Vec<InstanceRaw> = instances.iter().map(Instance::to_raw).collect();
+        let capacity = instances.len().max(1);
+        let buffer = if data.is_empty() {
+            create_buffer(device, capacity)
+        } else {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Instance Buffer"),
+                contents: bytemuck::cast_slice(&data),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            })
+        };
+
+        Self {
+            instances,
+            buffer,
+            capacity,
+            dirty: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Instance> {
+        self.instances.get(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instance> {
+        self.instances.iter()
+    }
+
+    /// Adds an instance and returns its index.
+    pub fn push(&mut self, instance: Instance) -> usize {
+        let index = self.instances.len();
+        self.instances.push(instance);
+        self.mark_dirty(index);
+        index
+    }
+
+    /// Removes an instance. The last instance is moved into its slot, so only
+    /// that one needs to be re-uploaded.
+    pub fn remove(&mut self, index: usize) -> Instance {
+        let removed = self.instances.swap_remove(index);
+        if index < self.instances.len() {
+            self.mark_dirty(index);
+        }
+        removed
+    }
+
+    pub fn set(&mut self, index: usize, instance: Instance) {
+        self.instances[index] = instance;
+        self.mark_dirty(index);
+    }
+
+    /// Edits an instance in place.
+    pub fn update(&mut self, index: usize, f: impl FnOnce(&mut Instance)) {
+        f(&mut self.instances[index]);
+        self.mark_dirty(index);
+    }
+
+    /// Advances every moving instance by `dt` seconds, see [`Instance::step`].
+    pub fn step(&mut self, dt: f32) {
+        for index in 0..self.instances.len() {
+            if self.instances[index].is_moving() {
+                self.instances[index].step(dt);
+                self.mark_dirty(index);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty = None;
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(range) => range.start.min(index)..range.end.max(index + 1),
+            None => index..index + 1,
+        });
+    }
+
+    /// Uploads pending changes, growing the GPU buffer if needed.
+    pub fn sync(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.instances.len() > self.capacity {
+            self.capacity = self.instances.len().next_power_of_two();
+            self.buffer = create_buffer(device, self.capacity);
+            self.dirty = Some(0..self.instances.len());
+        }
+
+        let Some(range) = self.dirty.take() else {
+            return;
+        };
+        let range = range.start..range.end.min(self.instances.len());
+        if range.is_empty() {
+            return;
+        }
+        let data: Vec<InstanceRaw> = self.instances[range.clone()]
+            .iter()
+            .map(Instance::to_raw)
+            .collect();
+        let offset = (range.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&data));
+    }
+
+    /// The part of the GPU buffer holding the current instances.
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        let size = self.instances.len() * std::mem::size_of::<InstanceRaw>();
+        self.buffer.slice(..size as wgpu::BufferAddress)
+    }
+}
+
+fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Instance Buffer"),
+        size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
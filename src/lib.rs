@@ -1,27 +1,84 @@
 use std::iter;
 
-use wgpu::util::DeviceExt;
+use cgmath::{Quaternion, Rad, Rotation3, Vector3};
 use winit::{
     event::*,
     event_loop::EventLoop,
     keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowBuilder},
+    window::{CursorGrabMode, Window, WindowBuilder},
 };
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-mod texture;
+mod antialiasing;
+pub mod clock;
+pub mod controller;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headless;
+pub mod ibl;
+mod id_buffer;
+pub mod instance;
+pub mod light;
+pub mod model;
+pub mod picking;
+pub mod post;
+pub mod projection;
+mod renderer;
+pub mod scene;
+mod shadow;
+pub mod skybox;
+pub mod texture;
+
+pub use antialiasing::Antialiasing;
+pub use clock::{FrameClock, FrameTime};
+pub use controller::{CameraController, CameraMode, FlyController, OrbitController};
+pub use post::{BloomSettings, PostSettings, Tonemapping};
+pub use projection::{Projection, ProjectionMode};
+pub use renderer::{supported_sample_counts, DepthSettings, Renderer};
+pub use shadow::{ShadowSettings, MAX_CASCADES};
+
+use light::Light;
+use renderer::OPTIONAL_FEATURES;
+
+/// How fast the sun circles the scene in the apps, in radians per simulated
+/// second
+const SUN_SPEED: f32 = std::f32::consts::TAU / 60.0;
+/// How fast the textured pentagon turns in place in the apps, in radians per
+/// simulated second
+const PENTAGON_SPEED: f32 = 1.0;
+
+/// Sets the pentagon of the default scene turning, so the simulation has
+/// something to do.
+fn spin_pentagon(renderer: &mut Renderer) {
+    renderer.models[0].instances.update(0, |pentagon| {
+        pentagon.angular_velocity = Vector3::unit_z() * PENTAGON_SPEED;
+    });
+}
+
+/// Runs the frame's fixed simulation steps, and circles the sun around the
+/// scene on simulated time, pausing and speeding up with the simulation.
+fn simulate(renderer: &mut Renderer, time: &FrameTime) {
+    for _ in 0..time.fixed_steps {
+        renderer.step(time.fixed_delta);
+    }
+    if time.delta > 0.0 {
+        if let Some(Light::Directional { direction, .. }) = renderer.lights.get_mut(0) {
+            *direction = Quaternion::from_angle_y(Rad(SUN_SPEED * time.delta)) * *direction;
+        }
+    }
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
 }
 
 impl Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -37,6 +94,11 @@ impl Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
@@ -46,169 +108,119 @@ const VERTICES: &[Vertex] = &[
     Vertex {
         position: [-0.0868241, 0.49240386, 0.0],
         tex_coords: [0.4131759, 0.00759614],
+        normal: [0.0, 0.0, 1.0],
     }, // A
     Vertex {
         position: [-0.49513406, 0.06958647, 0.0],
         tex_coords: [0.0048659444, 0.43041354],
+        normal: [0.0, 0.0, 1.0],
     }, // B
     Vertex {
         position: [-0.21918549, -0.44939706, 0.0],
         tex_coords: [0.28081453, 0.949397],
+        normal: [0.0, 0.0, 1.0],
     }, // C
     Vertex {
         position: [0.35966998, -0.3473291, 0.0],
         tex_coords: [0.85967, 0.84732914],
+        normal: [0.0, 0.0, 1.0],
     }, // D
     Vertex {
         position: [0.44147372, 0.2347359, 0.0],
         tex_coords: [0.9414737, 0.2652641],
+        normal: [0.0, 0.0, 1.0],
     }, // E
 ];
 
-const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4, /* padding */ 0];
+const INDICES: &[u32] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
 
+/// Maps OpenGL's -1..1 clip space depth, which cgmath builds projections
+/// for, to wgpu's 0..1. Arguments are columns, so this is z' = (z + w) / 2.
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
-struct Camera {
-    eye: cgmath::Point3<f32>,
-    target: cgmath::Point3<f32>,
-    up: cgmath::Vector3<f32>,
-    aspect: f32,
-    fovy: f32,
-    znear: f32,
-    zfar: f32,
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+    pub aspect: f32,
+    /// Vertical field of view in degrees. Orthographic cameras use it to
+    /// size their view, see [`ProjectionMode::Orthographic`].
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    pub projection: ProjectionMode,
 }
 
 impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-        proj * view
+    pub fn view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    pub fn projection(&self) -> Projection {
+        use cgmath::MetricSpace;
+        let fovy = cgmath::Deg(self.fovy).into();
+        match self.projection {
+            ProjectionMode::Perspective => Projection::Perspective {
+                fovy,
+                aspect: self.aspect,
+                znear: self.znear,
+                zfar: self.zfar,
+            },
+            ProjectionMode::Orthographic => {
+                // As tall as the perspective view at the target's distance
+                let height = 2.0 * self.eye.distance(self.target) * (fovy / 2.0).0.tan();
+                Projection::orthographic(height * self.aspect, height, self.znear, self.zfar)
+            }
+            ProjectionMode::ReverseZ => Projection::ReverseZ {
+                fovy,
+                aspect: self.aspect,
+                znear: self.znear,
+            },
+        }
+    }
+
+    /// Maps world space to wgpu's clip space.
+    pub fn view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.projection().matrix() * self.view_matrix()
     }
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
+    // Vec4 instead of vec3 to match the 16 byte alignment of uniforms
+    view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    /// Direction the camera looks in, and 1 in w if it's orthographic
+    view_forward: [f32; 4],
 }
 
 impl CameraUniform {
     fn new() -> Self {
         use cgmath::SquareMatrix;
         Self {
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            view_forward: [0.0, 0.0, -1.0, 0.0],
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix()).into();
-    }
-}
-
-struct CameraController {
-    speed: f32,
-    is_up_pressed: bool,
-    is_down_pressed: bool,
-    is_forward_pressed: bool,
-    is_backward_pressed: bool,
-    is_left_pressed: bool,
-    is_right_pressed: bool,
-}
-
-impl CameraController {
-    fn new(speed: f32) -> Self {
-        Self {
-            speed,
-            is_up_pressed: false,
-            is_down_pressed: false,
-            is_forward_pressed: false,
-            is_backward_pressed: false,
-            is_left_pressed: false,
-            is_right_pressed: false,
-        }
-    }
-
-    fn process_events(&mut self, event: &WindowEvent) -> bool {
-        match event {
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state,
-                        physical_key: PhysicalKey::Code(keycode),
-                        ..
-                    },
-                ..
-            } => {
-                let is_pressed = *state == ElementState::Pressed;
-                match keycode {
-                    KeyCode::Space => {
-                        self.is_up_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::ShiftLeft => {
-                        self.is_down_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyW | KeyCode::ArrowUp => {
-                        self.is_forward_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyA | KeyCode::ArrowLeft => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyS | KeyCode::ArrowDown => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyD | KeyCode::ArrowRight => {
-                        self.is_right_pressed = is_pressed;
-                        true
-                    }
-                    _ => false,
-                }
-            }
-            _ => false,
-        }
-    }
-
-    fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::InnerSpace;
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
-
-        // Prevents glitching when camera gets too close to the
-        // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
-        }
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
-        }
-
-        let right = forward_norm.cross(camera.up);
-
-        // Redo radius calc in case the up/ down is pressed.
-        let forward = camera.target - camera.eye;
-        let forward_mag = forward.magnitude();
-
-        if self.is_right_pressed {
-            // Rescale the distance between the target and eye so
-            // that it doesn't change. The eye therefore still
-            // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
-        }
-        if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
-        }
+        use cgmath::{EuclideanSpace, InnerSpace};
+        self.view_position = camera.eye.to_vec().extend(1.0).into();
+        self.view_proj = camera.view_projection_matrix().into();
+        let orthographic = camera.projection().is_orthographic();
+        self.view_forward = (camera.target - camera.eye)
+            .normalize()
+            .extend(if orthographic { 1.0 } else { 0.0 })
+            .into();
     }
 }
 
@@ -218,24 +230,17 @@ struct State<'a> {
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
-    #[allow(dead_code)]
-    diffuse_texture: texture::Texture,
-    diffuse_bind_group: wgpu::BindGroup,
-    // NEW!
-    camera: Camera,
+    renderer: Renderer,
+    /// MSAA sample counts the surface supports, cycled through with M
+    sample_counts: Vec<u32>,
     camera_controller: CameraController,
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
+    clock: FrameClock,
+    cursor: Option<winit::dpi::PhysicalPosition<f64>>,
     window: &'a Window,
 }
 
 impl<'a> State<'a> {
-    async fn new(window: &'a Window) -> State<'a> {
+    async fn new(window: &'a Window, sample_count: u32, post: PostSettings) -> State<'a> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -262,7 +267,7 @@ impl<'a> State<'a> {
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::empty(),
+                    required_features: adapter.features() & OPTIONAL_FEATURES,
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     required_limits: if cfg!(target_arch = "wasm32") {
@@ -278,9 +283,8 @@ impl<'a> State<'a> {
             .unwrap();
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
+        // Post-processing encodes sRGB itself for other surfaces, but an Srgb surface
+        // does it for free.
         let surface_format = surface_caps
             .formats
             .iter()
@@ -298,168 +302,16 @@ impl<'a> State<'a> {
             desired_maximum_frame_latency: 2,
         };
 
-        let diffuse_bytes = include_bytes!("../assets/happy-tree.png");
-        let diffuse_texture = texture::Texture::from_bytes(
+        let mut renderer =
+            Renderer::new(&device, &queue, config.format, config.width, config.height);
+        let sample_counts = supported_sample_counts(
+            &adapter,
             &device,
-            &queue,
-            diffuse_bytes,
-            "../assets/happy-tree.png",
-        )
-        .unwrap();
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
-
-        let camera = Camera {
-            eye: (0.0, 1.0, 2.0).into(),
-            target: (0.0, 0.0, 0.0).into(),
-            up: cgmath::Vector3::unit_y(),
-            aspect: config.width as f32 / config.height as f32,
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 100.0,
-        };
-        let camera_controller = CameraController::new(0.2);
-
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
-
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
-
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
-                // or Features::POLYGON_MODE_POINT
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-            // Useful for optimizing shader compilation on Android
-            cache: None,
-        });
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        let num_indices = INDICES.len() as u32;
+            &[post::HDR_FORMAT, renderer.depth_settings().format],
+        );
+        renderer.set_sample_count(&device, closest_sample_count(&sample_counts, sample_count));
+        renderer.post.set_settings(&device, post);
+        let camera_controller = CameraController::new();
 
         Self {
             surface,
@@ -467,23 +319,17 @@ impl<'a> State<'a> {
             queue,
             config,
             size,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
-            diffuse_texture,
-            diffuse_bind_group,
-            camera,
+            renderer,
+            sample_counts,
             camera_controller,
-            camera_buffer,
-            camera_bind_group,
-            camera_uniform,
+            clock: FrameClock::new(),
+            cursor: None,
             window,
         }
     }
 
     pub fn window(&self) -> &Window {
-        &self.window
+        self.window
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -493,22 +339,164 @@ impl<'a> State<'a> {
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
 
-            self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+            self.renderer
+                .resize(&self.device, self.config.width, self.config.height);
         }
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+        match event {
+            WindowEvent::CursorMoved { position, .. } => self.cursor = Some(*position),
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if self.camera_controller.mode() == CameraMode::Orbit => {
+                if let Some(cursor) = self.cursor {
+                    if self.renderer.gpu_picking() {
+                        // Logged in `update` once the GPU has answered
+                        self.renderer.request_pick(cursor.x as u32, cursor.y as u32);
+                    } else {
+                        match self.renderer.pick(cursor.x as f32, cursor.y as f32) {
+                            Some(hit) => log::info!("Picked {hit:?}"),
+                            None => log::info!("Picked nothing"),
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let WindowEvent::Focused(false) = event {
+            // Give the cursor back when switching to another window
+            self.camera_controller.set_mode(CameraMode::Orbit);
+        }
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(keycode),
+                    ..
+                },
+            ..
+        } = event
+        {
+            match keycode {
+                KeyCode::KeyG => {
+                    let enabled = !self.renderer.gpu_picking();
+                    self.renderer.set_gpu_picking(&self.device, enabled);
+                    log::info!("GPU picking: {enabled}");
+                    return true;
+                }
+                KeyCode::KeyM => {
+                    let current = self.renderer.sample_count();
+                    let next = self
+                        .sample_counts
+                        .iter()
+                        .copied()
+                        .find(|&count| count > current)
+                        .unwrap_or(1);
+                    self.renderer.set_sample_count(&self.device, next);
+                    log::info!("MSAA: {next}x");
+                    return true;
+                }
+                KeyCode::KeyT => {
+                    let mut settings = self.renderer.post.settings();
+                    settings.tonemapping = settings.tonemapping.next();
+                    self.renderer.post.set_settings(&self.device, settings);
+                    log::info!("Tonemapping: {:?}", settings.tonemapping);
+                    return true;
+                }
+                KeyCode::KeyB => {
+                    let mut settings = self.renderer.post.settings();
+                    settings.bloom = match settings.bloom {
+                        Some(_) => None,
+                        None => Some(BloomSettings::default()),
+                    };
+                    self.renderer.post.set_settings(&self.device, settings);
+                    log::info!("Bloom: {}", settings.bloom.is_some());
+                    return true;
+                }
+                KeyCode::Minus | KeyCode::Equal => {
+                    let mut settings = self.renderer.post.settings();
+                    settings.exposure += if *keycode == KeyCode::Minus {
+                        -0.5
+                    } else {
+                        0.5
+                    };
+                    self.renderer.post.set_settings(&self.device, settings);
+                    log::info!("Exposure: {:+} stops", settings.exposure);
+                    return true;
+                }
+                KeyCode::KeyF => {
+                    let mut settings = self.renderer.post.settings();
+                    settings.antialiasing = settings.antialiasing.next();
+                    self.renderer.post.set_settings(&self.device, settings);
+                    log::info!("Anti-aliasing: {:?}", settings.antialiasing);
+                    return true;
+                }
+                KeyCode::KeyP => {
+                    self.clock.toggle_paused();
+                    return true;
+                }
+                KeyCode::Period => {
+                    self.clock.step();
+                    return true;
+                }
+                KeyCode::BracketLeft | KeyCode::BracketRight => {
+                    let factor = if *keycode == KeyCode::BracketLeft {
+                        0.5
+                    } else {
+                        2.0
+                    };
+                    let scale = (self.clock.time_scale() * factor).clamp(1.0 / 16.0, 16.0);
+                    self.clock.set_time_scale(scale);
+                    log::info!("Time scale: {scale}");
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        let used = self.camera_controller.process_events(event);
+        if self.camera_controller.mode_changed() {
+            self.grab_cursor(self.camera_controller.mode() == CameraMode::Fly);
+        }
+        used
+    }
+
+    fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        self.camera_controller.process_device_events(event)
+    }
+
+    fn grab_cursor(&self, grab: bool) {
+        let result = if grab {
+            // Not every platform supports both modes
+            self.window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            log::warn!("Couldn't grab the cursor: {e}");
+        }
+        self.window.set_cursor_visible(!grab);
     }
 
     fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
+        let time = self.clock.tick();
+        // The camera runs on real time so it can still be moved around
+        // while the simulation is paused or slowed down
+        self.camera_controller
+            .update_camera(&mut self.renderer.camera, time.real_delta);
+        simulate(&mut self.renderer, &time);
+        self.renderer.update(&self.device, &self.queue);
+        for pick in self.renderer.poll_picks(&self.device) {
+            match pick.objects().first() {
+                Some(id) => log::info!("Picked {id:?}"),
+                None => log::info!("Picked nothing"),
+            }
+        }
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -523,34 +511,7 @@ impl<'a> State<'a> {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-        }
+        self.renderer.render(&mut encoder, &view);
 
         self.queue.submit(iter::once(encoder.finish()));
         output.present();
@@ -559,8 +520,50 @@ impl<'a> State<'a> {
     }
 }
 
+/// The most MSAA samples `supported` allows, up to `requested`.
+fn closest_sample_count(supported: &[u32], requested: u32) -> u32 {
+    let count = supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1);
+    if count != requested {
+        log::warn!("{requested}x MSAA isn't supported, using {count}x");
+    }
+    count
+}
+
+/// Where the apps cache image-based lighting between runs.
+#[cfg(not(target_arch = "wasm32"))]
+fn ibl_cache_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join("rustgl-ibl")
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    // MSAA costs too much fill rate on WebGL2, where FXAA does instead
+    if cfg!(target_arch = "wasm32") {
+        let post = PostSettings {
+            antialiasing: Antialiasing::Fxaa,
+            ..PostSettings::filmic()
+        };
+        run_with_models(&[], &[], 1, post).await
+    } else {
+        run_with_models(&[], &[], 4, PostSettings::filmic()).await
+    }
+}
+
+/// Opens a window showing the given `.obj`/`.gltf`/`.glb` models, or the textured pentagon
+/// if `models` is empty, in front of the skybox loaded from `skybox`, if any; see
+/// [`Renderer::load_skybox`]. Edges are smoothed with up to `sample_count` MSAA samples
+/// per pixel, and the image is post-processed with `post`.
+pub async fn run_with_models(
+    models: &[std::path::PathBuf],
+    skybox: &[std::path::PathBuf],
+    sample_count: u32,
+    post: PostSettings,
+) {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -593,7 +596,23 @@ pub async fn run() {
     }
 
     // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window, sample_count, post).await;
+    state
+        .renderer
+        .load_models(&state.device, &state.queue, models)
+        .expect("Couldn't load models");
+    if models.is_empty() {
+        spin_pentagon(&mut state.renderer);
+    }
+    // The web has no file system to cache to
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        state.renderer.ibl_cache_dir = Some(ibl_cache_dir());
+    }
+    state
+        .renderer
+        .load_skybox(&state.device, &state.queue, skybox)
+        .expect("Couldn't load the skybox");
     let mut surface_configured = false;
 
     event_loop
@@ -602,56 +621,112 @@ pub async fn run() {
                 Event::WindowEvent {
                     ref event,
                     window_id,
-                } if window_id == state.window().id() => {
-                    if !state.input(event) {
-                        match event {
-                            WindowEvent::CloseRequested
-                            | WindowEvent::KeyboardInput {
-                                event:
-                                    KeyEvent {
-                                        state: ElementState::Pressed,
-                                        physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                        ..
-                                    },
-                                ..
-                            } => control_flow.exit(),
-                            WindowEvent::Resized(physical_size) => {
-                                surface_configured = true;
-                                state.resize(*physical_size);
+                } if window_id == state.window().id() && !state.input(event) => {
+                    match event {
+                        WindowEvent::CloseRequested
+                        | WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::Escape),
+                                    ..
+                                },
+                            ..
+                        } => control_flow.exit(),
+                        WindowEvent::Resized(physical_size) => {
+                            surface_configured = true;
+                            state.resize(*physical_size);
+                        }
+                        WindowEvent::RedrawRequested => {
+                            // This tells winit that we want another frame after this one
+                            state.window().request_redraw();
+
+                            if !surface_configured {
+                                return;
                             }
-                            WindowEvent::RedrawRequested => {
-                                // This tells winit that we want another frame after this one
-                                state.window().request_redraw();
 
-                                if !surface_configured {
-                                    return;
+                            state.update();
+                            match state.render() {
+                                Ok(_) => {}
+                                // Reconfigure the surface if it's lost or outdated
+                                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                                    state.resize(state.size)
+                                }
+                                // The system is out of memory, we should probably quit
+                                Err(wgpu::SurfaceError::OutOfMemory) => {
+                                    log::error!("OutOfMemory");
+                                    control_flow.exit();
                                 }
 
-                                state.update();
-                                match state.render() {
-                                    Ok(_) => {}
-                                    // Reconfigure the surface if it's lost or outdated
-                                    Err(
-                                        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
-                                    ) => state.resize(state.size),
-                                    // The system is out of memory, we should probably quit
-                                    Err(wgpu::SurfaceError::OutOfMemory) => {
-                                        log::error!("OutOfMemory");
-                                        control_flow.exit();
-                                    }
-
-                                    // This happens when the a frame takes too long to present
-                                    Err(wgpu::SurfaceError::Timeout) => {
-                                        log::warn!("Surface timeout")
-                                    }
+                                // This happens when the a frame takes too long to present
+                                Err(wgpu::SurfaceError::Timeout) => {
+                                    log::warn!("Surface timeout")
                                 }
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
                 }
+                Event::DeviceEvent { ref event, .. } => {
+                    state.device_input(event);
+                }
                 _ => {}
             }
         })
         .unwrap();
 }
+
+/// Renders `frames` frames without a window and writes them as PNGs. With a
+/// single frame the image is written to `path` as is; otherwise a frame number
+/// is appended to the file stem. The scene animates as in the apps, with
+/// frames a fixed step of simulated time (1/60 s) apart however long they
+/// take to render.
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(clippy::too_many_arguments)]
+pub async fn run_headless(
+    path: &std::path::Path,
+    width: u32,
+    height: u32,
+    frames: u32,
+    models: &[std::path::PathBuf],
+    skybox: &[std::path::PathBuf],
+    sample_count: u32,
+    post: PostSettings,
+) -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut headless = headless::Headless::new(width, height).await?;
+    let sample_count = closest_sample_count(headless.supported_sample_counts(), sample_count);
+    headless
+        .renderer
+        .set_sample_count(&headless.device, sample_count);
+    headless.renderer.post.set_settings(&headless.device, post);
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, models)?;
+    if models.is_empty() {
+        spin_pentagon(&mut headless.renderer);
+    }
+    headless.renderer.ibl_cache_dir = Some(ibl_cache_dir());
+    headless
+        .renderer
+        .load_skybox(&headless.device, &headless.queue, skybox)?;
+    let mut clock = FrameClock::new();
+    for frame in 0..frames {
+        // The first frame shows the scene as it starts, each later one a
+        // fixed step on
+        if frame > 0 {
+            let time = clock.advance(clock.fixed_step);
+            simulate(&mut headless.renderer, &time);
+        }
+        let frame_path = if frames == 1 {
+            path.to_path_buf()
+        } else {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("{stem}_{frame:04}.png"))
+        };
+        headless.save_png(&frame_path)?;
+        log::info!("Wrote {}", frame_path.display());
+    }
+    Ok(())
+}
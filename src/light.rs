@@ -0,0 +1,438 @@
+use bytemuck::Zeroable;
+use cgmath::{Angle, Deg, InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::ibl::Environment;
+
+/// Lights are passed in a uniform array, since WebGL2 has no storage buffers.
+/// Must match `MAX_LIGHTS` in `shader.wgsl`.
+pub const MAX_LIGHTS: usize = 16;
+
+/// Number of directional and spot lights that can cast shadows at once, one
+/// shadow map layer each. Further shadow-casting lights are drawn unshadowed.
+/// Must match `MAX_SHADOWS` in `shader.wgsl`.
+pub const MAX_SHADOWS: usize = 4;
+
+/// Distance falloff of point and spot lights:
+/// `1 / (constant + linear * d + quadratic * d²)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Default for Attenuation {
+    /// Falls to 5% at a distance of about 9 units, and to about 1% at 20
+    fn default() -> Self {
+        Self {
+            constant: 1.0,
+            linear: 0.22,
+            quadratic: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Infinitely far away light, like the sun. `direction` is the direction
+    /// the light travels in.
+    Directional {
+        direction: Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        cast_shadows: bool,
+    },
+    Point {
+        position: Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        attenuation: Attenuation,
+    },
+    /// A point light restricted to a cone. The intensity fades out between
+    /// `inner_angle` and `outer_angle`, both measured from `direction`.
+    Spot {
+        position: Vector3<f32>,
+        direction: Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        attenuation: Attenuation,
+        inner_angle: Deg<f32>,
+        outer_angle: Deg<f32>,
+        cast_shadows: bool,
+    },
+}
+
+impl Light {
+    pub fn position(&self) -> Option<Vector3<f32>> {
+        match self {
+            Light::Directional { .. } => None,
+            Light::Point { position, .. } | Light::Spot { position, .. } => Some(*position),
+        }
+    }
+
+    /// Moves point and spot lights; directional lights are unaffected.
+    pub fn set_position(&mut self, new_position: Vector3<f32>) {
+        match self {
+            Light::Directional { .. } => {}
+            Light::Point { position, .. } | Light::Spot { position, .. } => {
+                *position = new_position
+            }
+        }
+    }
+
+    /// Point lights never cast shadows.
+    pub fn casts_shadows(&self) -> bool {
+        match self {
+            Light::Directional { cast_shadows, .. } | Light::Spot { cast_shadows, .. } => {
+                *cast_shadows
+            }
+            Light::Point { .. } => false,
+        }
+    }
+
+    /// `shadow_index` is the light's shadow map layer, or -1 without one.
+    fn to_raw(self, shadow_index: i32) -> LightRaw {
+        const DIRECTIONAL: u32 = 0;
+        const POINT: u32 = 1;
+        const SPOT: u32 = 2;
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        match self {
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+                ..
+            } => LightRaw {
+                position: zero.into(),
+                kind: DIRECTIONAL,
+                direction: direction.normalize().into(),
+                intensity,
+                color,
+                cos_inner: 0.0,
+                attenuation: [1.0, 0.0, 0.0],
+                cos_outer: 0.0,
+                shadow_index,
+                _padding: [0; 3],
+            },
+            Light::Point {
+                position,
+                color,
+                intensity,
+                attenuation,
+            } => LightRaw {
+                position: position.into(),
+                kind: POINT,
+                direction: zero.into(),
+                intensity,
+                color,
+                cos_inner: 0.0,
+                attenuation: attenuation.into(),
+                cos_outer: 0.0,
+                shadow_index,
+                _padding: [0; 3],
+            },
+            Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                attenuation,
+                inner_angle,
+                outer_angle,
+                ..
+            } => LightRaw {
+                position: position.into(),
+                kind: SPOT,
+                direction: direction.normalize().into(),
+                intensity,
+                color,
+                cos_inner: inner_angle.cos(),
+                attenuation: attenuation.into(),
+                cos_outer: outer_angle.cos(),
+                shadow_index,
+                _padding: [0; 3],
+            },
+        }
+    }
+}
+
+impl From<Attenuation> for [f32; 3] {
+    fn from(a: Attenuation) -> Self {
+        [a.constant, a.linear, a.quadratic]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    cos_inner: f32,
+    attenuation: [f32; 3],
+    cos_outer: f32,
+    shadow_index: i32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsUniform {
+    ambient: [f32; 3],
+    count: u32,
+    lights: [LightRaw; MAX_LIGHTS],
+    /// Scale of the image-based lighting, 0 without an environment
+    environment_intensity: f32,
+    specular_max_lod: f32,
+    _padding: [f32; 2],
+}
+
+/// The lights of a scene and their uniform buffer. Changes are uploaded by
+/// `sync`, which `Renderer::update` calls every frame.
+///
+/// The environment's image-based lighting shares the bind group, since
+/// WebGL2 allows only four and the other three are taken.
+pub struct Lights {
+    lights: Vec<Light>,
+    ambient: [f32; 3],
+    environment: Option<Environment>,
+    environment_intensity: f32,
+    /// Bound in place of the environment's maps while there is none
+    placeholder: Environment,
+    sampler: wgpu::Sampler,
+    dirty: bool,
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Lights {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[LightsUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let cube = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                cube(1),
+                cube(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("light_bind_group_layout"),
+        });
+
+        let placeholder = Environment::placeholder(device);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group =
+            create_bind_group(device, &bind_group_layout, &buffer, &placeholder, &sampler);
+
+        Self {
+            lights: Vec::new(),
+            ambient: [0.0; 3],
+            environment: None,
+            environment_intensity: 1.0,
+            placeholder,
+            sampler,
+            dirty: true,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn ambient(&self) -> [f32; 3] {
+        self.ambient
+    }
+
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.ambient = ambient;
+        self.dirty = true;
+    }
+
+    pub fn environment(&self) -> Option<&Environment> {
+        self.environment.as_ref()
+    }
+
+    /// Lights the scene with an environment's image-based lighting instead of
+    /// the flat `ambient` color, or goes back to `ambient` with `None`.
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: Option<Environment>) {
+        self.environment = environment;
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.buffer,
+            self.environment.as_ref().unwrap_or(&self.placeholder),
+            &self.sampler,
+        );
+        self.dirty = true;
+    }
+
+    pub fn environment_intensity(&self) -> f32 {
+        self.environment_intensity
+    }
+
+    /// Scales the environment's light, 1 by default.
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment_intensity = intensity;
+        self.dirty = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Light> {
+        self.lights.iter()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Light> {
+        self.lights.get(index)
+    }
+
+    /// Returns a light for editing, e.g. to move it.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Light> {
+        self.dirty = true;
+        self.lights.get_mut(index)
+    }
+
+    /// Adds a light and returns its index, or `None` if there already are
+    /// `MAX_LIGHTS` lights.
+    pub fn add(&mut self, light: Light) -> Option<usize> {
+        if self.lights.len() >= MAX_LIGHTS {
+            return None;
+        }
+        self.lights.push(light);
+        self.dirty = true;
+        Some(self.lights.len() - 1)
+    }
+
+    /// Removes a light. Lights after it shift down by one index.
+    pub fn remove(&mut self, index: usize) -> Light {
+        self.dirty = true;
+        self.lights.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+        self.dirty = true;
+    }
+
+    /// The lights that get a shadow map, in shadow map layer order: the first
+    /// `MAX_SHADOWS` lights that cast shadows.
+    pub fn shadow_casters(&self) -> impl Iterator<Item = &Light> {
+        self.lights
+            .iter()
+            .filter(|light| light.casts_shadows())
+            .take(MAX_SHADOWS)
+    }
+
+    pub fn sync(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        let mut uniform = LightsUniform::zeroed();
+        uniform.ambient = self.ambient;
+        uniform.count = self.lights.len() as u32;
+        if let Some(environment) = &self.environment {
+            uniform.environment_intensity = self.environment_intensity;
+            uniform.specular_max_lod = environment.specular_max_lod();
+        }
+        let mut shadow_count = 0;
+        for (raw, light) in uniform.lights.iter_mut().zip(&self.lights) {
+            let shadow_index = if light.casts_shadows() && shadow_count < MAX_SHADOWS {
+                shadow_count += 1;
+                shadow_count as i32 - 1
+            } else {
+                -1
+            };
+            *raw = light.to_raw(shadow_index);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        self.dirty = false;
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    environment: &Environment,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&environment.irradiance.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&environment.specular.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&environment.brdf_lut.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+        label: Some("light_bind_group"),
+    })
+}
@@ -1,6 +1,85 @@
+use std::path::PathBuf;
+
 use pollster::block_on;
-use rustgl::run;
+#[cfg(not(target_arch = "wasm32"))]
+use rustgl::run_headless;
+use rustgl::{run_with_models, PostSettings};
+
+const USAGE: &str = "usage: rustgl [--headless <out.png> [--size WxH] [--frames N]] \
+                     [--skybox <panorama.hdr> | --skybox <face.png> x6 (+X -X +Y -Y +Z -Z)] \
+                     [--msaa 1|2|4|8] [--tonemap none|reinhard|aces|agx] [--exposure <stops>] \
+                     [--no-bloom] [--aa none|fxaa|smaa] [model.obj|model.gltf ...]";
+
+/// Prints the usage and exits, for arguments that can't be parsed
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
 
 fn main() {
-    block_on(run());
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut headless = None;
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut size = (800, 600);
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut frames = 1;
+    let mut models = Vec::new();
+    let mut skybox = Vec::new();
+    let mut sample_count = 4;
+    let mut post = PostSettings::filmic();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            #[cfg(not(target_arch = "wasm32"))]
+            "--headless" => {
+                headless = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage()))
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            "--size" => {
+                size = args
+                    .next()
+                    .as_ref()
+                    .and_then(|s| s.split_once('x'))
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .unwrap_or_else(|| usage())
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            "--frames" => frames = parse(args.next()),
+            "--skybox" => skybox.push(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "--msaa" => sample_count = parse(args.next()),
+            "--tonemap" => post.tonemapping = parse(args.next()),
+            "--exposure" => post.exposure = parse(args.next()),
+            "--no-bloom" => post.bloom = None,
+            "--aa" => post.antialiasing = parse(args.next()),
+            _ if arg.starts_with("--") => usage(),
+            _ => models.push(PathBuf::from(arg)),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = headless {
+        let result = block_on(run_headless(
+            &path,
+            size.0,
+            size.1,
+            frames,
+            &models,
+            &skybox,
+            sample_count,
+            post,
+        ));
+        if let Err(e) = result {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    block_on(run_with_models(&models, &skybox, sample_count, post));
+}
+
+/// Parses an option's value, or exits with the usage if it's missing or bad
+fn parse<T: std::str::FromStr>(value: Option<String>) -> T {
+    value
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| usage())
 }
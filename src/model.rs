@@ -0,0 +1,585 @@
+use std::{fs::File, io::BufReader, ops::Range, path::Path};
+
+use anyhow::*;
+use cgmath::{EuclideanSpace, Matrix4, Point3, Transform};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    instance::{Instance, InstanceBuffer},
+    picking::Bvh,
+    texture, Vertex,
+};
+
+/// Scalar metallic-roughness parameters, multiplied with the matching maps.
+/// Defaults describe a plain white, fully rough dielectric.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParams {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub normal_scale: f32,
+    /// How much the occlusion map darkens ambient light, from 0 to 1
+    pub occlusion_strength: f32,
+    /// Linear emitted color. Black unless the material glows.
+    pub emissive: [f32; 3],
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    _padding: f32,
+}
+
+impl From<MaterialParams> for MaterialUniform {
+    fn from(p: MaterialParams) -> Self {
+        Self {
+            base_color: p.base_color,
+            emissive: p.emissive,
+            metallic: p.metallic,
+            roughness: p.roughness,
+            normal_scale: p.normal_scale,
+            occlusion_strength: p.occlusion_strength,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// The texture maps of a material, laid out like glTF's metallic-roughness
+/// model. Any of them may be missing.
+#[derive(Default)]
+pub struct MaterialMaps {
+    /// sRGB color, multiplied with `MaterialParams::base_color`
+    pub base_color: Option<texture::Texture>,
+    /// Linear; roughness in green, metallic in blue
+    pub metallic_roughness: Option<texture::Texture>,
+    /// Linear tangent-space normals
+    pub normal: Option<texture::Texture>,
+    /// Linear; ambient occlusion in red
+    pub occlusion: Option<texture::Texture>,
+    /// sRGB color, multiplied with `MaterialParams::emissive`
+    pub emissive: Option<texture::Texture>,
+}
+
+/// Bind group layout shared by all materials, plus the 1x1 textures bound in
+/// place of missing maps. White leaves the scalar parameters unchanged, and
+/// the flat normal leaves the surface normal unchanged.
+pub struct MaterialLayout {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    white: texture::Texture,
+    flat_normal: texture::Texture,
+}
+
+impl MaterialLayout {
+    /// Number of texture maps, each bound as a texture and sampler pair.
+    const MAPS: u32 = 5;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let mut entries = Vec::new();
+        for map in 0..Self::MAPS {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: map * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: map * 2 + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: Self::MAPS * 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("material_bind_group_layout"),
+        });
+
+        let white = texture::Texture::from_color(device, queue, [255; 4], "default_white", true)
+            .expect("1x1 textures are always valid");
+        let flat_normal = texture::Texture::from_color(
+            device,
+            queue,
+            [128, 128, 255, 255],
+            "default_normal",
+            false,
+        )
+        .expect("1x1 textures are always valid");
+
+        Self {
+            bind_group_layout,
+            white,
+            flat_normal,
+        }
+    }
+}
+
+pub struct Material {
+    pub name: String,
+    pub maps: MaterialMaps,
+    params: MaterialParams,
+    params_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        maps: MaterialMaps,
+        params: MaterialParams,
+        layout: &MaterialLayout,
+    ) -> Self {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{name} Material Buffer")),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(params)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let textures = [
+            maps.base_color.as_ref().unwrap_or(&layout.white),
+            maps.metallic_roughness.as_ref().unwrap_or(&layout.white),
+            maps.normal.as_ref().unwrap_or(&layout.flat_normal),
+            maps.occlusion.as_ref().unwrap_or(&layout.white),
+            maps.emissive.as_ref().unwrap_or(&layout.white),
+        ];
+        let mut entries = Vec::new();
+        for (map, texture) in (0..).zip(textures) {
+            entries.push(wgpu::BindGroupEntry {
+                binding: map * 2,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: map * 2 + 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: MaterialLayout::MAPS * 2,
+            resource: params_buffer.as_entire_binding(),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout.bind_group_layout,
+            entries: &entries,
+            label: Some(name),
+        });
+
+        Self {
+            name: name.to_string(),
+            maps,
+            params,
+            params_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn params(&self) -> MaterialParams {
+        self.params
+    }
+
+    /// Changes the scalar parameters, uploading them right away.
+    pub fn set_params(&mut self, queue: &wgpu::Queue, params: MaterialParams) {
+        self.params = params;
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialUniform::from(params)]),
+        );
+    }
+}
+
+/// Axis-aligned bounding box. `Aabb::EMPTY` contains nothing and grows to
+/// fit whatever is added to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::EMPTY, |aabb, p| aabb.with_point(p))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn with_point(self, p: Point3<f32>) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(p.x),
+                self.min.y.min(p.y),
+                self.min.z.min(p.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(p.x),
+                self.max.y.max(p.y),
+                self.max.z.max(p.z),
+            ),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        if other.is_empty() {
+            return self;
+        }
+        self.with_point(other.min).with_point(other.max)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// Bounds of this box after `transform`, which may be larger than needed
+    /// for rotations.
+    pub fn transform(&self, transform: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        Self::from_points(self.corners().map(|p| transform.transform_point(p)))
+    }
+}
+
+pub struct Mesh {
+    pub name: String,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    /// `Uint16` unless the mesh has more vertices than a `u16` can address.
+    pub index_format: wgpu::IndexFormat,
+    pub num_elements: u32,
+    /// Index into the owning `Model::materials`
+    pub material: usize,
+    /// Bounds of the vertex positions
+    pub bounds: Aabb,
+    /// The triangles on the CPU, for picking
+    pub bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[Vertex],
+        indices: &[u32],
+        material: usize,
+    ) -> Self {
+        let positions: Vec<Point3<f32>> = vertices.iter().map(|v| v.position.into()).collect();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{name} Vertex Buffer")),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_label = format!("{name} Index Buffer");
+        let (index_buffer, index_format) = if vertices.len() > u16::MAX as usize {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&index_label),
+                contents: bytemuck::cast_slice(indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+            (buffer, wgpu::IndexFormat::Uint32)
+        } else {
+            let indices: Vec<u16> = indices.iter().map(|&i| i as u16).collect();
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&index_label),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+            (buffer, wgpu::IndexFormat::Uint16)
+        };
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            index_format,
+            num_elements: indices.len() as u32,
+            material,
+            bounds: Aabb::from_points(positions.iter().copied()),
+            bvh: Bvh::new(&positions, indices),
+        }
+    }
+}
+
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    /// Copies of the model drawn each frame, a single untransformed one by default
+    pub instances: InstanceBuffer,
+}
+
+impl Model {
+    pub fn new(device: &wgpu::Device, meshes: Vec<Mesh>, materials: Vec<Material>) -> Self {
+        Self {
+            meshes,
+            materials,
+            instances: InstanceBuffer::new(device, vec![Instance::default()]),
+        }
+    }
+
+    /// World-space bounds of all instances of the model.
+    pub fn bounds(&self) -> Aabb {
+        let local = self
+            .meshes
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, mesh| aabb.union(mesh.bounds));
+        self.instances.iter().fold(Aabb::EMPTY, |aabb, instance| {
+            aabb.union(local.transform(&instance.model_matrix()))
+        })
+    }
+
+    /// Loads a Wavefront `.obj` file and the `.mtl` libraries it references.
+    ///
+    /// Every object/group becomes its own `Mesh`. Texture paths in the
+    /// materials are resolved relative to the `.obj` file. Besides `Kd`,
+    /// `map_Kd` and `map_Bump`, the PBR extension's `Pr`, `Pm` and `Ke` are read.
+    /// Meshes without a material use a plain white one.
+    pub fn load_obj(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &MaterialLayout,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut reader = BufReader::new(
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+        );
+
+        let (obj_models, obj_materials) =
+            tobj::load_obj_buf(&mut reader, &tobj::GPU_LOAD_OPTIONS, |mtl_path| {
+                tobj::load_mtl(dir.join(mtl_path))
+            })?;
+        let obj_materials = obj_materials.unwrap_or_else(|e| {
+            log::warn!("Failed to load materials of {}: {e}", path.display());
+            Vec::new()
+        });
+
+        let mut materials = Vec::with_capacity(obj_materials.len() + 1);
+        for m in obj_materials {
+            let load = |file: &Option<String>, srgb| {
+                file.as_ref()
+                    .map(|file| load_texture(device, queue, &dir.join(file), srgb))
+                    .transpose()
+            };
+            let maps = MaterialMaps {
+                base_color: load(&m.diffuse_texture, true)?,
+                normal: load(&m.normal_texture, false)?,
+                ..Default::default()
+            };
+            let mut params = MaterialParams::default();
+            if let Some([r, g, b]) = m.diffuse {
+                params.base_color = [r, g, b, m.dissolve.unwrap_or(1.0)];
+            }
+            // The PBR extension of the MTL format isn't parsed by tobj
+            let scalar = |key: &str| m.unknown_param.get(key).and_then(|v| v.parse().ok());
+            params.roughness = scalar("Pr").unwrap_or(params.roughness);
+            params.metallic = scalar("Pm").unwrap_or(params.metallic);
+            params.emissive = m.emissive.unwrap_or(params.emissive);
+            materials.push(Material::new(device, &m.name, maps, params, layout));
+        }
+        // Shared fallback for meshes that don't reference any material
+        let default_material = materials.len();
+        materials.push(Material::new(
+            device,
+            "default",
+            MaterialMaps::default(),
+            MaterialParams::default(),
+            layout,
+        ));
+
+        let meshes = obj_models
+            .into_iter()
+            .map(|m| {
+                let vertices = obj_vertices(&m.mesh);
+                let material = m
+                    .mesh
+                    .material_id
+                    .filter(|&id| id < default_material)
+                    .unwrap_or(default_material);
+                Mesh::new(device, &m.name, &vertices, &m.mesh.indices, material)
+            })
+            .collect();
+
+        Ok(Self::new(device, meshes, materials))
+    }
+}
+
+fn load_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    path: &Path,
+    srgb: bool,
+) -> Result<texture::Texture> {
+    // Texture coordinates outside 0..1 tile, as MTL files assume by default
+    let options = texture::TextureOptions {
+        srgb,
+        ..Default::default()
+    }
+    .with_address_mode(wgpu::AddressMode::Repeat);
+    texture::Texture::from_path(device, queue, path, options)
+}
+
+/// Interleaves the attributes of a single-indexed `tobj` mesh. OBJ texture
+/// coordinates have their origin in the bottom left, so `v` is flipped. If the
+/// file has no normals, smooth normals are generated from the faces.
+fn obj_vertices(mesh: &tobj::Mesh) -> Vec<Vertex> {
+    let vertex_count = mesh.positions.len() / 3;
+    let mut vertices: Vec<Vertex> = (0..vertex_count)
+        .map(|i| Vertex {
+            position: [
+                mesh.positions[i * 3],
+                mesh.positions[i * 3 + 1],
+                mesh.positions[i * 3 + 2],
+            ],
+            tex_coords: if mesh.texcoords.is_empty() {
+                [0.0, 0.0]
+            } else {
+                [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]]
+            },
+            normal: if mesh.normals.is_empty() {
+                [0.0, 0.0, 0.0]
+            } else {
+                [
+                    mesh.normals[i * 3],
+                    mesh.normals[i * 3 + 1],
+                    mesh.normals[i * 3 + 2],
+                ]
+            },
+        })
+        .collect();
+
+    if mesh.normals.is_empty() {
+        use cgmath::{InnerSpace, Vector3};
+        let mut normals = vec![Vector3::new(0.0f32, 0.0, 0.0); vertex_count];
+        for tri in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| Vector3::from(vertices[tri[k] as usize].position));
+            // Not normalized, so larger faces weigh more
+            let face_normal = (b - a).cross(c - a);
+            for &i in tri {
+                normals[i as usize] += face_normal;
+            }
+        }
+        for (vertex, normal) in vertices.iter_mut().zip(normals) {
+            if normal.magnitude2() > 0.0 {
+                vertex.normal = normal.normalize().into();
+            }
+        }
+    }
+
+    vertices
+}
+
+pub trait DrawModel<'a> {
+    fn draw_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+
+    /// Draws every mesh once per instance in `model.instances`.
+    fn draw_model(&mut self, model: &'a Model, camera_bind_group: &'a wgpu::BindGroup);
+
+    /// Draws the model's geometry without binding any materials, for
+    /// depth-only passes like shadow maps. Bind groups are left to the caller.
+    fn draw_model_depth(&mut self, model: &'a Model);
+}
+
+impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'b wgpu::BindGroup) {
+        if model.instances.is_empty() {
+            return;
+        }
+        self.set_vertex_buffer(1, model.instances.slice());
+        let instances = 0..model.instances.len() as u32;
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+        }
+    }
+
+    fn draw_model_depth(&mut self, model: &'b Model) {
+        if model.instances.is_empty() {
+            return;
+        }
+        self.set_vertex_buffer(1, model.instances.slice());
+        for mesh in &model.meshes {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+            self.draw_indexed(0..mesh.num_elements, 0, 0..model.instances.len() as u32);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3, Vector4,
+};
+
+use crate::{model::Aabb, model::Model, Camera};
+
+/// A half-line starting at `origin`. Distances along it are measured in
+/// multiples of `direction`, so they're in world units for a normalized one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self { origin, direction }
+    }
+
+    /// The ray through a point on screen, in pixels from the top left corner
+    /// of a `width` by `height` view, like the positions in
+    /// `WindowEvent::CursorMoved`. It starts on the camera's near plane.
+    pub fn from_screen(camera: &Camera, x: f32, y: f32, width: u32, height: u32) -> Self {
+        let ndc_x = 2.0 * x / width as f32 - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / height as f32;
+        Self::from_ndc(camera, ndc_x, ndc_y)
+    }
+
+    /// The ray through a point in normalized device coordinates, -1 to 1
+    /// from the bottom left to the top right. It starts on the camera's near
+    /// plane.
+    pub fn from_ndc(camera: &Camera, x: f32, y: f32) -> Self {
+        // The view-projection includes the projection's conversion to
+        // wgpu's clip space, so its inverse takes wgpu depths
+        let inverse = camera
+            .view_projection_matrix()
+            .invert()
+            .expect("camera view-projection should be invertible");
+        let unproject = |depth: f32| {
+            let p = inverse * Vector4::new(x, y, depth, 1.0);
+            Point3::from_homogeneous(p)
+        };
+        // Any point further along works for the direction. Reverse-Z puts
+        // the near plane at 1 and the far plane at infinity, out of reach.
+        let near_depth = if camera.projection().is_reverse_z() {
+            1.0
+        } else {
+            0.0
+        };
+        let near = unproject(near_depth);
+        let further = unproject(0.5);
+        Self::new(near, (further - near).normalize())
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// The same ray in the space `matrix` maps to. Distances along the
+    /// result match those along `self`, even if `matrix` scales.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        Self::new(
+            matrix.transform_point(self.origin),
+            matrix.transform_vector(self.direction),
+        )
+    }
+
+    /// Where the ray enters and leaves `aabb`, if it hits it before
+    /// `max_distance`.
+    fn intersect_aabb(
+        &self,
+        aabb: &Aabb,
+        inverse_direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let t0 = (aabb.min[axis] - self.origin[axis]) * inverse_direction[axis];
+            let t1 = (aabb.max[axis] - self.origin[axis]) * inverse_direction[axis];
+            // NaN from 0 * inf leaves the bounds alone
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        (near <= far).then_some(near)
+    }
+
+    /// Möller–Trumbore, hitting both sides of the triangle. Returns the
+    /// distance and the weights of `b` and `c`.
+    fn intersect_triangle(&self, [a, b, c]: &[Point3<f32>; 3]) -> Option<(f32, f32, f32)> {
+        let ab = b - a;
+        let ac = c - a;
+        let p = self.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < f32::EPSILON * ab.magnitude() * ac.magnitude() * self.direction.magnitude() {
+            // Parallel to the triangle, or the triangle is degenerate
+            return None;
+        }
+        let inverse_det = 1.0 / det;
+        let ao = self.origin - a;
+        let u = ao.dot(p) * inverse_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = ao.cross(ab);
+        let v = self.direction.dot(q) * inverse_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = ac.dot(q) * inverse_det;
+        (t >= 0.0).then_some((t, u, v))
+    }
+}
+
+/// Where a ray hit a [`Bvh`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleHit {
+    /// Index of the triangle, the `i` in indices `3i..3i + 3`
+    pub triangle: usize,
+    /// Distance along the ray
+    pub distance: f32,
+    /// Weights of the triangle's three vertices at the hit
+    pub barycentrics: [f32; 3],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    /// First child for inner nodes, which have their children next to each
+    /// other, or first triangle for leaves
+    first: u32,
+    /// Number of triangles, 0 for inner nodes
+    count: u32,
+}
+
+/// Bounding volume hierarchy over a mesh's triangles, for ray casts that
+/// only test the few triangles near the ray.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Triangle corners, ordered so every leaf's triangles are contiguous
+    triangles: Vec<[Point3<f32>; 3]>,
+    /// Original index of each of `triangles`
+    ids: Vec<u32>,
+}
+
+impl Bvh {
+    /// Most triangles in a leaf
+    const LEAF_SIZE: usize = 4;
+
+    /// Builds the hierarchy for the triangle list `indices` into `positions`.
+    pub fn new(positions: &[Point3<f32>], indices: &[u32]) -> Self {
+        let triangles: Vec<[Point3<f32>; 3]> = indices
+            .chunks_exact(3)
+            .map(|t| [0, 1, 2].map(|corner| positions[t[corner] as usize]))
+            .collect();
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            triangles: Vec::new(),
+            ids: (0..triangles.len() as u32).collect(),
+        };
+        if triangles.is_empty() {
+            return bvh;
+        }
+
+        let centroids: Vec<Point3<f32>> = triangles
+            .iter()
+            .map(|[a, b, c]| Point3::centroid(&[*a, *b, *c]))
+            .collect();
+        bvh.nodes.push(Node {
+            bounds: Aabb::EMPTY,
+            first: 0,
+            count: 0,
+        });
+        let mut ids = std::mem::take(&mut bvh.ids);
+        bvh.split(0, 0, &mut ids, &triangles, &centroids);
+        bvh.triangles = ids.iter().map(|&i| triangles[i as usize]).collect();
+        bvh.ids = ids;
+        bvh
+    }
+
+    /// Fills in `node` for the triangles `ids`, which start at `start`,
+    /// splitting them at the median along their longest axis until they're
+    /// small enough for a leaf.
+    fn split(
+        &mut self,
+        node: usize,
+        start: usize,
+        ids: &mut [u32],
+        triangles: &[[Point3<f32>; 3]],
+        centroids: &[Point3<f32>],
+    ) {
+        let bounds = Aabb::from_points(ids.iter().flat_map(|&i| triangles[i as usize]));
+        let centroid_bounds = Aabb::from_points(ids.iter().map(|&i| centroids[i as usize]));
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        if ids.len() <= Self::LEAF_SIZE || extent[axis] <= 0.0 {
+            self.nodes[node] = Node {
+                bounds,
+                first: start as u32,
+                count: ids.len() as u32,
+            };
+            return;
+        }
+
+        let mid = ids.len() / 2;
+        ids.select_nth_unstable_by(mid, |&a, &b| {
+            centroids[a as usize][axis].total_cmp(&centroids[b as usize][axis])
+        });
+        let first = self.nodes.len();
+        let placeholder = Node {
+            bounds: Aabb::EMPTY,
+            first: 0,
+            count: 0,
+        };
+        self.nodes.extend([placeholder, placeholder]);
+        self.nodes[node] = Node {
+            bounds,
+            first: first as u32,
+            count: 0,
+        };
+        let (left, right) = ids.split_at_mut(mid);
+        self.split(first, start, left, triangles, centroids);
+        self.split(first + 1, start + mid, right, triangles, centroids);
+    }
+
+    /// Bounds of all triangles.
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| node.bounds)
+    }
+
+    /// The nearest triangle `ray` hits before `max_distance`.
+    pub fn intersect(&self, ray: &Ray, max_distance: f32) -> Option<TriangleHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = ray.direction.map(|d| 1.0 / d);
+        let mut nearest: Option<TriangleHit> = None;
+        let mut max_distance = max_distance;
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if ray
+                .intersect_aabb(&node.bounds, inverse_direction, max_distance)
+                .is_none()
+            {
+                continue;
+            }
+            if node.count == 0 {
+                // Visit the nearer child first, so the other can often be
+                // skipped once something closer was hit
+                let (a, b) = (node.first, node.first + 1);
+                let distance = |i: u32| {
+                    ray.intersect_aabb(
+                        &self.nodes[i as usize].bounds,
+                        inverse_direction,
+                        max_distance,
+                    )
+                    .unwrap_or(f32::INFINITY)
+                };
+                if distance(a) <= distance(b) {
+                    stack.extend([b, a]);
+                } else {
+                    stack.extend([a, b]);
+                }
+                continue;
+            }
+            let range = node.first as usize..(node.first + node.count) as usize;
+            for (triangle, &id) in self.triangles[range.clone()].iter().zip(&self.ids[range]) {
+                if let Some((distance, u, v)) = ray.intersect_triangle(triangle) {
+                    if distance < max_distance {
+                        max_distance = distance;
+                        nearest = Some(TriangleHit {
+                            triangle: id as usize,
+                            distance,
+                            barycentrics: [1.0 - u - v, u, v],
+                        });
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+/// What a ray hit in a scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    /// Index into the models searched
+    pub model: usize,
+    /// Index into the model's `instances`
+    pub instance: usize,
+    /// Index into the model's `meshes`
+    pub mesh: usize,
+    /// Index of the triangle in the mesh, the `i` in indices `3i..3i + 3`
+    pub triangle: usize,
+    /// Weights of the triangle's three vertices at the hit
+    pub barycentrics: [f32; 3],
+    /// World space position of the hit
+    pub position: Point3<f32>,
+    /// Distance along the ray
+    pub distance: f32,
+}
+
+/// Casts `ray` against every instance of every mesh of `models` and returns
+/// the nearest hit.
+pub fn pick(models: &[Model], ray: &Ray) -> Option<Hit> {
+    let mut nearest: Option<Hit> = None;
+    for (model_index, model) in models.iter().enumerate() {
+        for (instance_index, instance) in model.instances.iter().enumerate() {
+            let Some(inverse) = instance.model_matrix().invert() else {
+                // Scaled flat, nothing to hit
+                continue;
+            };
+            let local = ray.transform(&inverse);
+            for (mesh_index, mesh) in model.meshes.iter().enumerate() {
+                let max_distance = nearest.map_or(f32::INFINITY, |hit| hit.distance);
+                if let Some(hit) = mesh.bvh.intersect(&local, max_distance) {
+                    nearest = Some(Hit {
+                        model: model_index,
+                        instance: instance_index,
+                        mesh: mesh_index,
+                        triangle: hit.triangle,
+                        barycentrics: hit.barycentrics,
+                        position: ray.at(hit.distance),
+                        distance: hit.distance,
+                    });
+                }
+            }
+        }
+    }
+    nearest
+}
+
+/// A model instance found by GPU picking, see [`crate::Renderer::request_pick`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PickId {
+    /// Index into the renderer's `models`
+    pub model: usize,
+    /// Index into the model's `instances`
+    pub instance: usize,
+}
+
+/// A rectangle of pixels, from the top left corner of the view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PickRect {
+    /// The rectangle between two corners in either order, like the start and
+    /// end of a drag, including both.
+    pub fn from_corners(a: (u32, u32), b: (u32, u32)) -> Self {
+        Self {
+            x: a.0.min(b.0),
+            y: a.1.min(b.1),
+            width: a.0.abs_diff(b.0) + 1,
+            height: a.1.abs_diff(b.1) + 1,
+        }
+    }
+
+    /// The part of the rectangle inside a `width` by `height` view.
+    pub fn clamp(&self, width: u32, height: u32) -> Self {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Self {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// Identifies a GPU pick until its result comes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PickRequest(pub(crate) u64);
+
+/// What GPU picking found in a rectangle of the view.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuPick {
+    pub request: PickRequest,
+    /// The rectangle read back, clipped to the view
+    pub rect: PickRect,
+    /// The instance covering each pixel of `rect`, row by row
+    pub pixels: Vec<Option<PickId>>,
+}
+
+impl GpuPick {
+    /// The instance covering a pixel, in view coordinates.
+    pub fn at(&self, x: u32, y: u32) -> Option<PickId> {
+        if !self.rect.contains(x, y) {
+            return None;
+        }
+        let index = (y - self.rect.y) * self.rect.width + x - self.rect.x;
+        self.pixels[index as usize]
+    }
+
+    /// Every instance visible in the rectangle, sorted and without repeats.
+    pub fn objects(&self) -> Vec<PickId> {
+        let mut objects: Vec<PickId> = self.pixels.iter().flatten().copied().collect();
+        objects.sort_unstable();
+        objects.dedup();
+        objects
+    }
+}
@@ -0,0 +1,719 @@
+use std::str::FromStr;
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    antialiasing::{self, Antialiasing, AntialiasingPass},
+    texture::{self, Texture},
+};
+
+/// Format of the offscreen target the scene is drawn into, with room for
+/// colors brighter than the display can show.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// How HDR colors are squeezed into the display's range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapping {
+    /// Clips everything above 1, as if there were no HDR target.
+    #[default]
+    None,
+    /// `c / (1 + c)`: simple, but washes out bright colors.
+    Reinhard,
+    /// A fit of the ACES filmic curve: contrasty, with saturated highlights.
+    Aces,
+    /// Blender's default: desaturates highlights towards white the way film
+    /// does, so bright colors keep their hue.
+    Agx,
+}
+
+impl Tonemapping {
+    pub const ALL: [Self; 4] = [Self::None, Self::Reinhard, Self::Aces, Self::Agx];
+
+    /// The next one in `ALL`, wrapping around, to cycle through at runtime.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&t| t == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+impl FromStr for Tonemapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "reinhard" => Ok(Self::Reinhard),
+            "aces" => Ok(Self::Aces),
+            "agx" => Ok(Self::Agx),
+            _ => bail!("Unknown tonemapping {s:?}, expected none, reinhard, aces or agx"),
+        }
+    }
+}
+
+/// Glow around bright parts of the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    /// Brightness above which pixels glow.
+    pub threshold: f32,
+    /// Fraction of `threshold` below it over which the glow fades in, so
+    /// there's no hard edge.
+    pub knee: f32,
+    /// How much of the glow, averaged over the levels, is added to the
+    /// image.
+    pub intensity: f32,
+    /// Number of half-size steps the glow spreads over, starting at half
+    /// the target's size. More reach further.
+    pub levels: u32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.3,
+            levels: 6,
+        }
+    }
+}
+
+/// What happens to the HDR image between the scene and the display. The
+/// default leaves colors as they were before there was an HDR target, and
+/// costs a single fullscreen pass.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PostSettings {
+    /// In stops: each one doubles the brightness before tonemapping.
+    pub exposure: f32,
+    pub tonemapping: Tonemapping,
+    /// `None` turns bloom off.
+    pub bloom: Option<BloomSettings>,
+    /// Adds noise of under one 8-bit step to the output, hiding banding in
+    /// smooth gradients.
+    pub dither: bool,
+    /// Smooths edges after tonemapping, an alternative to MSAA.
+    pub antialiasing: Antialiasing,
+}
+
+impl PostSettings {
+    /// What the apps start with: ACES tonemapping, bloom and dithering.
+    pub fn filmic() -> Self {
+        Self {
+            exposure: 0.0,
+            tonemapping: Tonemapping::Aces,
+            bloom: Some(BloomSettings::default()),
+            dither: true,
+            antialiasing: Antialiasing::None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    /// Linear, unlike `PostSettings::exposure`
+    exposure: f32,
+    tonemapping: u32,
+    encode_srgb: u32,
+    dither: u32,
+    _padding: u32,
+}
+
+/// A fullscreen pass written in WGSL, see [`PostProcess::add_effect`].
+struct Effect {
+    name: String,
+    pipeline: wgpu::RenderPipeline,
+    params: [f32; 4],
+    buffer: wgpu::Buffer,
+    /// Reading each of the two targets
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// The passes that turn the HDR scene into the final image: user effects in
+/// the order they were added, then bloom, and finally exposure, tonemapping
+/// and encoding for the output format, with optional dithering, and last
+/// anti-aliasing. All of them are render passes, so they run on WebGL2 as
+/// well.
+pub struct PostProcess {
+    settings: PostSettings,
+    output_format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    /// The scene is drawn into the first; effects read one and write the
+    /// other in turns.
+    targets: [Texture; 2],
+    /// From half the target's size down, empty while bloom is off
+    bloom_levels: Vec<Texture>,
+    /// Stands in for the bloom while it's off
+    black: Texture,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    /// Composites into the anti-aliasing's LDR target instead of the output
+    composite_ldr_pipeline: wgpu::RenderPipeline,
+    /// Bloom prefilter and composite passes, reading each of the targets
+    prefilter_bind_groups: [wgpu::BindGroup; 2],
+    composite_bind_groups: [wgpu::BindGroup; 2],
+    /// Reading each bloom level, to downsample or upsample it
+    level_bind_groups: Vec<wgpu::BindGroup>,
+    effect_bind_group_layout: wgpu::BindGroupLayout,
+    effect_pipeline_layout: wgpu::PipelineLayout,
+    effects: Vec<Effect>,
+    antialiasing: AntialiasingPass,
+}
+
+impl PostProcess {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Buffer"),
+            size: std::mem::size_of::<PostUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry(0),
+                texture_entry(1),
+                sampler_entry(2),
+                texture_entry(3),
+            ],
+            label: Some("post_bind_group_layout"),
+        });
+        let effect_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[texture_entry(0), sampler_entry(1), uniform_entry(2)],
+                label: Some("post_effect_bind_group_layout"),
+            });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("post.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let effect_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Post Effect Pipeline Layout"),
+                bind_group_layouts: &[&effect_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = |entry_point, format, blend| {
+            create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                entry_point,
+                format,
+                blend,
+            )
+        };
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        let prefilter_pipeline = pipeline("fs_bloom_prefilter", HDR_FORMAT, None);
+        let downsample_pipeline = pipeline("fs_bloom_downsample", HDR_FORMAT, None);
+        let upsample_pipeline = pipeline("fs_bloom_upsample", HDR_FORMAT, Some(additive));
+        let composite_pipeline = pipeline("fs_composite", output_format, None);
+        let composite_ldr_pipeline = pipeline("fs_composite", antialiasing::LDR_FORMAT, None);
+
+        let targets = [0, 1].map(|i| create_target(device, width, height, &format!("hdr_{i}")));
+        let black = create_target(device, 1, 1, "post_black");
+        let mut post = Self {
+            settings: PostSettings::default(),
+            output_format,
+            width,
+            height,
+            // Filled in by `rebuild`
+            prefilter_bind_groups: [(); 2].map(|_| {
+                create_bind_group(
+                    device,
+                    &bind_group_layout,
+                    &uniform_buffer,
+                    &black,
+                    &sampler,
+                    &black,
+                )
+            }),
+            composite_bind_groups: [(); 2].map(|_| {
+                create_bind_group(
+                    device,
+                    &bind_group_layout,
+                    &uniform_buffer,
+                    &black,
+                    &sampler,
+                    &black,
+                )
+            }),
+            targets,
+            bloom_levels: Vec::new(),
+            black,
+            sampler,
+            uniform_buffer,
+            bind_group_layout,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+            composite_ldr_pipeline,
+            level_bind_groups: Vec::new(),
+            effect_bind_group_layout,
+            effect_pipeline_layout,
+            effects: Vec::new(),
+            antialiasing: AntialiasingPass::new(device, queue, output_format, width, height),
+        };
+        post.rebuild(device);
+        post
+    }
+
+    pub fn settings(&self) -> PostSettings {
+        self.settings
+    }
+
+    /// Switches to new settings, reallocating the bloom levels and the
+    /// anti-aliasing's targets if they changed.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: PostSettings) {
+        let levels = |settings: &PostSettings| settings.bloom.map(|bloom| bloom.levels);
+        let reallocate = levels(&settings) != levels(&self.settings);
+        self.settings = settings;
+        if reallocate {
+            self.rebuild(device);
+        }
+        self.antialiasing.set_mode(device, settings.antialiasing);
+    }
+
+    /// Adds a fullscreen pass to the end of the effects, which run on the
+    /// linear HDR image before bloom and tonemapping. `source` is WGSL
+    /// defining `fs_main`; it's appended to a prelude that declares the input
+    /// image, a sampler, parameters and the vertex shader, see
+    /// `post_effect.wgsl`. Fails if the effect doesn't compile or its name is
+    /// already taken.
+    pub fn add_effect(&mut self, device: &wgpu::Device, name: &str, source: &str) -> Result<()> {
+        ensure!(
+            self.effects.iter().all(|effect| effect.name != name),
+            "There already is a post effect called {name:?}"
+        );
+        let source = format!("{}{source}", include_str!("post_effect.wgsl"));
+
+        // Catch mistakes in the WGSL here rather than panicking later
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline = create_pipeline(
+            device,
+            &self.effect_pipeline_layout,
+            &shader,
+            "fs_main",
+            HDR_FORMAT,
+            None,
+        );
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            bail!("Post effect {name:?} is invalid: {error}");
+        }
+
+        let params = [0.0; 4];
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_groups = self.effect_bind_groups(device, &buffer);
+        self.effects.push(Effect {
+            name: name.to_string(),
+            pipeline,
+            params,
+            buffer,
+            bind_groups,
+        });
+        Ok(())
+    }
+
+    /// Removes the effect called `name`, returning whether there was one.
+    pub fn remove_effect(&mut self, name: &str) -> bool {
+        let count = self.effects.len();
+        self.effects.retain(|effect| effect.name != name);
+        self.effects.len() != count
+    }
+
+    /// Sets the `effect.params` the effect called `name` sees from the next
+    /// [`crate::Renderer::update`] on, returning whether there is one.
+    pub fn set_effect_params(&mut self, name: &str, params: [f32; 4]) -> bool {
+        match self.effects.iter_mut().find(|effect| effect.name == name) {
+            Some(effect) => {
+                effect.params = params;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names of the effects, in the order they run.
+    pub fn effects(&self) -> impl Iterator<Item = &str> {
+        self.effects.iter().map(|effect| effect.name.as_str())
+    }
+
+    /// The HDR target to draw the scene into.
+    pub(crate) fn target(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.targets = [0, 1].map(|i| create_target(device, width, height, &format!("hdr_{i}")));
+        self.rebuild(device);
+        self.antialiasing.resize(device, width, height);
+    }
+
+    /// Uploads the settings and effect parameters.
+    pub(crate) fn update(&self, queue: &wgpu::Queue) {
+        let settings = &self.settings;
+        let bloom = settings.bloom.unwrap_or_default();
+        let uniform = PostUniform {
+            threshold: bloom.threshold,
+            knee: bloom.knee,
+            // Every level adds up to as much light as the prefilter let through,
+            // so the sum is averaged to keep `intensity` apart from `levels`
+            intensity: if settings.bloom.is_some() {
+                bloom.intensity / self.bloom_levels.len() as f32
+            } else {
+                0.0
+            },
+            exposure: settings.exposure.exp2(),
+            tonemapping: settings.tonemapping as u32,
+            // The anti-aliasing reads the encoded image, and decodes it again for
+            // sRGB outputs itself
+            encode_srgb: (self.antialiasing.target().is_some() || !self.output_format.is_srgb())
+                as u32,
+            dither: settings.dither as u32,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        for effect in &self.effects {
+            queue.write_buffer(&effect.buffer, 0, bytemuck::cast_slice(&effect.params));
+        }
+    }
+
+    /// Records every pass, from the scene in [`PostProcess::target`] to
+    /// `output`.
+    pub(crate) fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut current = 0;
+        for effect in &self.effects {
+            fullscreen_pass(
+                encoder,
+                &effect.name,
+                &self.targets[1 - current].view,
+                false,
+                &effect.pipeline,
+                &effect.bind_groups[current],
+            );
+            current = 1 - current;
+        }
+
+        if let Some(first) = self.bloom_levels.first() {
+            fullscreen_pass(
+                encoder,
+                "Bloom Prefilter",
+                &first.view,
+                false,
+                &self.prefilter_pipeline,
+                &self.prefilter_bind_groups[current],
+            );
+            for (level, bind_group) in self.bloom_levels[1..].iter().zip(&self.level_bind_groups) {
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Downsample",
+                    &level.view,
+                    false,
+                    &self.downsample_pipeline,
+                    bind_group,
+                );
+            }
+            // Back up again, each level adding the blurred one below it
+            for (level, bind_group) in self
+                .bloom_levels
+                .iter()
+                .zip(&self.level_bind_groups[1..])
+                .rev()
+            {
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Upsample",
+                    &level.view,
+                    true,
+                    &self.upsample_pipeline,
+                    bind_group,
+                );
+            }
+        }
+
+        let (target, pipeline) = match self.antialiasing.target() {
+            Some(ldr) => (ldr, &self.composite_ldr_pipeline),
+            None => (output, &self.composite_pipeline),
+        };
+        fullscreen_pass(
+            encoder,
+            "Post Composite",
+            target,
+            false,
+            pipeline,
+            &self.composite_bind_groups[current],
+        );
+        self.antialiasing.render(encoder, output);
+    }
+
+    /// Recreates the bloom levels and every bind group after the targets or
+    /// the bloom settings changed.
+    fn rebuild(&mut self, device: &wgpu::Device) {
+        self.bloom_levels = match self.settings.bloom {
+            Some(bloom) => {
+                let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+                let count = bloom
+                    .levels
+                    .clamp(1, texture::mip_level_count(width, height));
+                (0..count)
+                    .map(|level| {
+                        create_target(
+                            device,
+                            (width >> level).max(1),
+                            (height >> level).max(1),
+                            &format!("bloom_{level}"),
+                        )
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        let bind_group = |source: &Texture, bloom: &Texture| {
+            create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                source,
+                &self.sampler,
+                bloom,
+            )
+        };
+        let bloom = self.bloom_levels.first().unwrap_or(&self.black);
+        self.prefilter_bind_groups = [0, 1].map(|i| bind_group(&self.targets[i], &self.black));
+        self.composite_bind_groups = [0, 1].map(|i| bind_group(&self.targets[i], bloom));
+        self.level_bind_groups = self
+            .bloom_levels
+            .iter()
+            .map(|level| bind_group(level, &self.black))
+            .collect();
+        let bind_groups: Vec<_> = self
+            .effects
+            .iter()
+            .map(|effect| self.effect_bind_groups(device, &effect.buffer))
+            .collect();
+        for (effect, bind_groups) in self.effects.iter_mut().zip(bind_groups) {
+            effect.bind_groups = bind_groups;
+        }
+    }
+
+    fn effect_bind_groups(
+        &self,
+        device: &wgpu::Device,
+        buffer: &wgpu::Buffer,
+    ) -> [wgpu::BindGroup; 2] {
+        [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.effect_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&self.targets[i].view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("post_effect_bind_group"),
+            })
+        })
+    }
+}
+
+fn create_target(device: &wgpu::Device, width: u32, height: u32, label: &str) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    source: &Texture,
+    sampler: &wgpu::Sampler,
+    bloom: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&source.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&bloom.view),
+            },
+        ],
+        label: Some("post_bind_group"),
+    })
+}
+
+pub(crate) fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Draws one fullscreen triangle into `target`, adding to what's there if
+/// `load` is set and replacing it otherwise.
+pub(crate) fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: bool,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: if load {
+                    wgpu::LoadOp::Load
+                } else {
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+                },
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
@@ -0,0 +1,185 @@
+// Post-processing of the HDR scene: bloom through a chain of ever smaller
+// textures, then exposure, tonemapping and encoding for the display.
+
+struct Post {
+    // Brightness where bloom starts, and how softly it fades in below that
+    threshold: f32,
+    knee: f32,
+    // How much of the bloom is added to the image
+    intensity: f32,
+    // Linear multiplier applied before tonemapping
+    exposure: f32,
+    // 0 none, 1 Reinhard, 2 ACES, 3 AgX
+    tonemapping: u32,
+    // Whether to encode sRGB in the shader, since the target doesn't
+    encode_srgb: u32,
+    dither: u32,
+    _padding: u32,
+}
+@group(0) @binding(0)
+var<uniform> post: Post;
+@group(0) @binding(1)
+var t_source: texture_2d<f32>;
+@group(0) @binding(2)
+var s_source: sampler;
+// The largest bloom level, or black while bloom is off
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0 to 1 across the target, from the top left
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// 13 bilinear taps in the pattern of Jimenez's "Next Generation Post
+// Processing in Call of Duty", which halves the image without flickering
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(-2.0, -2.0), 0.0).rgb;
+    let b = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(0.0, -2.0), 0.0).rgb;
+    let c = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(2.0, -2.0), 0.0).rgb;
+    let d = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(-2.0, 0.0), 0.0).rgb;
+    let e = textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+    let f = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(2.0, 0.0), 0.0).rgb;
+    let g = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(-2.0, 2.0), 0.0).rgb;
+    let h = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(0.0, 2.0), 0.0).rgb;
+    let i = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(2.0, 2.0), 0.0).rgb;
+    let j = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(-1.0, -1.0), 0.0).rgb;
+    let k = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(1.0, -1.0), 0.0).rgb;
+    let l = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(-1.0, 1.0), 0.0).rgb;
+    let m = textureSampleLevel(t_source, s_source, uv + texel * vec2<f32>(1.0, 1.0), 0.0).rgb;
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+// Keeps what's brighter than the threshold, with a quadratic knee below it
+@fragment
+fn fs_bloom_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = max(post.threshold * post.knee, 1e-5);
+    var soft = clamp(brightness - post.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+    let weight = max(soft, brightness - post.threshold) / max(brightness, 1e-5);
+    return vec4<f32>(color * weight, 1.0);
+}
+
+@fragment
+fn fs_bloom_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// A 3x3 tent filter over the smaller level, added onto the larger one by
+// the pipeline's blending
+@fragment
+fn fs_bloom_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    var sum = textureSampleLevel(t_source, s_source, in.uv, 0.0).rgb * 4.0;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(-1.0, 0.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(1.0, 0.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(0.0, -1.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(0.0, 1.0), 0.0).rgb * 2.0;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(-1.0, -1.0), 0.0).rgb;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(1.0, -1.0), 0.0).rgb;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(-1.0, 1.0), 0.0).rgb;
+    sum += textureSampleLevel(t_source, s_source, in.uv + texel * vec2<f32>(1.0, 1.0), 0.0).rgb;
+    return vec4<f32>(sum / 16.0, 1.0);
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777,
+    );
+    let output = mat3x3<f32>(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602,
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return output * (a / b);
+}
+
+// Troy Sobotka's AgX with Benjamin Wrensch's polynomial fit of its default
+// contrast curve
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var x = clamp(log2(max(inset * color, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    x = (x - min_ev) / (max_ev - min_ev);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    // Back from the curve's display encoding to linear
+    return pow(max(outset * x, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn srgb_encode(linear: vec3<f32>) -> vec3<f32> {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3<f32>(0.0031308));
+}
+
+fn srgb_decode(encoded: vec3<f32>) -> vec3<f32> {
+    let low = encoded / 12.92;
+    let high = pow((encoded + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, encoded <= vec3<f32>(0.04045));
+}
+
+// Jorge Jimenez's interleaved gradient noise, 0 to 1
+fn interleaved_gradient_noise(position: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(position, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// Adds the bloom, exposes, tonemaps and encodes the image for the target
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source = textureSampleLevel(t_source, s_source, in.uv, 0.0);
+    let bloom = textureSampleLevel(t_bloom, s_source, in.uv, 0.0).rgb;
+    var color = (source.rgb + bloom * post.intensity) * post.exposure;
+    switch post.tonemapping {
+        case 1u: { color = reinhard(color); }
+        case 2u: { color = aces(color); }
+        case 3u: { color = agx(color); }
+        default: {}
+    }
+    color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    if post.dither != 0u {
+        // Dithering works on the encoded values the display receives
+        let noise = interleaved_gradient_noise(in.clip_position.xy) - 0.5;
+        let encoded = clamp(srgb_encode(color) + noise / 255.0, vec3<f32>(0.0), vec3<f32>(1.0));
+        color = select(srgb_decode(encoded), encoded, post.encode_srgb != 0u);
+    } else if post.encode_srgb != 0u {
+        color = srgb_encode(color);
+    }
+    return vec4<f32>(color, clamp(source.a, 0.0, 1.0));
+}
@@ -0,0 +1,33 @@
+// Put in front of every post effect registered from Rust. An effect defines
+//
+//     @fragment
+//     fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>
+//
+// and reads the linear HDR image so far from `t_input` at `in.uv`.
+
+struct Effect {
+    // Whatever the effect wants, set with `PostProcess::set_effect_params`
+    params: vec4<f32>,
+}
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(0) @binding(2)
+var<uniform> effect: Effect;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0 to 1 across the image, from the top left
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
@@ -0,0 +1,126 @@
+use cgmath::{Matrix4, Rad};
+
+use crate::OPENGL_TO_WGPU_MATRIX;
+
+/// How a camera's view space maps to wgpu's clip space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Depth goes from 0 at `znear` to 1 at `zfar`.
+    Perspective {
+        fovy: Rad<f32>,
+        aspect: f32,
+        znear: f32,
+        zfar: f32,
+    },
+    /// Parallel projection of the box between the given view space bounds.
+    /// Depth goes from 0 at `znear` to 1 at `zfar`.
+    Orthographic {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        znear: f32,
+        zfar: f32,
+    },
+    /// Perspective with the far plane at infinity and depth reversed: 1 at
+    /// `znear`, approaching 0 far away. Spreads float depth precision evenly
+    /// over the scene, but needs a depth buffer cleared to 0 and a `Greater`
+    /// depth test.
+    ReverseZ {
+        fovy: Rad<f32>,
+        aspect: f32,
+        znear: f32,
+    },
+}
+
+impl Projection {
+    /// An orthographic projection of a `width` by `height` box centered on
+    /// the view axis.
+    pub fn orthographic(width: f32, height: f32, znear: f32, zfar: f32) -> Self {
+        Self::Orthographic {
+            left: -width / 2.0,
+            right: width / 2.0,
+            bottom: -height / 2.0,
+            top: height / 2.0,
+            znear,
+            zfar,
+        }
+    }
+
+    /// The projection matrix, including the conversion from OpenGL's clip
+    /// space that cgmath builds for to wgpu's.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        match *self {
+            Self::Perspective {
+                fovy,
+                aspect,
+                znear,
+                zfar,
+            } => OPENGL_TO_WGPU_MATRIX * cgmath::perspective(fovy, aspect, znear, zfar),
+            Self::Orthographic {
+                left,
+                right,
+                bottom,
+                top,
+                znear,
+                zfar,
+            } => OPENGL_TO_WGPU_MATRIX * cgmath::ortho(left, right, bottom, top, znear, zfar),
+            Self::ReverseZ {
+                fovy,
+                aspect,
+                znear,
+            } => {
+                // Built directly for wgpu's 0..1 depth range: clip z is
+                // always `znear` and w is the distance in front of the
+                // camera, so depth is znear / distance
+                let f = 1.0 / (fovy / 2.0).0.tan();
+                #[rustfmt::skip]
+                let matrix = Matrix4::new(
+                    f / aspect, 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, znear, 0.0,
+                );
+                matrix
+            }
+        }
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        matches!(self, Self::Orthographic { .. })
+    }
+
+    /// Whether depth decreases with distance, see [`Projection::ReverseZ`].
+    pub fn is_reverse_z(&self) -> bool {
+        matches!(self, Self::ReverseZ { .. })
+    }
+
+    pub fn znear(&self) -> f32 {
+        match *self {
+            Self::Perspective { znear, .. }
+            | Self::Orthographic { znear, .. }
+            | Self::ReverseZ { znear, .. } => znear,
+        }
+    }
+
+    /// Distance to the far plane, infinite for [`Projection::ReverseZ`].
+    pub fn zfar(&self) -> f32 {
+        match *self {
+            Self::Perspective { zfar, .. } | Self::Orthographic { zfar, .. } => zfar,
+            Self::ReverseZ { .. } => f32::INFINITY,
+        }
+    }
+}
+
+/// Which kind of [`Projection`] a [`crate::Camera`] uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProjectionMode {
+    #[default]
+    Perspective,
+    /// Frames the plane through the camera's target exactly like
+    /// `Perspective` would, so switching between the two keeps the target
+    /// the same size on screen.
+    Orthographic,
+    /// See [`Projection::ReverseZ`]. The camera's `zfar` is ignored.
+    ReverseZ,
+}
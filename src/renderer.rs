@@ -0,0 +1,727 @@
+use std::path::{Path, PathBuf};
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    ibl::{self, Environment, IblSettings},
+    id_buffer::IdBuffer,
+    instance::InstanceRaw,
+    light::{Light, Lights},
+    model::{Aabb, DrawModel, Material, MaterialLayout, MaterialMaps, MaterialParams, Mesh, Model},
+    picking::{self, GpuPick, Hit, PickRect, PickRequest, Ray},
+    post::{self, PostProcess},
+    scene::Scene,
+    shadow::{ShadowMaps, ShadowSettings},
+    skybox::{Skybox, SkyboxPass},
+    texture, Camera, CameraUniform, ProjectionMode, Vertex, INDICES, VERTICES,
+};
+
+/// How the main pass tests and clears depth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthSettings {
+    pub format: wgpu::TextureFormat,
+    pub compare: wgpu::CompareFunction,
+    /// Value the depth buffer is cleared to, the far plane for `compare`.
+    pub clear_value: f32,
+}
+
+impl Default for DepthSettings {
+    fn default() -> Self {
+        Self::reverse_z()
+    }
+}
+
+impl DepthSettings {
+    /// Depth grows from 0 at the near plane to 1 at the far plane.
+    pub fn standard() -> Self {
+        Self {
+            format: texture::Texture::DEPTH_FORMAT,
+            compare: wgpu::CompareFunction::Less,
+            clear_value: 1.0,
+        }
+    }
+
+    /// Depth shrinks from 1 at the near plane towards 0 far away, for
+    /// `ProjectionMode::ReverseZ` cameras. Together with a float format this
+    /// keeps depth precise from centimetres to kilometres, so it's the
+    /// default.
+    pub fn reverse_z() -> Self {
+        Self {
+            format: wgpu::TextureFormat::Depth32Float,
+            compare: wgpu::CompareFunction::Greater,
+            clear_value: 0.0,
+        }
+    }
+
+    /// Whether nearer fragments have greater depth.
+    pub fn is_reverse_z(&self) -> bool {
+        use wgpu::CompareFunction::*;
+        matches!(self.compare, Greater | GreaterEqual)
+    }
+
+    /// The same settings for depth running the other way.
+    pub fn reversed(self) -> Self {
+        use wgpu::CompareFunction::*;
+        Self {
+            compare: match self.compare {
+                Less => Greater,
+                LessEqual => GreaterEqual,
+                Greater => Less,
+                GreaterEqual => LessEqual,
+                other => other,
+            },
+            clear_value: 1.0 - self.clear_value,
+            ..self
+        }
+    }
+}
+
+/// Everything needed to draw the scene into a color target of a given format,
+/// by way of an HDR target and the post-processing in `post`. Owns no surface, so it can be shared by the windowed `State` and the
+/// offscreen `headless::Headless` renderer.
+pub struct Renderer {
+    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    depth: DepthSettings,
+    depth_texture: texture::Texture,
+    sample_count: u32,
+    /// Drawn into and resolved into the target while `sample_count` is above 1
+    msaa_target: Option<texture::Texture>,
+    width: u32,
+    height: u32,
+    /// Needed to create materials for models added to `models`
+    pub material_layout: MaterialLayout,
+    /// Everything drawn each frame. Starts out holding the textured pentagon.
+    pub models: Vec<Model>,
+    pub camera: Camera,
+    /// Starts out with some ambient light and a single directional light
+    pub lights: Lights,
+    shadows: ShadowMaps,
+    sky: SkyboxPass,
+    /// Background wherever the scene and the skybox leave the target empty
+    pub clear_color: wgpu::Color,
+    /// Turns the HDR scene into the final image
+    pub post: PostProcess,
+    /// Quality of the image-based lighting [`Renderer::load_skybox`] sets up
+    pub ibl_settings: IblSettings,
+    /// Where [`Renderer::load_skybox`] caches image-based lighting, so it's
+    /// only computed the first time an environment is used. `None` by
+    /// default, which computes it every time.
+    pub ibl_cache_dir: Option<PathBuf>,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    /// Present while GPU picking is enabled
+    id_buffer: Option<IdBuffer>,
+}
+
+impl Renderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let diffuse_bytes = include_bytes!("../assets/happy-tree.png");
+        let diffuse_texture = texture::Texture::from_bytes(
+            device,
+            queue,
+            diffuse_bytes,
+            "../assets/happy-tree.png",
+            true,
+        )
+        .unwrap();
+
+        let material_layout = MaterialLayout::new(device, queue);
+
+        let camera = Camera {
+            eye: (0.0, 1.0, 2.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: width as f32 / height as f32,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            projection: ProjectionMode::ReverseZ,
+        };
+
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("camera_bind_group_layout"),
+            });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+
+        let mut lights = Lights::new(device);
+        lights.set_ambient([0.15; 3]);
+        lights.add(Light::Directional {
+            direction: cgmath::Vector3::new(-0.3, -0.6, -1.0),
+            color: [1.0; 3],
+            // Roughly π, so lit white surfaces come out close to white
+            intensity: 3.0,
+            cast_shadows: true,
+        });
+        let shadows = ShadowMaps::new(device, ShadowSettings::default());
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &material_layout.bind_group_layout,
+                    &camera_bind_group_layout,
+                    &lights.bind_group_layout,
+                    &shadows.bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let depth = DepthSettings::default();
+        let sample_count = 1;
+        let (depth_texture, msaa_target) =
+            create_targets(device, &depth, sample_count, width, height);
+        let render_pipeline = create_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            &depth,
+            sample_count,
+        );
+        let sky = SkyboxPass::new(device, post::HDR_FORMAT, &depth, sample_count);
+
+        let pentagon = Model::new(
+            device,
+            vec![Mesh::new(device, "Pentagon", VERTICES, INDICES, 0)],
+            vec![Material::new(
+                device,
+                "diffuse_bind_group",
+                MaterialMaps {
+                    base_color: Some(diffuse_texture),
+                    ..Default::default()
+                },
+                MaterialParams::default(),
+                &material_layout,
+            )],
+        );
+
+        Self {
+            render_pipeline,
+            render_pipeline_layout,
+            shader,
+            depth,
+            depth_texture,
+            sample_count,
+            msaa_target,
+            width,
+            height,
+            material_layout,
+            models: vec![pentagon],
+            camera,
+            lights,
+            shadows,
+            sky,
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            post: PostProcess::new(device, queue, format, width, height),
+            ibl_settings: IblSettings::default(),
+            ibl_cache_dir: None,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group_layout,
+            camera_bind_group,
+            id_buffer: None,
+        }
+    }
+
+    /// Loads a Wavefront `.obj` model and adds it to the scene.
+    pub fn load_obj(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let model = Model::load_obj(device, queue, &self.material_layout, path)?;
+        self.models.push(model);
+        Ok(())
+    }
+
+    /// Loads a `.gltf`/`.glb` scene and adds it to the models drawn each frame.
+    pub fn load_gltf(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let scene = Scene::load_gltf(device, queue, &self.material_layout, path)?;
+        self.models.push(scene.model);
+        Ok(())
+    }
+
+    /// Replaces the scene with the given models, loaded as glTF or OBJ
+    /// depending on their extension. An empty list keeps the current scene.
+    pub fn load_models(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        paths: &[PathBuf],
+    ) -> anyhow::Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.models.clear();
+        for path in paths {
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("gltf" | "glb") => self.load_gltf(device, queue, path)?,
+                _ => self.load_obj(device, queue, path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a skybox from a single equirectangular panorama or six cube map
+    /// faces, see [`Skybox`], and lights the scene with it. An empty list
+    /// keeps the current one.
+    pub fn load_skybox(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        paths: &[PathBuf],
+    ) -> anyhow::Result<()> {
+        let skybox = match paths {
+            [] => return Ok(()),
+            [panorama] => Skybox::load_equirectangular(device, queue, panorama)?,
+            [_, _, _, _, _, _] => {
+                Skybox::load_faces(device, queue, <&[_; 6]>::try_from(paths).unwrap())?
+            }
+            _ => anyhow::bail!(
+                "A skybox needs one panorama or six faces, not {} images",
+                paths.len()
+            ),
+        };
+        let environment = match &self.ibl_cache_dir {
+            Some(dir) => {
+                let sources = paths
+                    .iter()
+                    .map(std::fs::read)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let sources: Vec<&[u8]> = sources.iter().map(Vec::as_slice).collect();
+                let key = ibl::cache_key(&sources, self.ibl_settings);
+                Environment::load_or_compute(
+                    device,
+                    queue,
+                    &skybox.texture,
+                    self.ibl_settings,
+                    dir.join(format!("{key:016x}.ibl")),
+                )
+            }
+            None => Environment::compute(device, queue, &skybox.texture, self.ibl_settings),
+        };
+        self.set_skybox(device, Some(skybox));
+        self.set_environment(device, Some(environment));
+        Ok(())
+    }
+
+    /// Lights the scene with `environment` instead of the flat ambient light
+    /// of `lights`. It needn't match the skybox.
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: Option<Environment>) {
+        self.lights.set_environment(device, environment);
+    }
+
+    pub fn skybox(&self) -> Option<&Skybox> {
+        self.sky.skybox()
+    }
+
+    /// Draws `skybox` behind the scene, or `clear_color` if it's `None`.
+    pub fn set_skybox(&mut self, device: &wgpu::Device, skybox: Option<Skybox>) {
+        self.sky.set_skybox(device, skybox);
+    }
+
+    pub fn depth_settings(&self) -> DepthSettings {
+        self.depth
+    }
+
+    /// Switches the depth format and compare function, rebuilding the depth
+    /// texture and the pipeline. The direction of the depth test still
+    /// follows the camera, see [`Renderer::update`].
+    pub fn set_depth_settings(&mut self, device: &wgpu::Device, depth: DepthSettings) {
+        self.depth = depth;
+        self.rebuild(device);
+        if let Some(id_buffer) = &mut self.id_buffer {
+            id_buffer.set_depth_settings(device, &self.depth);
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Switches multisample anti-aliasing to `sample_count` samples per
+    /// pixel, or off with 1, rebuilding the targets and pipelines. The count
+    /// must be one of [`supported_sample_counts`] for the color and depth
+    /// formats.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.sample_count = sample_count;
+        self.rebuild(device);
+    }
+
+    /// Recreates the targets and pipelines after a change to the depth
+    /// settings or the sample count.
+    fn rebuild(&mut self, device: &wgpu::Device) {
+        (self.depth_texture, self.msaa_target) = create_targets(
+            device,
+            &self.depth,
+            self.sample_count,
+            self.width,
+            self.height,
+        );
+        self.render_pipeline = create_render_pipeline(
+            device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            &self.depth,
+            self.sample_count,
+        );
+        self.sky.set_targets(device, &self.depth, self.sample_count);
+    }
+
+    /// The nearest object under a point on screen, in pixels from the top
+    /// left corner like `WindowEvent::CursorMoved` positions.
+    pub fn pick(&self, x: f32, y: f32) -> Option<Hit> {
+        let ray = Ray::from_screen(&self.camera, x, y, self.width, self.height);
+        picking::pick(&self.models, &ray)
+    }
+
+    pub fn gpu_picking(&self) -> bool {
+        self.id_buffer.is_some()
+    }
+
+    /// Turns the GPU picking pass on or off. While it's off,
+    /// [`Renderer::request_pick`] has nothing to answer with; turning it off
+    /// drops any picks still in flight.
+    pub fn set_gpu_picking(&mut self, device: &wgpu::Device, enabled: bool) {
+        if enabled == self.gpu_picking() {
+            return;
+        }
+        self.id_buffer = enabled.then(|| {
+            IdBuffer::new(
+                device,
+                &self.camera_bind_group_layout,
+                &self.depth,
+                self.width,
+                self.height,
+            )
+        });
+    }
+
+    /// Asks for the instance under a pixel, counted from the top left corner.
+    /// Unlike [`Renderer::pick`] this scales to dense scenes: the next
+    /// [`Renderer::update`] renders instance IDs on the GPU and starts copying
+    /// the pixel back, and [`Renderer::poll_picks`] hands out the result once
+    /// it has arrived. `None` if GPU picking is off.
+    pub fn request_pick(&mut self, x: u32, y: u32) -> Option<PickRequest> {
+        self.request_pick_rect(PickRect {
+            x,
+            y,
+            width: 1,
+            height: 1,
+        })
+    }
+
+    /// Like [`Renderer::request_pick`] for every pixel in `rect`, for box
+    /// selection.
+    pub fn request_pick_rect(&mut self, rect: PickRect) -> Option<PickRequest> {
+        Some(self.id_buffer.as_mut()?.request(rect))
+    }
+
+    /// GPU picks that have finished since the last call, oldest first.
+    /// Doesn't wait for the GPU.
+    pub fn poll_picks(&mut self, device: &wgpu::Device) -> Vec<GpuPick> {
+        match &mut self.id_buffer {
+            Some(id_buffer) => id_buffer.poll(device),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn shadow_settings(&self) -> ShadowSettings {
+        self.shadows.settings()
+    }
+
+    pub fn set_shadow_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        self.shadows.set_settings(device, settings);
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.camera.aspect = width as f32 / height as f32;
+        (self.depth_texture, self.msaa_target) =
+            create_targets(device, &self.depth, self.sample_count, width, height);
+        self.post.resize(device, width, height);
+        if let Some(id_buffer) = &mut self.id_buffer {
+            id_buffer.resize(device, &self.depth, width, height);
+        }
+    }
+
+    /// Advances the simulation by one fixed step of `dt` seconds, moving
+    /// the instances of every model by their velocities.
+    pub fn step(&mut self, dt: f32) {
+        for model in &mut self.models {
+            model.instances.step(dt);
+        }
+    }
+
+    /// Uploads the current `camera` and any changed instances and lights to
+    /// the GPU, and refits the shadow maps to the scene. Also flips the depth
+    /// test if the camera switched to or from a reverse-Z projection, and
+    /// submits the ID pass for any GPU picks requested since the last update.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.camera.projection().is_reverse_z() != self.depth.is_reverse_z() {
+            self.set_depth_settings(device, self.depth.reversed());
+        }
+        self.lights.sync(queue);
+        for model in &mut self.models {
+            model.instances.sync(device, queue);
+        }
+        let bounds = self
+            .models
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, model| bounds.union(model.bounds()));
+        self.shadows
+            .update(device, queue, &self.lights, bounds, &self.camera);
+        self.camera_uniform.update_view_proj(&self.camera);
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        self.sky.update(queue, &self.camera);
+        self.post.update(queue);
+        if let Some(id_buffer) = &mut self.id_buffer {
+            id_buffer.render(
+                device,
+                queue,
+                &self.models,
+                &self.camera_bind_group,
+                &self.depth,
+            );
+        }
+    }
+
+    /// Records the shadow passes, the main render pass and post-processing
+    /// into `encoder`, drawing into `view`. The skybox goes last in the main
+    /// pass, filling in around the scene. With multisampling the samples are
+    /// resolved into the HDR target before post-processing.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.shadows.render(encoder, &self.models);
+
+        let target = self.post.target();
+        let color_attachment = match &self.msaa_target {
+            // Only the resolved pixels are needed afterwards
+            Some(msaa_target) => wgpu::RenderPassColorAttachment {
+                view: &msaa_target.view,
+                resolve_target: Some(target),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store: wgpu::StoreOp::Discard,
+                },
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store: wgpu::StoreOp::Store,
+                },
+            },
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(color_attachment)],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth.clear_value),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(2, &self.lights.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.shadows.bind_group, &[]);
+        for model in &self.models {
+            render_pass.draw_model(model, &self.camera_bind_group);
+        }
+        self.sky.render(&mut render_pass);
+        drop(render_pass);
+
+        self.post.render(encoder, view);
+    }
+}
+
+/// Features used where the adapter has them: every MSAA sample count it
+/// supports, and 16-bit textures loaded without loss.
+pub(crate) const OPTIONAL_FEATURES: wgpu::Features =
+    wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+        .union(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM);
+
+/// Sample counts that every one of `formats` can be rendered with on
+/// `adapter`, for [`Renderer::set_sample_count`]. Always starts with 1.
+/// Counts other than 1 and 4 need the device to have been created with
+/// `Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`.
+pub fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    formats: &[wgpu::TextureFormat],
+) -> Vec<u32> {
+    let adapter_specific = device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    [1, 2, 4, 8, 16]
+        .into_iter()
+        .filter(|&count| {
+            formats.iter().all(|&format| {
+                let features = if adapter_specific {
+                    adapter.get_texture_format_features(format)
+                } else {
+                    format.guaranteed_format_features(device.features())
+                };
+                features.flags.sample_count_supported(count)
+            })
+        })
+        .collect()
+}
+
+/// The depth buffer, and the HDR target to resolve from if multisampling.
+fn create_targets(
+    device: &wgpu::Device,
+    depth: &DepthSettings,
+    sample_count: u32,
+    width: u32,
+    height: u32,
+) -> (texture::Texture, Option<texture::Texture>) {
+    let depth_texture = texture::Texture::create_depth_texture(
+        device,
+        width,
+        height,
+        depth.format,
+        sample_count,
+        "depth_texture",
+    );
+    let msaa_target = (sample_count > 1).then(|| {
+        texture::Texture::create_multisampled(
+            device,
+            width,
+            height,
+            post::HDR_FORMAT,
+            sample_count,
+            "msaa_target",
+        )
+    });
+    (depth_texture, msaa_target)
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    depth: &DepthSettings,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: post::HDR_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent::REPLACE,
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
+            // or Features::POLYGON_MODE_POINT
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth.format,
+            depth_write_enabled: true,
+            depth_compare: depth.compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        // If the pipeline will be used with a multiview render pass, this
+        // indicates how many array layers the attachments will have.
+        multiview: None,
+        // Useful for optimizing shader compilation on Android
+        cache: None,
+    })
+}
@@ -0,0 +1,320 @@
+use std::path::Path;
+
+use anyhow::*;
+use base64::Engine;
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3};
+
+use crate::{
+    model::{Material, MaterialLayout, MaterialMaps, MaterialParams, Mesh, Model},
+    texture, Vertex,
+};
+
+pub struct Node {
+    pub name: Option<String>,
+    /// Transform relative to the parent node
+    pub transform: Matrix4<f32>,
+    /// Transform relative to the scene root
+    pub world_transform: Matrix4<f32>,
+    pub children: Vec<usize>,
+    /// Indices into `Scene::model.meshes`, one per primitive of the node's mesh
+    pub meshes: Vec<usize>,
+}
+
+/// A glTF scene flattened into a drawable `Model`.
+///
+/// Primitives are baked into world space with the transform of the node that
+/// references them, so a mesh used by several nodes is uploaded once per node.
+/// The node tree is kept alongside for inspection.
+pub struct Scene {
+    pub nodes: Vec<Node>,
+    pub roots: Vec<usize>,
+    pub model: Model,
+}
+
+impl Scene {
+    /// Loads a `.gltf` (with external or data URI buffers) or `.glb` file.
+    pub fn load_gltf(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &MaterialLayout,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let gltf::Gltf { document, mut blob } = gltf::Gltf::from_slice(&bytes)?;
+
+        let buffers = document
+            .buffers()
+            .map(|buffer| match buffer.source() {
+                gltf::buffer::Source::Bin => blob.take().context("Missing binary glTF chunk"),
+                gltf::buffer::Source::Uri(uri) => read_uri(dir, uri),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let images = document
+            .images()
+            .map(|image| match image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    let start = view.offset();
+                    Ok(buffers[view.buffer().index()][start..start + view.length()].to_vec())
+                }
+                gltf::image::Source::Uri { uri, .. } => read_uri(dir, uri),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let load_image = |texture: gltf::Texture, srgb| {
+            let image = texture.source();
+            let label = image
+                .name()
+                .map_or_else(|| format!("image {}", image.index()), String::from);
+            let options = sampler_options(&texture.sampler(), srgb);
+            texture::Texture::from_bytes_with(
+                device,
+                queue,
+                &images[image.index()],
+                &label,
+                options,
+            )
+        };
+
+        let mut materials = Vec::with_capacity(document.materials().len() + 1);
+        for m in document.materials() {
+            let pbr = m.pbr_metallic_roughness();
+            let maps = MaterialMaps {
+                base_color: pbr
+                    .base_color_texture()
+                    .map(|info| load_image(info.texture(), true))
+                    .transpose()?,
+                metallic_roughness: pbr
+                    .metallic_roughness_texture()
+                    .map(|info| load_image(info.texture(), false))
+                    .transpose()?,
+                normal: m
+                    .normal_texture()
+                    .map(|info| load_image(info.texture(), false))
+                    .transpose()?,
+                occlusion: m
+                    .occlusion_texture()
+                    .map(|info| load_image(info.texture(), false))
+                    .transpose()?,
+                emissive: m
+                    .emissive_texture()
+                    .map(|info| load_image(info.texture(), true))
+                    .transpose()?,
+            };
+            let params = MaterialParams {
+                base_color: pbr.base_color_factor(),
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor(),
+                normal_scale: m.normal_texture().map_or(1.0, |n| n.scale()),
+                occlusion_strength: m.occlusion_texture().map_or(1.0, |o| o.strength()),
+                emissive: m.emissive_factor(),
+            };
+            let name = m.name().unwrap_or("material");
+            materials.push(Material::new(device, name, maps, params, layout));
+        }
+        // Used by primitives without a material, like the glTF default material
+        let default_material = materials.len();
+        let params = MaterialParams {
+            metallic: 1.0,
+            ..Default::default()
+        };
+        materials.push(Material::new(
+            device,
+            "default",
+            MaterialMaps::default(),
+            params,
+            layout,
+        ));
+
+        let gltf_nodes: Vec<gltf::Node> = document.nodes().collect();
+        let mut nodes: Vec<Node> = gltf_nodes
+            .iter()
+            .map(|node| Node {
+                name: node.name().map(String::from),
+                transform: node.transform().matrix().into(),
+                world_transform: Matrix4::identity(),
+                children: node.children().map(|child| child.index()).collect(),
+                meshes: Vec::new(),
+            })
+            .collect();
+
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .context("glTF file has no scenes")?;
+        let roots: Vec<usize> = scene.nodes().map(|node| node.index()).collect();
+
+        let mut meshes = Vec::new();
+        let mut stack: Vec<(usize, Matrix4<f32>)> = roots
+            .iter()
+            .map(|&root| (root, Matrix4::identity()))
+            .collect();
+        while let Some((index, parent_transform)) = stack.pop() {
+            let world_transform = parent_transform * nodes[index].transform;
+            nodes[index].world_transform = world_transform;
+            stack.extend(
+                nodes[index]
+                    .children
+                    .iter()
+                    .map(|&child| (child, world_transform)),
+            );
+
+            let Some(mesh) = gltf_nodes[index].mesh() else {
+                continue;
+            };
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!("Skipping non-triangle primitive in {:?}", mesh.name());
+                    continue;
+                }
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let (vertices, indices) = primitive_vertices(&reader, world_transform)?;
+                let material = primitive.material().index().unwrap_or(default_material);
+                let name = format!("{}/{}", mesh.name().unwrap_or("mesh"), primitive.index());
+                nodes[index].meshes.push(meshes.len());
+                meshes.push(Mesh::new(device, &name, &vertices, &indices, material));
+            }
+        }
+
+        Ok(Self {
+            nodes,
+            roots,
+            model: Model::new(device, meshes, materials),
+        })
+    }
+}
+
+/// Reads a buffer or image URI, either a base64 `data:` URI or a path
+/// relative to the glTF file.
+fn read_uri(dir: &Path, uri: &str) -> Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .context("Only base64 data URIs are supported")?;
+        return Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?);
+    }
+    let path = dir.join(uri);
+    std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Reads a primitive's attributes, transformed by `transform`. Normals use the
+/// inverse transpose so non-uniform scales keep them perpendicular.
+/// Primitives without normals are flat shaded, as glTF asks, so their
+/// triangles get vertices of their own.
+fn primitive_vertices<'a, 's, F>(
+    reader: &gltf::mesh::Reader<'a, 's, F>,
+    transform: Matrix4<f32>,
+) -> Result<(Vec<Vertex>, Vec<u32>)>
+where
+    F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+{
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .context("Primitive has no positions")?
+        .collect();
+    let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+    let tex_coords: Vec<[f32; 2]> = reader.read_tex_coords(0).map_or_else(
+        || vec![[0.0; 2]; positions.len()],
+        |t| t.into_f32().collect(),
+    );
+    let mut indices: Vec<u32> = reader.read_indices().map_or_else(
+        || (0..positions.len() as u32).collect(),
+        |i| i.into_u32().collect(),
+    );
+    // Mirroring turns the triangles clockwise, which would cull their fronts
+    if transform.determinant() < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    let linear = Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+    let normal_matrix = linear.invert().map(|m| m.transpose()).unwrap_or(linear);
+
+    let vertices: Vec<Vertex> = positions
+        .into_iter()
+        .zip(tex_coords)
+        .enumerate()
+        .map(|(i, (position, tex_coords))| {
+            let position = transform * Vector3::from(position).extend(1.0);
+            let normal = normals.as_ref().map_or([0.0; 3], |normals| {
+                (normal_matrix * Vector3::from(normals[i]))
+                    .normalize()
+                    .into()
+            });
+            Vertex {
+                position: position.truncate().into(),
+                tex_coords,
+                normal,
+            }
+        })
+        .collect();
+    if normals.is_some() {
+        return Ok((vertices, indices));
+    }
+
+    let flat: Vec<Vertex> = indices
+        .chunks_exact(3)
+        .flat_map(|triangle| {
+            let corners = [0, 1, 2].map(|k| vertices[triangle[k] as usize]);
+            let [a, b, c] = corners.map(|v| Vector3::from(v.position));
+            let normal = (b - a).cross(c - a);
+            // Degenerate triangles have no direction to face
+            let normal = if normal.magnitude2() > 0.0 {
+                normal.normalize().into()
+            } else {
+                [0.0; 3]
+            };
+            corners.map(|v| Vertex { normal, ..v })
+        })
+        .collect();
+    let indices = (0..flat.len() as u32).collect();
+    Ok((flat, indices))
+}
+
+/// Texture options following a glTF sampler. Filters it leaves open get the
+/// defaults, trilinear and anisotropic.
+fn sampler_options(sampler: &gltf::texture::Sampler, srgb: bool) -> texture::TextureOptions {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    use wgpu::FilterMode::{Linear, Nearest};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+    let mut options = texture::TextureOptions {
+        srgb,
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        ..Default::default()
+    };
+    if let Some(filter) = sampler.mag_filter() {
+        options.mag_filter = match filter {
+            MagFilter::Nearest => Nearest,
+            MagFilter::Linear => Linear,
+        };
+    }
+    if let Some(filter) = sampler.min_filter() {
+        let (min_filter, mipmap_filter) = match filter {
+            MinFilter::Nearest => (Nearest, None),
+            MinFilter::Linear => (Linear, None),
+            MinFilter::NearestMipmapNearest => (Nearest, Some(Nearest)),
+            MinFilter::LinearMipmapNearest => (Linear, Some(Nearest)),
+            MinFilter::NearestMipmapLinear => (Nearest, Some(Linear)),
+            MinFilter::LinearMipmapLinear => (Linear, Some(Linear)),
+        };
+        options.min_filter = min_filter;
+        match mipmap_filter {
+            Some(filter) => options.mipmap_filter = filter,
+            None => options.mipmaps = texture::MipPolicy::None,
+        }
+    }
+    options
+}
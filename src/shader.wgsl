@@ -1,7 +1,10 @@
 // Vertex shader
 
 struct CameraUniform {
+    view_position: vec4<f32>,
     view_proj: mat4x4<f32>,
+    // w is 1 for orthographic cameras
+    view_forward: vec4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
@@ -9,31 +12,463 @@ var<uniform> camera: CameraUniform;
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) color: vec4<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
 }
 
 @vertex
 fn vs_main(
     model: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.color = instance.color;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
 // Fragment shader
 
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0)@binding(1)
-var s_diffuse: sampler;
+var t_base_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_base_color: sampler;
+// Roughness in green, metallic in blue
+@group(0) @binding(2)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(3)
+var s_metallic_roughness: sampler;
+@group(0) @binding(4)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(5)
+var s_normal: sampler;
+// Occlusion in red
+@group(0) @binding(6)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(7)
+var s_occlusion: sampler;
+@group(0) @binding(8)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(9)
+var s_emissive: sampler;
+
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+}
+@group(0) @binding(10)
+var<uniform> material: Material;
+
+// Must match `light::MAX_LIGHTS`
+const MAX_LIGHTS: u32 = 16u;
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    // Direction the light travels in, for directional and spot lights
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    cos_inner: f32,
+    // Constant, linear and quadratic distance falloff
+    attenuation: vec3<f32>,
+    cos_outer: f32,
+    // Layer in `t_shadow`, or -1 if the light casts no shadows
+    shadow_index: i32,
+}
+
+struct Lights {
+    ambient: vec3<f32>,
+    count: u32,
+    lights: array<Light, MAX_LIGHTS>,
+    // Scale of the image-based lighting, 0 without an environment
+    environment_intensity: f32,
+    // Mip level of `t_specular` for a roughness of 1
+    specular_max_lod: f32,
+    _padding0: f32,
+    _padding1: f32,
+}
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+// Image-based lighting, see `ibl::Environment`. The cube maps are
+// left-handed, so they're sampled with z flipped.
+@group(2) @binding(1)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(2)
+var t_specular: texture_cube<f32>;
+@group(2) @binding(3)
+var t_brdf_lut: texture_2d<f32>;
+@group(2) @binding(4)
+var s_environment: sampler;
+
+// Must match `light::MAX_SHADOWS`
+const MAX_SHADOWS: u32 = 4u;
+// Must match `shadow::MAX_LAYERS`
+const MAX_SHADOW_LAYERS: u32 = 8u;
+// Must match `shadow::MAX_CASCADES`
+const MAX_CASCADES: u32 = 4u;
+
+struct Shadows {
+    view_proj: array<mat4x4<f32>, MAX_SHADOW_LAYERS>,
+    // World-space texel size of each layer at a distance of 1 from the light,
+    // packed four to a vector
+    texel_scale: array<vec4<f32>, 2>,
+    // First layer of each shadow-casting light, or -1 if it has none
+    first_layer: vec4<i32>,
+    // View-space depth at which each cascade ends
+    cascade_splits: vec4<f32>,
+    view_forward: vec3<f32>,
+    cascade_count: u32,
+    depth_bias: f32,
+    normal_bias: f32,
+    pcf_radius: i32,
+    cascade_blend: f32,
+    debug_cascades: u32,
+}
+@group(3) @binding(0)
+var t_shadow: texture_depth_2d_array;
+@group(3) @binding(1)
+var s_shadow: sampler_comparison;
+@group(3) @binding(2)
+var<uniform> shadows: Shadows;
+
+// Fraction of the light reaching `position` according to shadow map
+// `layer`, averaged over the PCF kernel. `light_dir` points towards the light.
+fn sample_shadow_layer(
+    layer: i32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    light_dir: vec3<f32>,
+) -> f32 {
+    // w is the distance from perspective lights and 1 for orthographic ones
+    let w = (shadows.view_proj[layer] * vec4<f32>(position, 1.0)).w;
+    let texel_size = shadows.texel_scale[layer / 4][layer % 4] * w;
+    let biased = position
+        + normal * shadows.normal_bias * texel_size
+        + light_dir * shadows.depth_bias;
+    let clip = shadows.view_proj[layer] * vec4<f32>(biased, 1.0);
+    let ndc = clip.xyz / clip.w;
+    // Outside the light's frustum nothing casts shadows
+    if clip.w <= 0.0 || any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    let depth = ndc.z;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+
+    var lit = 0.0;
+    for (var y = -shadows.pcf_radius; y <= shadows.pcf_radius; y++) {
+        for (var x = -shadows.pcf_radius; x <= shadows.pcf_radius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, layer, depth);
+        }
+    }
+    let width = f32(shadows.pcf_radius * 2 + 1);
+    return lit / (width * width);
+}
+
+// The cascade covering `view_depth`, or `cascade_count` beyond the last one
+fn cascade_index(view_depth: f32) -> u32 {
+    var cascade = 0u;
+    while cascade < shadows.cascade_count && view_depth > shadows.cascade_splits[cascade] {
+        cascade++;
+    }
+    return cascade;
+}
+
+// Shadow of a directional light, fading into the next cascade over the far
+// end of each one, and out entirely at the end of the last
+fn cascaded_shadow(
+    first_layer: i32,
+    view_depth: f32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    light_dir: vec3<f32>,
+) -> f32 {
+    let cascade = cascade_index(view_depth);
+    if cascade >= shadows.cascade_count {
+        return 1.0;
+    }
+    let layer = first_layer + i32(cascade);
+    var lit = sample_shadow_layer(layer, position, normal, light_dir);
+
+    let end = shadows.cascade_splits[cascade];
+    var start = 0.0;
+    if cascade > 0u {
+        start = shadows.cascade_splits[cascade - 1u];
+    }
+    let blend_start = end - (end - start) * shadows.cascade_blend;
+    if view_depth > blend_start {
+        var next = 1.0;
+        if cascade + 1u < shadows.cascade_count {
+            next = sample_shadow_layer(layer + 1, position, normal, light_dir);
+        }
+        lit = mix(lit, next, (view_depth - blend_start) / (end - blend_start));
+    }
+    return lit;
+}
+
+// Fraction of `light` reaching `position`
+fn shadow_factor(
+    light: Light,
+    view_depth: f32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    light_dir: vec3<f32>,
+) -> f32 {
+    if light.shadow_index < 0 {
+        return 1.0;
+    }
+    let first_layer = shadows.first_layer[light.shadow_index];
+    if first_layer < 0 {
+        return 1.0;
+    }
+    if light.kind == LIGHT_DIRECTIONAL {
+        return cascaded_shadow(first_layer, view_depth, position, normal, light_dir);
+    }
+    return sample_shadow_layer(first_layer, position, normal, light_dir);
+}
+
+// Tint of each cascade for `ShadowSettings::debug_cascades`
+fn cascade_debug_color(view_depth: f32) -> vec3<f32> {
+    var colors = array<vec3<f32>, MAX_CASCADES>(
+        vec3<f32>(1.0, 0.3, 0.3),
+        vec3<f32>(0.3, 1.0, 0.3),
+        vec3<f32>(0.3, 0.3, 1.0),
+        vec3<f32>(1.0, 1.0, 0.3),
+    );
+    let cascade = cascade_index(view_depth);
+    if cascade >= shadows.cascade_count {
+        return vec3<f32>(1.0);
+    }
+    return colors[cascade];
+}
+
+const PI: f32 = 3.14159265359;
+// Reflectance of dielectrics at normal incidence
+const DIELECTRIC_F0: vec3<f32> = vec3<f32>(0.04);
+
+// GGX/Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's height-correlated visibility term, V = G / (4 N·L N·V)
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(ggx_v + ggx_l, 1e-5);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+struct Surface {
+    albedo: vec3<f32>,
+    metallic: f32,
+    // Perceptual roughness squared
+    alpha: f32,
+    normal: vec3<f32>,
+    position: vec3<f32>,
+}
+
+// Light reaching the surface from `light`, and the direction towards it
+struct Incoming {
+    radiance: vec3<f32>,
+    direction: vec3<f32>,
+}
+
+fn incoming_light(light: Light, position: vec3<f32>) -> Incoming {
+    var out: Incoming;
+    out.direction = -light.direction;
+    out.radiance = light.color * light.intensity;
+    if light.kind != LIGHT_DIRECTIONAL {
+        let to_light = light.position - position;
+        let distance = length(to_light);
+        out.direction = to_light / distance;
+        let a = light.attenuation;
+        out.radiance /= a.x + a.y * distance + a.z * distance * distance;
+        if light.kind == LIGHT_SPOT {
+            let cos_theta = dot(-out.direction, light.direction);
+            out.radiance *= smoothstep(light.cos_outer, light.cos_inner, cos_theta);
+        }
+    }
+    return out;
+}
+
+// Cook-Torrance specular plus Lambertian diffuse, as in the glTF spec
+fn brdf(surface: Surface, light_dir: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    let n = surface.normal;
+    let h = normalize(view_dir + light_dir);
+    let n_dot_l = max(dot(n, light_dir), 0.0);
+    let n_dot_v = max(dot(n, view_dir), 1e-4);
+    let n_dot_h = max(dot(n, h), 0.0);
+    let v_dot_h = max(dot(view_dir, h), 0.0);
+
+    let f0 = mix(DIELECTRIC_F0, surface.albedo, surface.metallic);
+    let f = fresnel_schlick(v_dot_h, f0);
+    let specular = f * distribution_ggx(n_dot_h, surface.alpha)
+        * visibility_smith_ggx(n_dot_v, n_dot_l, surface.alpha);
+    let diffuse = (1.0 - f) * (1.0 - surface.metallic) * surface.albedo / PI;
+    return (diffuse + specular) * n_dot_l;
+}
+
+// Fresnel averaged over the rough microfacets reflecting the environment
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let f90 = max(vec3<f32>(1.0 - roughness), f0);
+    return f0 + (f90 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Light from the environment, with the split-sum approximation for specular
+fn environment_light(surface: Surface, view_dir: vec3<f32>) -> vec3<f32> {
+    let n = surface.normal;
+    let n_dot_v = max(dot(n, view_dir), 1e-4);
+    let r = reflect(-view_dir, n);
+    let roughness = sqrt(surface.alpha);
+
+    let f0 = mix(DIELECTRIC_F0, surface.albedo, surface.metallic);
+    let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let scale_bias = textureSampleLevel(
+        t_brdf_lut,
+        s_environment,
+        vec2<f32>(n_dot_v, roughness),
+        0.0,
+    ).rg;
+    let reflected = textureSampleLevel(
+        t_specular,
+        s_environment,
+        vec3<f32>(r.xy, -r.z),
+        roughness * lights.specular_max_lod,
+    ).rgb;
+    let specular = reflected * (f0 * scale_bias.x + scale_bias.y);
+
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, vec3<f32>(n.xy, -n.z), 0.0).rgb;
+    let diffuse = (1.0 - f) * (1.0 - surface.metallic) * surface.albedo * irradiance;
+    return (diffuse + specular) * lights.environment_intensity;
+}
+
+// Tangent frame from screen-space derivatives, so meshes need no tangents.
+// Normal maps point +Y up in the image, which is towards -v.
+fn perturb_normal(
+    normal: vec3<f32>,
+    position: vec3<f32>,
+    uv: vec2<f32>,
+    tangent_normal: vec3<f32>,
+) -> vec3<f32> {
+    let dp1 = dpdx(position);
+    let dp2 = dpdy(position);
+    let duv1 = dpdx(uv);
+    let duv2 = dpdy(uv);
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let t = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let b = -(dp2_perp * duv1.y + dp1_perp * duv2.y);
+    let scale = max(dot(t, t), dot(b, b));
+    // Meshes without texture coordinates have no tangent frame
+    if scale <= 0.0 {
+        return normal;
+    }
+    let tbn = mat3x3<f32>(t * inverseSqrt(scale), b * inverseSqrt(scale), normal);
+    return normalize(tbn * tangent_normal);
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let base_color = textureSample(t_base_color, s_base_color, in.tex_coords)
+        * material.base_color * in.color;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let occlusion = textureSample(t_occlusion, s_occlusion, in.tex_coords).r;
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive;
+    var tangent_normal = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    tangent_normal = vec3<f32>(tangent_normal.xy * material.normal_scale, tangent_normal.z);
+
+    let roughness = clamp(material.roughness * metallic_roughness.g, 0.03, 1.0);
+    var surface: Surface;
+    surface.albedo = base_color.rgb;
+    surface.metallic = clamp(material.metallic * metallic_roughness.b, 0.0, 1.0);
+    surface.alpha = roughness * roughness;
+    surface.normal = perturb_normal(
+        normalize(in.world_normal),
+        in.world_position,
+        in.tex_coords,
+        tangent_normal,
+    );
+    surface.position = in.world_position;
+    // Orthographic cameras look along parallel rays
+    let view_dir = select(
+        normalize(camera.view_position.xyz - in.world_position),
+        -camera.view_forward.xyz,
+        camera.view_forward.w > 0.5,
+    );
+    let view_depth = dot(in.world_position - camera.view_position.xyz, shadows.view_forward);
+
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i++) {
+        let light = incoming_light(lights.lights[i], surface.position);
+        let shadow = shadow_factor(
+            lights.lights[i],
+            view_depth,
+            surface.position,
+            normalize(in.world_normal),
+            light.direction,
+        );
+        color += shadow * light.radiance * brdf(surface, light.direction, view_dir);
+    }
+    let ambient_occlusion = mix(1.0, occlusion, material.occlusion_strength);
+    if lights.environment_intensity > 0.0 {
+        color += environment_light(surface, view_dir) * ambient_occlusion;
+    } else {
+        color += lights.ambient * base_color.rgb * ambient_occlusion;
+    }
+    color += emissive;
+    if shadows.debug_cascades != 0u {
+        color *= cascade_debug_color(view_depth);
+    }
+    return vec4<f32>(color, base_color.a);
+}
@@ -0,0 +1,604 @@
+use cgmath::{
+    Angle, Deg, EuclideanSpace, InnerSpace, Matrix4, MetricSpace, Point3, SquareMatrix, Transform,
+    Vector3,
+};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    instance::InstanceRaw,
+    light::{Light, Lights, MAX_SHADOWS},
+    model::{Aabb, DrawModel, Model},
+    Camera, Projection, Vertex, OPENGL_TO_WGPU_MATRIX,
+};
+
+/// Most cascades the view frustum can be split into for a directional light.
+/// Must match `MAX_CASCADES` in `shader.wgsl`.
+pub const MAX_CASCADES: usize = 4;
+
+/// Shadow map layers available. Spot lights take one layer, directional
+/// lights one per cascade; lights that don't fit any more are unshadowed.
+/// Must match `MAX_SHADOW_LAYERS` in `shader.wgsl`.
+const MAX_LAYERS: usize = 8;
+
+/// Shadow map quality settings, shared by all shadow-casting lights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of each light's shadow map in texels.
+    pub resolution: u32,
+    /// World-space distance a fragment is moved towards the light before
+    /// comparing its depth with the shadow map, against shadow acne.
+    pub depth_bias: f32,
+    /// Distance, in shadow map texels, a fragment is pushed along its normal
+    /// before the lookup. Scales with the resolution, unlike `depth_bias`,
+    /// and removes the acne left on surfaces at grazing angles to the light.
+    pub normal_bias: f32,
+    /// Width of the square PCF filter in texels. 1 takes a single,
+    /// bilinearly filtered sample.
+    pub pcf_kernel_size: u32,
+    /// Number of slices the camera frustum is split into for directional
+    /// lights, each with its own shadow map. At most `MAX_CASCADES`.
+    pub cascades: u32,
+    /// Distance from the camera up to which directional lights cast shadows.
+    /// Never further than the camera's `zfar`.
+    pub max_distance: f32,
+    /// Blend between uniform (0) and logarithmic (1) cascade splits.
+    /// Logarithmic splits keep texels a similar size on screen in every
+    /// cascade, but make the first cascade very short.
+    pub split_lambda: f32,
+    /// Fraction of each cascade, at its far end, over which it fades into
+    /// the next one to hide the seam.
+    pub cascade_blend: f32,
+    /// Tints everything by the cascade shadowing it: red, green, blue and
+    /// yellow from near to far.
+    pub debug_cascades: bool,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            depth_bias: 0.02,
+            normal_bias: 1.5,
+            pcf_kernel_size: 3,
+            cascades: 4,
+            max_distance: 100.0,
+            split_lambda: 0.75,
+            cascade_blend: 0.1,
+            debug_cascades: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    view_proj: [[[f32; 4]; 4]; MAX_LAYERS],
+    /// World-space size of a texel of each layer at a distance of 1 from
+    /// the light, or anywhere for orthographic projections
+    texel_scale: [f32; MAX_LAYERS],
+    /// First layer of each shadow-casting light, or -1 if it didn't fit
+    first_layer: [i32; MAX_SHADOWS],
+    /// View-space depth at which each cascade ends
+    cascade_splits: [f32; MAX_CASCADES],
+    view_forward: [f32; 3],
+    cascade_count: u32,
+    depth_bias: f32,
+    normal_bias: f32,
+    pcf_radius: i32,
+    cascade_blend: f32,
+    debug_cascades: u32,
+    _padding: [u32; 3],
+}
+
+/// Depth layers for the shadow-casting lights, rendered before the main pass.
+///
+/// Spot lights get a single layer covering their cone. Directional lights get
+/// a layer per cascade, each covering a slice of the camera frustum, so
+/// nearby shadows stay sharp in large scenes. The cascades are sized by the
+/// slice alone and snapped to whole texels, so shadow edges don't shimmer
+/// while the camera moves or turns.
+pub(crate) struct ShadowMaps {
+    settings: ShadowSettings,
+    texture: wgpu::Texture,
+    /// One view per layer, to render into
+    layer_views: Vec<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+    /// View-projection matrix of each layer, for the shadow pass
+    pass_buffers: Vec<wgpu::Buffer>,
+    pass_bind_groups: Vec<wgpu::BindGroup>,
+    uniform: ShadowUniform,
+    uniform_buffer: wgpu::Buffer,
+    /// Number of layers in use this frame
+    active: usize,
+    /// Number of layers `texture` has
+    layers: u32,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMaps {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("shadow_pass_bind_group_layout"),
+            });
+        let (pass_buffers, pass_bind_groups) = (0..MAX_LAYERS)
+            .map(|layer| {
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Shadow Pass Buffer {layer}")),
+                    contents: bytemuck::cast_slice(&[[[0.0f32; 4]; 4]]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &pass_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some(&format!("shadow_pass_bind_group_{layer}")),
+                });
+                (buffer, bind_group)
+            })
+            .unzip();
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                // Single-sided surfaces like the pentagon cast shadows from both sides
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            // Linear filtering compares the 4 nearest texels, smoothing each PCF tap
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let uniform = ShadowUniform {
+            view_proj: [Matrix4::identity().into(); MAX_LAYERS],
+            texel_scale: [0.0; MAX_LAYERS],
+            first_layer: [-1; MAX_SHADOWS],
+            cascade_splits: [0.0; MAX_CASCADES],
+            view_forward: [0.0, 0.0, -1.0],
+            cascade_count: 0,
+            depth_bias: settings.depth_bias,
+            normal_bias: settings.normal_bias,
+            pcf_radius: pcf_radius(settings.pcf_kernel_size),
+            cascade_blend: settings.cascade_blend,
+            debug_cascades: settings.debug_cascades as u32,
+            _padding: [0; 3],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // WebGL treats single-layer textures as plain 2D textures
+        let layers = 2;
+        let (texture, layer_views, bind_group) = create_maps(
+            device,
+            settings.resolution,
+            layers,
+            &bind_group_layout,
+            &sampler,
+            &uniform_buffer,
+        );
+
+        Self {
+            settings,
+            texture,
+            layer_views,
+            sampler,
+            pipeline,
+            pass_buffers,
+            pass_bind_groups,
+            uniform,
+            uniform_buffer,
+            active: 0,
+            layers,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn settings(&self) -> ShadowSettings {
+        self.settings
+    }
+
+    /// Applies new settings, reallocating the maps if the resolution changed.
+    /// The rest is uploaded by the next `update`.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = ShadowSettings {
+            cascades: settings.cascades.clamp(1, MAX_CASCADES as u32),
+            ..settings
+        };
+        if settings.resolution != self.texture.width() {
+            self.allocate(device, settings.resolution, self.layers);
+        }
+        self.settings = settings;
+    }
+
+    fn allocate(&mut self, device: &wgpu::Device, resolution: u32, layers: u32) {
+        self.layers = layers;
+        (self.texture, self.layer_views, self.bind_group) = create_maps(
+            device,
+            resolution,
+            layers,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+    }
+
+    /// Fits every shadow-casting light's projection to the scene, given by
+    /// its `bounds`, and to the `camera`, and uploads them. The maps grow if
+    /// more layers are needed than before.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &Lights,
+        bounds: Aabb,
+        camera: &Camera,
+    ) {
+        let resolution = self.settings.resolution;
+        let cascades = self.settings.cascades as usize;
+        let projection = camera.projection();
+        let near = projection.znear();
+        let far = self.settings.max_distance.min(projection.zfar()).max(near);
+        let splits = cascade_splits(near, far, cascades, self.settings.split_lambda);
+
+        self.active = 0;
+        self.uniform.first_layer = [-1; MAX_SHADOWS];
+        if !bounds.is_empty() {
+            for (slot, light) in lights.shadow_casters().enumerate() {
+                // (view-projection, width of the view at a distance of 1) per layer
+                let layers: Vec<(Matrix4<f32>, f32)> = match *light {
+                    Light::Directional { direction, .. } => (0..cascades)
+                        .map(|cascade| {
+                            let start = if cascade == 0 {
+                                near
+                            } else {
+                                splits[cascade - 1]
+                            };
+                            cascade_view_proj(
+                                direction,
+                                camera,
+                                start..splits[cascade],
+                                bounds,
+                                resolution,
+                            )
+                        })
+                        .collect(),
+                    _ => vec![spot_view_proj(light, bounds)],
+                };
+                if self.active + layers.len() > MAX_LAYERS {
+                    continue;
+                }
+                self.uniform.first_layer[slot] = self.active as i32;
+                for (view_proj, extent) in layers {
+                    let view_proj: [[f32; 4]; 4] = view_proj.into();
+                    self.uniform.view_proj[self.active] = view_proj;
+                    self.uniform.texel_scale[self.active] = extent / resolution as f32;
+                    queue.write_buffer(
+                        &self.pass_buffers[self.active],
+                        0,
+                        bytemuck::cast_slice(&[view_proj]),
+                    );
+                    self.active += 1;
+                }
+            }
+        }
+        if self.active as u32 > self.layers {
+            self.allocate(device, resolution, self.active as u32);
+        }
+
+        let forward = (camera.target - camera.eye).normalize();
+        self.uniform.cascade_splits = splits;
+        self.uniform.view_forward = forward.into();
+        self.uniform.cascade_count = cascades as u32;
+        self.uniform.depth_bias = self.settings.depth_bias;
+        self.uniform.normal_bias = self.settings.normal_bias;
+        self.uniform.pcf_radius = pcf_radius(self.settings.pcf_kernel_size);
+        self.uniform.cascade_blend = self.settings.cascade_blend;
+        self.uniform.debug_cascades = self.settings.debug_cascades as u32;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// Records one depth pass per layer in use.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, models: &[Model]) {
+        for layer in 0..self.active {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[layer],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.pass_bind_groups[layer], &[]);
+            for model in models {
+                pass.draw_model_depth(model);
+            }
+        }
+    }
+}
+
+/// Half the kernel width, as the shader loops from `-radius` to `radius`.
+fn pcf_radius(kernel_size: u32) -> i32 {
+    (kernel_size.max(1) as i32 - 1) / 2
+}
+
+fn create_maps(
+    device: &wgpu::Device,
+    resolution: u32,
+    layers: u32,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    uniform_buffer: &wgpu::Buffer,
+) -> (wgpu::Texture, Vec<wgpu::TextureView>, wgpu::BindGroup) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Shadow Maps"),
+        size: wgpu::Extent3d {
+            width: resolution.max(1),
+            height: resolution.max(1),
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ShadowMaps::FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let layer_views = (0..layers)
+        .map(|layer| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Shadow Map Layer"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect();
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("shadow_bind_group"),
+    });
+    (texture, layer_views, bind_group)
+}
+
+/// An up vector that isn't parallel to `direction`.
+fn up_for(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.normalize().y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
+
+/// Splits `near..far` into `count` cascades and returns the far end of each,
+/// blending logarithmic and uniform splits by `lambda`.
+fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> [f32; MAX_CASCADES] {
+    let mut splits = [far; MAX_CASCADES];
+    for (i, split) in splits.iter_mut().enumerate().take(count) {
+        let t = (i + 1) as f32 / count as f32;
+        let log = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        *split = lambda * log + (1.0 - lambda) * uniform;
+    }
+    splits
+}
+
+/// An orthographic view-projection matrix for a directional light covering
+/// the slice `depth` of the camera frustum, and the width of its view.
+///
+/// The view is a square around the slice's bounding sphere, whose size only
+/// depends on the slice and not on where the camera looks, and its position
+/// is snapped to whole texels. Both keep the shadow map's texels fixed in
+/// the world as the camera moves. The depth range covers the whole scene, so
+/// casters outside the slice still throw shadows into it.
+fn cascade_view_proj(
+    direction: Vector3<f32>,
+    camera: &Camera,
+    depth: std::ops::Range<f32>,
+    bounds: Aabb,
+    resolution: u32,
+) -> (Matrix4<f32>, f32) {
+    let (near, far) = (depth.start, depth.end);
+    // Squared half diagonals of the slice's near and far cross sections
+    let (near_diagonal2, far_diagonal2) = match camera.projection() {
+        Projection::Orthographic {
+            left,
+            right,
+            bottom,
+            top,
+            ..
+        } => {
+            let diagonal2 = ((right - left).powi(2) + (top - bottom).powi(2)) / 4.0;
+            (diagonal2, diagonal2)
+        }
+        _ => {
+            // At a distance of 1
+            let tan = (Deg(camera.fovy) / 2.0).tan();
+            let diagonal2 = tan * tan * (1.0 + camera.aspect * camera.aspect);
+            (near * near * diagonal2, far * far * diagonal2)
+        }
+    };
+    // The sphere's center on the view axis, equally far from the near and
+    // far corners, unless the far corners alone decide the radius
+    let center_depth = ((far * far - near * near + far_diagonal2 - near_diagonal2)
+        / (2.0 * (far - near)))
+        .clamp(near, far);
+    let radius = ((center_depth - near).powi(2) + near_diagonal2)
+        .max((far - center_depth).powi(2) + far_diagonal2)
+        .sqrt();
+    // Rounded up so float noise doesn't change the texel size
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let forward = (camera.target - camera.eye).normalize();
+    let center = camera.eye + forward * center_depth;
+    let view = Matrix4::look_to_rh(Point3::origin(), direction.normalize(), up_for(direction));
+    let center = view.transform_point(center);
+    let texel = radius * 2.0 / resolution as f32;
+    let x = (center.x / texel).floor() * texel;
+    let y = (center.y / texel).floor() * texel;
+
+    // View space looks down -z
+    let (mut z_min, mut z_max) = (center.z - radius, center.z + radius);
+    for corner in bounds.corners() {
+        let z = view.transform_point(corner).z;
+        z_min = z_min.min(z);
+        z_max = z_max.max(z);
+    }
+    let proj = cgmath::ortho(
+        x - radius,
+        x + radius,
+        y - radius,
+        y + radius,
+        -z_max,
+        -z_min,
+    );
+    (OPENGL_TO_WGPU_MATRIX * proj * view, radius * 2.0)
+}
+
+/// A spot light's view-projection matrix, as wide as its cone and deep
+/// enough for everything in `bounds`, and the width of its view at a
+/// distance of 1.
+fn spot_view_proj(light: &Light, bounds: Aabb) -> (Matrix4<f32>, f32) {
+    let Light::Spot {
+        position,
+        direction,
+        outer_angle,
+        ..
+    } = *light
+    else {
+        return (Matrix4::identity(), 0.0);
+    };
+    let eye = Point3::from_vec(position);
+    let view = Matrix4::look_at_rh(eye, eye + direction, up_for(direction));
+    let far = bounds
+        .corners()
+        .iter()
+        .map(|&corner| eye.distance(corner))
+        .fold(0.0, f32::max)
+        .max(1e-2);
+    let fovy = Deg((outer_angle.0 * 2.0).min(170.0));
+    let proj = cgmath::perspective(fovy, 1.0, (far * 1e-3).max(0.01), far);
+    let width = 2.0 * (fovy / 2.0).tan();
+    (OPENGL_TO_WGPU_MATRIX * proj * view, width)
+}
@@ -0,0 +1,454 @@
+use std::{iter, path::Path};
+
+use anyhow::*;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    renderer::DepthSettings,
+    texture::{self, Texture, TextureOptions},
+    Camera,
+};
+
+/// An environment cube map drawn behind everything else, see
+/// [`crate::Renderer::set_skybox`].
+///
+/// Faces follow the usual convention of skybox images, which is left-handed:
+/// +Z is the face straight ahead of a camera looking down -Z, with +X to its
+/// right and +Y up.
+pub struct Skybox {
+    pub texture: Texture,
+}
+
+impl Skybox {
+    /// Format of cube maps converted from panoramas, with room for HDR.
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Wraps an existing cube map.
+    pub fn new(texture: Texture) -> Self {
+        Self { texture }
+    }
+
+    /// Loads six face images, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub fn load_faces<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        paths: &[P; 6],
+    ) -> Result<Self> {
+        let texture = Texture::cube_from_paths(device, queue, paths, TextureOptions::default())?;
+        Ok(Self::new(texture))
+    }
+
+    /// Loads an equirectangular panorama, like most `.hdr` environment maps,
+    /// and converts it to a cube map with faces a quarter of its width.
+    pub fn load_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let panorama = Texture::from_path(device, queue, path, TextureOptions::default())?;
+        let face_size = (panorama.texture.width() / 4).max(1);
+        Ok(Self::from_equirectangular(
+            device, queue, &panorama, face_size,
+        ))
+    }
+
+    /// Converts an equirectangular panorama to a cube map on the GPU. The
+    /// middle of the panorama ends up straight ahead, down -Z. Each mip level
+    /// of the cube reads the panorama's mip level of about the same texel
+    /// size, so give it mipmaps to avoid aliasing.
+    pub fn from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        panorama: &Texture,
+        face_size: u32,
+    ) -> Self {
+        let mip_level_count = texture::mip_level_count(face_size, face_size);
+        let cube = Texture::create_cube(
+            device,
+            face_size,
+            Self::FORMAT,
+            mip_level_count,
+            "Skybox Cube",
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<FaceUniform>() as _
+                        ),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("equirect_bind_group_layout"),
+        });
+
+        // One face and mip level per offset
+        let stride = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let panorama_width = panorama.texture.width() as f32;
+        let mut faces = vec![0; stride * 6 * mip_level_count as usize];
+        for mip_level in 0..mip_level_count {
+            let size = (face_size >> mip_level).max(1) as f32;
+            // The panorama's width covers four faces
+            let lod = (panorama_width / (4.0 * size)).log2().max(0.0);
+            for index in 0..6 {
+                let uniform = FaceUniform {
+                    index,
+                    lod,
+                    _padding: [0; 2],
+                };
+                let offset = stride * (mip_level * 6 + index) as usize;
+                faces[offset..offset + std::mem::size_of::<FaceUniform>()]
+                    .copy_from_slice(bytemuck::bytes_of(&uniform));
+            }
+        }
+        let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Equirect Face Buffer"),
+            contents: &faces,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        // Wraps around horizontally, so there's no seam behind the camera
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Equirect Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &face_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<FaceUniform>() as _),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&panorama.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("equirect_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Equirect Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("equirect.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Equirect Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Equirect Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(Self::FORMAT.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Equirect Encoder"),
+        });
+        for mip_level in 0..mip_level_count {
+            for index in 0..6 {
+                let view = cube.face_view(index, mip_level);
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Equirect Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+                let offset = stride as u32 * (mip_level * 6 + index);
+                pass.set_pipeline(&pipeline);
+                pass.set_bind_group(0, &bind_group, &[offset]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+        queue.submit(iter::once(encoder.finish()));
+
+        Self::new(cube)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FaceUniform {
+    index: u32,
+    lod: f32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    /// From clip space to a world space direction, ignoring the camera's
+    /// position
+    inv_view_proj: [[f32; 4]; 4],
+    /// Direction the camera looks in, and 1 in w if it's orthographic
+    view_forward: [f32; 4],
+    /// Depth of the far plane, where the sky is drawn
+    far_depth: f32,
+    _padding: [f32; 3],
+}
+
+/// Draws the skybox, if there is one, at the far plane after the scene, so
+/// only the pixels the scene left empty are shaded.
+pub(crate) struct SkyboxPass {
+    skybox: Option<(Skybox, wgpu::BindGroup)>,
+    uniform: SkyUniform,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SkyboxPass {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        depth: &DepthSettings,
+        sample_count: u32,
+    ) -> Self {
+        let uniform = SkyUniform {
+            inv_view_proj: Matrix4::identity().into(),
+            view_forward: [0.0, 0.0, -1.0, 0.0],
+            far_depth: depth.clear_value,
+            _padding: [0.0; 3],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("sky_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Sky Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
+        });
+        let pipeline = create_sky_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            depth,
+            sample_count,
+        );
+
+        Self {
+            skybox: None,
+            uniform,
+            uniform_buffer,
+            bind_group_layout,
+            pipeline_layout,
+            shader,
+            color_format,
+            pipeline,
+        }
+    }
+
+    pub fn skybox(&self) -> Option<&Skybox> {
+        self.skybox.as_ref().map(|(skybox, _)| skybox)
+    }
+
+    pub fn set_skybox(&mut self, device: &wgpu::Device, skybox: Option<Skybox>) {
+        self.skybox = skybox.map(|skybox| {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&skybox.texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&skybox.texture.sampler),
+                    },
+                ],
+                label: Some("sky_bind_group"),
+            });
+            (skybox, bind_group)
+        });
+    }
+
+    /// Follows a change to the main pass's depth settings or sample count.
+    pub fn set_targets(&mut self, device: &wgpu::Device, depth: &DepthSettings, sample_count: u32) {
+        self.uniform.far_depth = depth.clear_value;
+        self.pipeline = create_sky_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.color_format,
+            depth,
+            sample_count,
+        );
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        if self.skybox.is_none() {
+            return;
+        }
+        // Only the camera's orientation matters, the sky is infinitely far
+        let mut view = camera.view_matrix();
+        view.w = Vector4::unit_w();
+        let view_proj = camera.projection().matrix() * view;
+        self.uniform.inv_view_proj = view_proj.invert().unwrap_or(Matrix4::identity()).into();
+        let orthographic = camera.projection().is_orthographic();
+        self.uniform.view_forward = (camera.target - camera.eye)
+            .normalize()
+            .extend(if orthographic { 1.0 } else { 0.0 })
+            .into();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniform));
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some((_, bind_group)) = &self.skybox {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+fn create_sky_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth: &DepthSettings,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    use wgpu::CompareFunction::*;
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Sky Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(color_format.into())],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Drawn exactly at the far plane, so it passes only where the depth
+        // buffer still holds its clear value
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth.format,
+            depth_write_enabled: false,
+            depth_compare: match depth.compare {
+                Less => LessEqual,
+                Greater => GreaterEqual,
+                other => other,
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,49 @@
+// Draws the environment cube map behind everything else
+
+struct Sky {
+    // From clip space to a world space direction, ignoring the camera's
+    // position
+    inv_view_proj: mat4x4<f32>,
+    // Direction the camera looks in, and 1 in w if it's orthographic
+    view_forward: vec4<f32>,
+    // Depth of the far plane, where the sky is drawn
+    far_depth: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+@group(0) @binding(0)
+var<uniform> sky: Sky;
+@group(0) @binding(1)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(2)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// One triangle covering the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, sky.far_depth, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var direction = sky.view_forward.xyz;
+    if sky.view_forward.w < 0.5 {
+        // Any depth short of the far plane lies on the same ray
+        let world = sky.inv_view_proj * vec4<f32>(in.ndc, 0.5, 1.0);
+        direction = world.xyz / world.w;
+    }
+    // Cube maps are left-handed: +Z is the face ahead of a camera looking
+    // down -Z
+    let color = textureSample(t_sky, s_sky, vec3<f32>(direction.xy, -direction.z));
+    return vec4<f32>(color.rgb, 1.0);
+}
@@ -1,6 +1,483 @@
+use std::path::Path;
+
 use anyhow::*;
 use image::GenericImageView;
 
+/// How a texture is filtered when drawn smaller or larger than its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filtering {
+    /// A full mip chain, sampled trilinearly and anisotropically so surfaces
+    /// far away or at grazing angles don't shimmer.
+    #[default]
+    Mipmapped,
+    /// Bilinear filtering of the full-size image only, for UI and other
+    /// textures drawn at about their own size.
+    Linear,
+    /// The nearest texel of the full-size image, for pixel art.
+    Nearest,
+}
+
+/// Which mip levels a texture gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MipPolicy {
+    /// Every level down to 1x1.
+    #[default]
+    Full,
+    /// At most this many levels, keeping the smallest ones from blurring
+    /// tiles of a texture atlas into each other.
+    MaxLevels(u32),
+    /// Only the full-size image.
+    None,
+}
+
+/// Which channels of an image a texture keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Channels {
+    /// Always red, green, blue and alpha, with gray repeated in red, green
+    /// and blue. Materials and skyboxes need this, as they read several
+    /// channels of every map.
+    #[default]
+    Rgba,
+    /// Only the channels the image has, to save memory: gray becomes a
+    /// single red channel, gray with alpha red and green. Such textures hold
+    /// data, so gray is never sRGB decoded. Colors are still stored as RGBA.
+    Source,
+}
+
+/// How much precision images with more than 8 bits per channel keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    /// As much as formats every device can filter hold, so the texture fits
+    /// the material bind group and skyboxes. 16-bit integer images keep
+    /// every bit as `R16Unorm`, `Rg16Unorm` or `Rgba16Unorm` where the device
+    /// has `Features::TEXTURE_FORMAT_16BIT_NORM` and they needn't be decoded
+    /// from sRGB, and lose their lowest bits as 16-bit floats elsewhere.
+    /// Float images, like Radiance `.hdr` and OpenEXR, become 16-bit floats,
+    /// which keep their range.
+    #[default]
+    Filterable,
+    /// Like `Filterable`, but 16-bit integer images always keep every bit,
+    /// as 32-bit floats where the normalized formats don't do. Such
+    /// textures can't be filtered without `Features::FLOAT32_FILTERABLE`, so
+    /// their sampler falls back to `Nearest` on devices without it, and they
+    /// don't fit the material bind group.
+    Lossless,
+    /// 32-bit floats for everything beyond 8 bits, float images included,
+    /// with the same limits as `Lossless`.
+    Full,
+}
+
+/// How a texture is stored and sampled. The default suits color maps on
+/// surfaces in the scene; see the other constructors for common variations.
+///
+/// The format follows the image: 8-bit colors become `Rgba8UnormSrgb` or
+/// `Rgba8Unorm`, and with [`Channels::Source`] 8-bit gray becomes `R8Unorm`
+/// (with alpha, `Rg8Unorm`). Anything with more precision is stored as
+/// [`Precision`] says.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureOptions {
+    /// Whether texels are sRGB encoded colors, decoded to linear when
+    /// sampled. Data like normals, roughness or masks must be linear.
+    pub srgb: bool,
+    /// What happens outside 0..1 horizontally: `Repeat` for tiling textures,
+    /// `ClampToEdge` for decals and UI.
+    pub address_mode_u: wgpu::AddressMode,
+    /// The same vertically.
+    pub address_mode_v: wgpu::AddressMode,
+    /// Filter for texels drawn larger than a pixel.
+    pub mag_filter: wgpu::FilterMode,
+    /// Filter for texels drawn smaller than a pixel.
+    pub min_filter: wgpu::FilterMode,
+    /// Filter between mip levels, `Linear` for trilinear filtering.
+    pub mipmap_filter: wgpu::FilterMode,
+    /// Most samples taken along surfaces at grazing angles, from 1 (off) to
+    /// 16. Only used if all three filters are `Linear`, and ignored where
+    /// the adapter doesn't support it.
+    pub anisotropy: u16,
+    pub mipmaps: MipPolicy,
+    /// How the texture may be used. `COPY_DST` is always added for the upload.
+    pub usage: wgpu::TextureUsages,
+    pub channels: Channels,
+    pub precision: Precision,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            srgb: true,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy: 16,
+            mipmaps: MipPolicy::Full,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            channels: Channels::Rgba,
+            precision: Precision::Filterable,
+        }
+    }
+}
+
+impl From<Filtering> for TextureOptions {
+    fn from(filtering: Filtering) -> Self {
+        Self::default().with_filtering(filtering)
+    }
+}
+
+impl TextureOptions {
+    /// Linear data like normal, metallic-roughness or occlusion maps.
+    pub fn data() -> Self {
+        Self {
+            srgb: false,
+            ..Default::default()
+        }
+    }
+
+    /// Sharp texels and no mipmaps.
+    pub fn pixel_art() -> Self {
+        Filtering::Nearest.into()
+    }
+
+    /// The same options with both address modes set to `mode`.
+    pub fn with_address_mode(self, mode: wgpu::AddressMode) -> Self {
+        Self {
+            address_mode_u: mode,
+            address_mode_v: mode,
+            ..self
+        }
+    }
+
+    /// The same options with the filters, anisotropy and mipmaps of a
+    /// [`Filtering`] preset.
+    pub fn with_filtering(self, filtering: Filtering) -> Self {
+        let (filter, anisotropy, mipmaps) = match filtering {
+            Filtering::Mipmapped => (wgpu::FilterMode::Linear, 16, MipPolicy::Full),
+            Filtering::Linear => (wgpu::FilterMode::Linear, 1, MipPolicy::None),
+            Filtering::Nearest => (wgpu::FilterMode::Nearest, 1, MipPolicy::None),
+        };
+        Self {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            anisotropy,
+            mipmaps,
+            ..self
+        }
+    }
+
+    /// Format of 8-bit color images.
+    pub fn format(&self) -> wgpu::TextureFormat {
+        if self.srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        }
+    }
+
+    fn sampler_descriptor<'a>(
+        &self,
+        label: Option<&'a str>,
+        filterable: bool,
+    ) -> wgpu::SamplerDescriptor<'a> {
+        use wgpu::FilterMode::{Linear, Nearest};
+        let filter = |filter| if filterable { filter } else { Nearest };
+        let (mag_filter, min_filter, mipmap_filter) = (
+            filter(self.mag_filter),
+            filter(self.min_filter),
+            filter(self.mipmap_filter),
+        );
+        let all_linear = [mag_filter, min_filter, mipmap_filter]
+            .iter()
+            .all(|&filter| filter == Linear);
+        wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter,
+            min_filter,
+            mipmap_filter,
+            anisotropy_clamp: if all_linear {
+                self.anisotropy.clamp(1, 16)
+            } else {
+                1
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Number of levels in a full mip chain, down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+/// The full mip chain of `img`, starting with `img` itself. Each level
+/// averages 2x2 texels of the one before, in linear space for sRGB images
+/// so distant surfaces keep their brightness.
+pub fn generate_mipmaps(img: &image::RgbaImage, srgb: bool) -> Vec<image::RgbaImage> {
+    let (width, height) = img.dimensions();
+    let levels = mip_chain(
+        decode_8bit(img.as_raw(), 4, srgb),
+        width,
+        height,
+        4,
+        mip_level_count(width, height) as usize,
+    );
+    let mut images = vec![img.clone()];
+    images.extend(levels.into_iter().skip(1).map(|level| {
+        image::RgbaImage::from_raw(
+            level.width,
+            level.height,
+            encode_8bit(&level.texels, 4, srgb),
+        )
+        .unwrap()
+    }));
+    images
+}
+
+/// One mip level of linear texels, `channels` floats per texel.
+struct Level {
+    width: u32,
+    height: u32,
+    texels: Vec<f32>,
+}
+
+/// `count` mip levels, starting with `texels` itself.
+fn mip_chain(
+    texels: Vec<f32>,
+    width: u32,
+    height: u32,
+    channels: usize,
+    count: usize,
+) -> Vec<Level> {
+    let mut levels = vec![Level {
+        width,
+        height,
+        texels,
+    }];
+    while levels.len() < count {
+        let level = levels.last().unwrap();
+        let (width, height) = (level.width, level.height);
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut next = Vec::with_capacity((next_width * next_height) as usize * channels);
+        for y in 0..next_height {
+            for x in 0..next_width {
+                // The last row or column of an odd-sized level is dropped
+                let texel = |dx: u32, dy: u32| {
+                    let sx = (2 * x + dx).min(width - 1);
+                    let sy = (2 * y + dy).min(height - 1);
+                    let start = (sy * width + sx) as usize * channels;
+                    &level.texels[start..start + channels]
+                };
+                let corners = [texel(0, 0), texel(1, 0), texel(0, 1), texel(1, 1)];
+                next.extend((0..channels).map(|c| corners.iter().map(|t| t[c]).sum::<f32>() / 4.0));
+            }
+        }
+        levels.push(Level {
+            width: next_width,
+            height: next_height,
+            texels: next,
+        });
+    }
+    levels
+}
+
+/// Whether channel `c` of `channels` holds an sRGB encoded color. Alpha is
+/// always linear.
+fn is_color(c: usize, channels: usize, srgb: bool) -> bool {
+    srgb && !(channels == 4 && c == 3)
+}
+
+fn decode_8bit(bytes: &[u8], channels: usize, srgb: bool) -> Vec<f32> {
+    let decode: [f32; 256] = std::array::from_fn(|i| srgb_to_linear(i as f32 / 255.0));
+    bytes
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if is_color(i % channels, channels, srgb) {
+                decode[b as usize]
+            } else {
+                b as f32 / 255.0
+            }
+        })
+        .collect()
+}
+
+fn encode_8bit(texels: &[f32], channels: usize, srgb: bool) -> Vec<u8> {
+    texels
+        .iter()
+        .enumerate()
+        .map(|(i, &t)| {
+            let t = if is_color(i % channels, channels, srgb) {
+                linear_to_srgb(t)
+            } else {
+                t
+            };
+            (t * 255.0).round().clamp(0.0, 255.0) as u8
+        })
+        .collect()
+}
+
+/// Decodes an image file. The `image` crate squeezes Radiance `.hdr` files
+/// into 8 bits, so those are read separately to keep their range.
+fn decode(bytes: &[u8]) -> Result<image::DynamicImage> {
+    if image::guess_format(bytes)? != image::ImageFormat::Hdr {
+        return Ok(image::load_from_memory(bytes)?);
+    }
+    let decoder = image::codecs::hdr::HdrDecoder::new(bytes)?;
+    let metadata = decoder.metadata();
+    let texels = decoder.read_image_hdr()?;
+    let img = image::Rgb32FImage::from_raw(
+        metadata.width,
+        metadata.height,
+        texels.iter().flat_map(|texel| texel.0).collect(),
+    )
+    .context("Truncated HDR image")?;
+    Ok(image::DynamicImage::ImageRgb32F(img))
+}
+
+/// Texels of an image ready for upload, and the format they're in.
+fn prepare(
+    img: &image::DynamicImage,
+    options: &TextureOptions,
+    features: wgpu::Features,
+) -> (wgpu::TextureFormat, Vec<(u32, u32, Vec<u8>)>) {
+    use image::DynamicImage::*;
+    use wgpu::TextureFormat::*;
+
+    let (width, height) = img.dimensions();
+    let count = match options.mipmaps {
+        MipPolicy::Full => mip_level_count(width, height),
+        MipPolicy::MaxLevels(count) => count.clamp(1, mip_level_count(width, height)),
+        MipPolicy::None => 1,
+    } as usize;
+
+    // 8 bits stay 8 bits, with the full-size level uploaded untouched
+    let unorm = |bytes: &[u8], channels: usize, srgb: bool, format| {
+        let levels = mip_chain(
+            decode_8bit(bytes, channels, srgb),
+            width,
+            height,
+            channels,
+            count,
+        );
+        let mut levels: Vec<_> = levels
+            .into_iter()
+            .map(|level| {
+                (
+                    level.width,
+                    level.height,
+                    encode_8bit(&level.texels, channels, srgb),
+                )
+            })
+            .collect();
+        levels[0].2 = bytes.to_vec();
+        (format, levels)
+    };
+    // Other images go through linear floats, 32-bit ones when `full`
+    let float = |texels: Vec<f32>, channels: usize, full: bool| {
+        let format = match (channels, full) {
+            (1, false) => R16Float,
+            (2, false) => Rg16Float,
+            (_, false) => Rgba16Float,
+            (1, true) => R32Float,
+            (2, true) => Rg32Float,
+            (_, true) => Rgba32Float,
+        };
+        let levels = mip_chain(texels, width, height, channels, count)
+            .into_iter()
+            .map(|level| {
+                let bytes = if full {
+                    bytemuck::cast_slice(&level.texels).to_vec()
+                } else {
+                    level
+                        .texels
+                        .iter()
+                        .flat_map(|&t| half::f16::from_f32(t).to_le_bytes())
+                        .collect()
+                };
+                (level.width, level.height, bytes)
+            })
+            .collect();
+        (format, levels)
+    };
+    // 16 bits stored as they are, if the device can and they needn't be
+    // decoded from sRGB
+    let norm16 = features.contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM)
+        && options.precision != Precision::Full;
+    let unorm_16bit = |values: &[u16], channels: usize, srgb: bool| {
+        let texels = values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let v = v as f32 / 65535.0;
+                if is_color(i % channels, channels, srgb) {
+                    srgb_to_linear(v)
+                } else {
+                    v
+                }
+            })
+            .collect();
+        if !norm16 || srgb {
+            return float(texels, channels, options.precision != Precision::Filterable);
+        }
+        let format = match channels {
+            1 => R16Unorm,
+            2 => Rg16Unorm,
+            _ => Rgba16Unorm,
+        };
+        let mut levels: Vec<_> = mip_chain(texels, width, height, channels, count)
+            .into_iter()
+            .map(|level| {
+                let bytes = level
+                    .texels
+                    .iter()
+                    .flat_map(|&t| ((t * 65535.0).round() as u16).to_le_bytes())
+                    .collect();
+                (level.width, level.height, bytes)
+            })
+            .collect();
+        levels[0].2 = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        (format, levels)
+    };
+
+    let source = options.channels == Channels::Source;
+    match img {
+        ImageLuma8(gray) if source => unorm(gray.as_raw(), 1, false, R8Unorm),
+        ImageLumaA8(gray) if source => unorm(gray.as_raw(), 2, false, Rg8Unorm),
+        ImageLuma16(gray) if source => unorm_16bit(gray.as_raw(), 1, false),
+        ImageLumaA16(gray) if source => unorm_16bit(gray.as_raw(), 2, false),
+        ImageLuma16(_) | ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) => {
+            unorm_16bit(img.to_rgba16().as_raw(), 4, options.srgb)
+        }
+        // Already linear
+        ImageRgb32F(_) | ImageRgba32F(_) => float(
+            img.to_rgba32f().into_raw(),
+            4,
+            options.precision == Precision::Full,
+        ),
+        _ => unorm(img.to_rgba8().as_raw(), 4, options.srgb, options.format()),
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
@@ -9,68 +486,374 @@ pub struct Texture {
 }
 
 impl Texture {
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    /// A depth buffer, multisampled if `sample_count` is above 1 to go with a
+    /// target from [`Texture::create_multisampled`].
+    pub fn create_depth_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // Multisampled depth is only ever drawn into. On GL that makes it
+            // a renderbuffer like its color target, which it has to be for the
+            // framebuffer to be complete.
+            usage: if sample_count > 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            },
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 100.0,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// A multisampled color target, drawn into and then resolved into a
+    /// single-sampled texture of the same format and size.
+    pub fn create_multisampled(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Decodes an image file. Colors (base color, emissive) are stored as
+    /// sRGB; data maps like normals or roughness need `srgb: false`.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        srgb: bool,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        let options = TextureOptions {
+            srgb,
+            ..Default::default()
+        };
+        Self::from_bytes_with(device, queue, bytes, label, options)
     }
 
+    /// Decodes an image file held in memory.
+    pub fn from_bytes_with(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        let img = decode(bytes)?;
+        Self::from_image_with(device, queue, &img, Some(label), options)
+    }
+
+    /// Loads an image file, in any format the `image` crate was built with.
+    pub fn from_path(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let img = std::fs::read(path)
+            .map_err(Error::from)
+            .and_then(|bytes| decode(&bytes))
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+        Self::from_image_with(device, queue, &img, path.to_str(), options)
+    }
+
+    /// A 1x1 texture of a single color, used where a material has no map.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        srgb: bool,
+    ) -> Result<Self> {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+        Self::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some(label),
+            srgb,
+        )
+    }
+
+    /// Uploads an image with [`Filtering::Mipmapped`].
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        srgb: bool,
+    ) -> Result<Self> {
+        Self::from_image_filtered(device, queue, img, label, srgb, Filtering::Mipmapped)
+    }
+
+    pub fn from_image_filtered(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        srgb: bool,
+        filtering: Filtering,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let options = TextureOptions {
+            srgb,
+            ..filtering.into()
+        };
+        Self::from_image_with(device, queue, img, label, options)
+    }
+
+    pub fn from_image_with(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        Self::from_layers(
+            device,
+            queue,
+            &[img],
+            wgpu::TextureViewDimension::D2,
+            label,
+            options,
+        )
+    }
+
+    /// A cube map from six square images, in wgpu's face order: +X, -X, +Y,
+    /// -Y, +Z, -Z. They must all be the same size and kind of image.
+    pub fn cube_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: Option<&str>,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        let (width, height) = faces[0].dimensions();
+        ensure!(
+            width == height,
+            "Cube map faces must be square, not {width}x{height}"
+        );
+        Self::from_layers(
+            device,
+            queue,
+            &faces.each_ref(),
+            wgpu::TextureViewDimension::Cube,
+            label,
+            options,
+        )
+    }
+
+    /// Loads the six faces of a cube map, in the order of
+    /// [`Texture::cube_from_images`].
+    pub fn cube_from_paths<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        paths: &[P; 6],
+        options: TextureOptions,
+    ) -> Result<Self> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            let path = path.as_ref();
+            let img = std::fs::read(path)
+                .map_err(Error::from)
+                .and_then(|bytes| decode(&bytes))
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+            faces.push(img);
+        }
+        let faces: [image::DynamicImage; 6] = faces.try_into().unwrap();
+        let label = paths[0].as_ref().to_str();
+        Self::cube_from_images(device, queue, &faces, label, options)
+    }
+
+    /// An empty cube map with faces of `size` texels, to render into one face
+    /// and mip level at a time, or to write texels into.
+    pub fn create_cube(
+        device: &wgpu::Device,
+        size: u32,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler =
+            device.create_sampler(&TextureOptions::data().sampler_descriptor(Some(label), true));
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// A view of one face and mip level of a cube map, to render into.
+    pub fn face_view(&self, face: u32, mip_level: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: mip_level,
+            mip_level_count: Some(1),
+            base_array_layer: face,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    /// Uploads images of the same size and kind as the layers of one texture.
+    fn from_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[&image::DynamicImage],
+        dimension: wgpu::TextureViewDimension,
+        label: Option<&str>,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        let dimensions = layers[0].dimensions();
+        let prepared: Vec<_> = layers
+            .iter()
+            .map(|img| prepare(img, &options, device.features()))
+            .collect();
+        let format = prepared[0].0;
+        for (img, (layer_format, _)) in layers.iter().zip(&prepared) {
+            ensure!(
+                img.dimensions() == dimensions && *layer_format == format,
+                "Layers must all be the same size and kind of image"
+            );
+        }
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: layers.len() as u32,
         };
-        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: prepared[0].1.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            usage: options.usage | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
+        let block_size = format.block_copy_size(None).unwrap();
+        for (layer, (_, levels)) in prepared.iter().enumerate() {
+            for (mip_level, (width, height, level)) in levels.iter().enumerate() {
+                let (width, height) = (*width, *height);
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level: mip_level as u32,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                    },
+                    level,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(block_size * width),
+                        rows_per_image: Some(height),
+                    },
+                    wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
             ..Default::default()
         });
+        let filterable = format
+            .guaranteed_format_features(device.features())
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE);
+        let sampler = device.create_sampler(&options.sampler_descriptor(label, filterable));
 
         Ok(Self {
             texture,
@@ -0,0 +1,105 @@
+mod common;
+
+use common::{
+    assert_golden, blended_pixels, glowing, show_alone, small_headless, square, Tolerance,
+    SMALL_HEIGHT, SMALL_WIDTH,
+};
+use rustgl::{headless::Headless, Antialiasing, PostSettings};
+
+/// A white square on black, turned `angle` radians about the view axis
+fn white_square(angle: f32) -> Headless {
+    let mut headless = small_headless();
+    let square = square(&headless, 0.5, angle, 0.0, glowing([1.0; 3]));
+    show_alone(&mut headless, square);
+    headless
+}
+
+/// Turned a little, so its edges cut across pixels at an angle
+fn tilted_square() -> Headless {
+    white_square(0.3)
+}
+
+fn set_antialiasing(headless: &mut Headless, antialiasing: Antialiasing) {
+    headless.renderer.post.set_settings(
+        &headless.device,
+        PostSettings {
+            antialiasing,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn fxaa_smooths_edges() {
+    let mut headless = tilted_square();
+    let aliased = headless.render().unwrap();
+    assert_eq!(blended_pixels(&aliased), 0);
+
+    set_antialiasing(&mut headless, Antialiasing::Fxaa);
+    let smooth = headless.render().unwrap();
+    assert!(blended_pixels(&smooth) > 50, "{}", blended_pixels(&smooth));
+    assert_golden("fxaa_square", &smooth, Tolerance::default());
+
+    // Flat areas are left alone
+    assert_eq!(smooth.get_pixel(0, 0).0, [0, 0, 0, 255]);
+    assert_eq!(
+        smooth.get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 2).0,
+        [255; 4]
+    );
+}
+
+#[test]
+fn smaa_smooths_edges() {
+    let mut headless = tilted_square();
+    set_antialiasing(&mut headless, Antialiasing::Smaa);
+    let smooth = headless.render().unwrap();
+    assert!(blended_pixels(&smooth) > 50, "{}", blended_pixels(&smooth));
+    assert_golden("smaa_square", &smooth, Tolerance::default());
+    assert_eq!(smooth.get_pixel(0, 0).0, [0, 0, 0, 255]);
+    assert_eq!(
+        smooth.get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 2).0,
+        [255; 4]
+    );
+}
+
+#[test]
+fn smaa_keeps_straight_edges_sharp() {
+    // Without the tilt the edges run along the pixel grid, so the only
+    // shapes to smooth are the corners, which are kept mostly sharp
+    let mut headless = white_square(0.0);
+    let aliased = headless.render().unwrap();
+    set_antialiasing(&mut headless, Antialiasing::Smaa);
+    let frame = headless.render().unwrap();
+    for (a, b) in aliased.pixels().zip(frame.pixels()) {
+        assert!(a[0].abs_diff(b[0]) <= 32, "{a:?} {b:?}");
+    }
+
+    // Halfway along the top edge, furthest from the corners
+    let top = (0..SMALL_HEIGHT)
+        .find(|&y| aliased.get_pixel(SMALL_WIDTH / 2, y)[0] == 255)
+        .unwrap();
+    assert!(frame.get_pixel(SMALL_WIDTH / 2, top)[0] >= 248);
+    assert_eq!(frame.get_pixel(SMALL_WIDTH / 2, top - 1)[0], 0);
+}
+
+#[test]
+fn antialiasing_switches_at_runtime() {
+    let mut headless = tilted_square();
+    let aliased = headless.render().unwrap();
+    for antialiasing in Antialiasing::ALL {
+        set_antialiasing(&mut headless, antialiasing);
+        // Resizing reallocates the intermediate targets
+        headless
+            .renderer
+            .resize(&headless.device, SMALL_WIDTH, SMALL_HEIGHT);
+        let frame = headless.render().unwrap();
+        assert_eq!(
+            frame == aliased,
+            antialiasing == Antialiasing::None,
+            "{antialiasing:?}"
+        );
+    }
+    assert_eq!(Antialiasing::Smaa.next(), Antialiasing::None);
+    assert_eq!("SMAA".parse::<Antialiasing>().unwrap(), Antialiasing::Smaa);
+    assert!("msaa".parse::<Antialiasing>().is_err());
+}
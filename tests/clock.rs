@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use rustgl::FrameClock;
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn fixed_steps_add_up_to_simulated_time() {
+    let mut clock = FrameClock::new();
+    clock.fixed_step = ms(10);
+
+    let time = clock.advance(ms(25));
+    assert_eq!(time.fixed_steps, 2);
+    assert!((time.alpha - 0.5).abs() < 1e-5);
+    assert!((time.delta - 0.025).abs() < 1e-6);
+
+    // The leftover 5ms carry over into the next frame
+    let time = clock.advance(ms(7));
+    assert_eq!(time.fixed_steps, 1);
+    assert!((time.alpha - 0.2).abs() < 1e-5);
+
+    // Frame times don't matter, only their sum
+    let mut steps = 0;
+    for _ in 0..100 {
+        steps += clock.advance(Duration::from_micros(3_333)).fixed_steps;
+    }
+    assert_eq!(steps, 33);
+    assert_eq!(clock.elapsed(), ms(32) + Duration::from_micros(333_300));
+}
+
+#[test]
+fn long_frames_are_clamped() {
+    let mut clock = FrameClock::new();
+    clock.fixed_step = ms(10);
+    clock.max_delta = ms(100);
+    let time = clock.advance(Duration::from_secs(5));
+    assert_eq!(time.fixed_steps, 10);
+    assert!((time.real_delta - 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn pause_and_single_step() {
+    let mut clock = FrameClock::new();
+    clock.fixed_step = ms(10);
+    clock.set_paused(true);
+
+    let time = clock.advance(ms(50));
+    assert_eq!(time.fixed_steps, 0);
+    assert_eq!(time.delta, 0.0);
+    // Real time still passes, e.g. for moving the camera
+    assert!((time.real_delta - 0.05).abs() < 1e-6);
+
+    clock.step();
+    clock.step();
+    let time = clock.advance(ms(1));
+    assert_eq!(time.fixed_steps, 2);
+    assert!((time.delta - 0.02).abs() < 1e-6);
+    assert_eq!(clock.advance(ms(50)).fixed_steps, 0);
+    assert_eq!(clock.elapsed(), ms(20));
+
+    // Stepping does nothing while running
+    clock.set_paused(false);
+    clock.step();
+    assert_eq!(clock.advance(ms(10)).fixed_steps, 1);
+}
+
+#[test]
+fn time_scale() {
+    let mut clock = FrameClock::new();
+    clock.fixed_step = ms(10);
+
+    clock.set_time_scale(0.5);
+    let time = clock.advance(ms(40));
+    assert_eq!(time.fixed_steps, 2);
+    assert!((time.delta - 0.02).abs() < 1e-6);
+    assert!((time.real_delta - 0.04).abs() < 1e-6);
+
+    clock.set_time_scale(3.0);
+    assert_eq!(clock.advance(ms(10)).fixed_steps, 3);
+
+    clock.set_time_scale(-1.0);
+    assert_eq!(clock.time_scale(), 0.0);
+    assert_eq!(clock.advance(ms(100)).fixed_steps, 0);
+}
+
+#[test]
+fn first_tick_reports_no_time() {
+    let mut clock = FrameClock::new();
+    let time = clock.tick();
+    assert_eq!(time.real_delta, 0.0);
+    assert_eq!(time.fixed_steps, 0);
+}
@@ -0,0 +1,206 @@
+//! Golden-image helpers and scene fixtures shared by the integration tests.
+//!
+//! Reference images live in `tests/golden/`. Set `UPDATE_GOLDEN=1` to
+//! (re)write them from the current render instead of comparing.
+
+// Each test file uses only some of the helpers
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use image::{Rgba, RgbaImage};
+use rustgl::{
+    headless::Headless,
+    model::{Material, MaterialMaps, MaterialParams, Mesh, Model},
+    Vertex,
+};
+
+/// Size of most test renders
+pub const WIDTH: u32 = 256;
+pub const HEIGHT: u32 = 192;
+/// Size of renders that only look at a few simple shapes
+pub const SMALL_WIDTH: u32 = 128;
+pub const SMALL_HEIGHT: u32 = 96;
+
+pub fn headless_sized(width: u32, height: u32) -> Headless {
+    pollster::block_on(Headless::new(width, height)).unwrap()
+}
+
+/// A renderer of `WIDTH` x `HEIGHT` showing the default scene
+pub fn headless() -> Headless {
+    headless_sized(WIDTH, HEIGHT)
+}
+
+/// A renderer of `SMALL_WIDTH` x `SMALL_HEIGHT` showing the default scene
+pub fn small_headless() -> Headless {
+    headless_sized(SMALL_WIDTH, SMALL_HEIGHT)
+}
+
+/// Material of a square that glows with `color` over a black base, so it
+/// looks the same however it's lit
+pub fn glowing(color: [f32; 3]) -> MaterialParams {
+    MaterialParams {
+        base_color: [0.0, 0.0, 0.0, 1.0],
+        emissive: color,
+        ..Default::default()
+    }
+}
+
+/// A square facing +z at depth `z`, `half_size` from its center to each
+/// side and turned `angle` radians about the view axis
+pub fn square(
+    headless: &Headless,
+    half_size: f32,
+    angle: f32,
+    z: f32,
+    params: MaterialParams,
+) -> Model {
+    let (sin, cos) = angle.sin_cos();
+    let vertices: Vec<Vertex> = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+        .into_iter()
+        .map(|(x, y): (f32, f32)| Vertex {
+            position: [
+                (x * cos - y * sin) * half_size,
+                (x * sin + y * cos) * half_size,
+                z,
+            ],
+            tex_coords: [(x + 1.0) / 2.0, (1.0 - y) / 2.0],
+            normal: [0.0, 0.0, 1.0],
+        })
+        .collect();
+    let mesh = Mesh::new(
+        &headless.device,
+        "square",
+        &vertices,
+        &[0, 1, 2, 0, 2, 3],
+        0,
+    );
+    let material = Material::new(
+        &headless.device,
+        "square",
+        MaterialMaps::default(),
+        params,
+        &headless.renderer.material_layout,
+    );
+    Model::new(&headless.device, vec![mesh], vec![material])
+}
+
+/// Replaces the scene with `model` alone, seen head-on in front of a black
+/// background with the lights off
+pub fn show_alone(headless: &mut Headless, model: Model) {
+    let renderer = &mut headless.renderer;
+    renderer.models = vec![model];
+    renderer.lights.clear();
+    renderer.clear_color = wgpu::Color::BLACK;
+    renderer.camera.eye = (0.0, 0.0, 2.0).into();
+}
+
+/// Pixels that are neither black nor white, like those blended on the edges
+/// of a white square on black
+pub fn blended_pixels(frame: &RgbaImage) -> usize {
+    frame
+        .pixels()
+        .filter(|pixel| (8..248).contains(&pixel[0]))
+        .count()
+}
+
+/// Pixels differing by more than a few levels in any channel
+pub fn mismatched_pixels(a: &RgbaImage, b: &RgbaImage) -> usize {
+    a.pixels()
+        .zip(b.pixels())
+        .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > 3))
+        .count()
+}
+
+/// How far a render may drift from its reference before the test fails.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    /// Largest allowed absolute difference of any channel of a pixel.
+    pub per_channel: u8,
+    /// Fraction of pixels allowed to exceed `per_channel`, to absorb
+    /// rasterization differences on triangle edges between drivers.
+    pub max_mismatch_ratio: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            per_channel: 3,
+            max_mismatch_ratio: 0.002,
+        }
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn output_path(name: &str, suffix: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join("golden")
+        .join(format!("{name}.{suffix}.png"))
+}
+
+/// Compares `actual` against `tests/golden/<name>.png`.
+///
+/// On failure the render and a diff image (mismatching pixels in red over a
+/// faded copy of the reference) are written next to the test binaries and
+/// their paths are included in the panic message.
+pub fn assert_golden(name: &str, actual: &RgbaImage, tolerance: Tolerance) {
+    let golden = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&golden).unwrap();
+        eprintln!("updated {}", golden.display());
+        return;
+    }
+
+    let expected = image::open(&golden)
+        .unwrap_or_else(|e| {
+            panic!(
+                "missing reference {} ({e}), run with UPDATE_GOLDEN=1 to create it",
+                golden.display()
+            )
+        })
+        .to_rgba8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{name}: render size differs from reference"
+    );
+
+    let mut diff = RgbaImage::new(expected.width(), expected.height());
+    let mut mismatched = 0usize;
+    for ((e, a), d) in expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let max_delta = e.0.iter().zip(a.0).map(|(e, a)| e.abs_diff(a)).max();
+        if max_delta.unwrap_or(0) > tolerance.per_channel {
+            mismatched += 1;
+            *d = Rgba([255, 0, 0, 255]);
+        } else {
+            let [r, g, b, _] = e.0.map(|c| c / 4);
+            *d = Rgba([r, g, b, 255]);
+        }
+    }
+
+    let ratio = mismatched as f32 / (expected.width() * expected.height()) as f32;
+    if ratio > tolerance.max_mismatch_ratio {
+        let actual_path = output_path(name, "actual");
+        let diff_path = output_path(name, "diff");
+        std::fs::create_dir_all(diff_path.parent().unwrap()).unwrap();
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "{name}: {mismatched} pixels ({:.3}%) differ by more than {} from {}\n  actual: {}\n  diff:   {}",
+            ratio * 100.0,
+            tolerance.per_channel,
+            golden.display(),
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use cgmath::{assert_abs_diff_eq, InnerSpace, Point3, Vector3};
+use rustgl::{
+    Camera, CameraController, CameraMode, FlyController, OrbitController, ProjectionMode,
+};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{
+        DeviceEvent, DeviceId, ElementState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent,
+    },
+    keyboard::KeyCode,
+};
+
+/// One frame at 60 frames per second
+const DT: f32 = 1.0 / 60.0;
+
+fn camera() -> Camera {
+    Camera {
+        eye: (0.0, 0.0, 5.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: Vector3::unit_y(),
+        aspect: 4.0 / 3.0,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    }
+}
+
+/// Runs updates until the controller has applied all its input
+fn settle(controller: &mut OrbitController, camera: &mut Camera) {
+    for _ in 0..1000 {
+        controller.update_camera(camera, DT);
+        if !controller.is_moving() {
+            return;
+        }
+    }
+    panic!("controller never settled");
+}
+
+fn distance(camera: &Camera) -> f32 {
+    (camera.eye - camera.target).magnitude()
+}
+
+#[test]
+fn rotation_keeps_distance_and_target() {
+    let mut camera = camera();
+    let mut controller = OrbitController::new();
+    controller.rotate(200.0, 100.0);
+    settle(&mut controller, &mut camera);
+
+    assert_abs_diff_eq!(distance(&camera), 5.0, epsilon = 1e-4);
+    assert_eq!(camera.target, Point3::new(0.0, 0.0, 0.0));
+    // Dragging right and down moves the eye left and above the target
+    assert!(camera.eye.x < 0.0);
+    assert!(camera.eye.y > 0.0);
+}
+
+#[test]
+fn pitch_is_clamped_below_up() {
+    let mut camera = camera();
+    let mut controller = OrbitController::new();
+    for _ in 0..10 {
+        controller.rotate(0.0, 1000.0);
+        settle(&mut controller, &mut camera);
+        let direction = (camera.eye - camera.target).normalize();
+        let pitch = direction.dot(camera.up).asin();
+        assert!(pitch <= controller.max_pitch.0 + 1e-4, "{pitch}");
+        // Still in front of the target, so the view hasn't flipped over
+        assert!(camera.eye.z > 0.0);
+    }
+
+    controller.rotate(0.0, -5000.0);
+    settle(&mut controller, &mut camera);
+    let direction = (camera.eye - camera.target).normalize();
+    assert!(direction.dot(camera.up).asin() >= -controller.max_pitch.0 - 1e-4);
+    assert!(camera.eye.z > 0.0);
+}
+
+#[test]
+fn pan_moves_eye_and_target_together() {
+    let mut camera = camera();
+    let mut controller = OrbitController::new();
+    controller.pan(100.0, 0.0);
+    settle(&mut controller, &mut camera);
+
+    // The scene follows the cursor to the right, so the camera moves left
+    assert!(camera.target.x < 0.0);
+    assert_abs_diff_eq!(camera.eye.x, camera.target.x, epsilon = 1e-5);
+    assert_abs_diff_eq!(distance(&camera), 5.0, epsilon = 1e-4);
+}
+
+#[test]
+fn zoom_is_clamped() {
+    let mut camera = camera();
+    let mut controller = OrbitController::new();
+    controller.zoom(2.0);
+    settle(&mut controller, &mut camera);
+    assert!(distance(&camera) < 5.0);
+
+    controller.zoom(1000.0);
+    settle(&mut controller, &mut camera);
+    assert_abs_diff_eq!(distance(&camera), controller.min_distance, epsilon = 1e-4);
+
+    controller.zoom(-1000.0);
+    settle(&mut controller, &mut camera);
+    assert_abs_diff_eq!(distance(&camera), controller.max_distance, epsilon = 1e-1);
+}
+
+#[test]
+fn damping_eases_to_the_same_result() {
+    let mut eased = camera();
+    let mut controller = OrbitController::new();
+    controller.rotate(150.0, -50.0);
+    controller.zoom(1.0);
+    controller.update_camera(&mut eased, DT);
+    // Only part of the input is applied at first, the rest over later updates
+    assert!(controller.is_moving());
+    settle(&mut controller, &mut eased);
+
+    let mut immediate = camera();
+    let mut controller = OrbitController::new();
+    controller.damping = 0.0;
+    controller.rotate(150.0, -50.0);
+    controller.zoom(1.0);
+    controller.update_camera(&mut immediate, DT);
+    assert!(!controller.is_moving());
+
+    assert_abs_diff_eq!(eased.eye, immediate.eye, epsilon = 1e-3);
+}
+
+#[test]
+fn damping_is_frame_rate_independent() {
+    let run = |frames: u32| {
+        let mut camera = camera();
+        let mut controller = OrbitController::new();
+        controller.rotate(150.0, -50.0);
+        controller.zoom(1.0);
+        for _ in 0..frames {
+            controller.update_camera(&mut camera, 0.1 / frames as f32);
+        }
+        camera
+    };
+    let slow = run(3);
+    let fast = run(24);
+    assert_abs_diff_eq!(slow.eye, fast.eye, epsilon = 1e-4);
+}
+
+#[test]
+fn mouse_events_drive_the_controller() {
+    // SAFETY: only used to build synthetic events
+    let device_id = unsafe { DeviceId::dummy() };
+    let moved = |x, y| WindowEvent::CursorMoved {
+        device_id,
+        position: PhysicalPosition::new(x, y),
+    };
+    let button = |button, state| WindowEvent::MouseInput {
+        device_id,
+        state,
+        button,
+    };
+
+    let mut camera = camera();
+    let mut controller = OrbitController::new();
+    controller.damping = 0.0;
+
+    // Moving without a button held does nothing
+    assert!(!controller.process_events(&moved(10.0, 10.0)));
+    assert!(!controller.process_events(&moved(50.0, 10.0)));
+    controller.update_camera(&mut camera, DT);
+    assert_eq!(camera.eye, Point3::new(0.0, 0.0, 5.0));
+
+    assert!(controller.process_events(&button(MouseButton::Left, ElementState::Pressed)));
+    assert!(controller.process_events(&moved(90.0, 10.0)));
+    assert!(controller.process_events(&button(MouseButton::Left, ElementState::Released)));
+    controller.update_camera(&mut camera, DT);
+    assert!(camera.eye.x < 0.0);
+    assert_eq!(camera.target, Point3::new(0.0, 0.0, 0.0));
+
+    assert!(controller.process_events(&button(MouseButton::Middle, ElementState::Pressed)));
+    assert!(controller.process_events(&moved(90.0, 50.0)));
+    assert!(controller.process_events(&button(MouseButton::Middle, ElementState::Released)));
+    controller.update_camera(&mut camera, DT);
+    assert!(camera.target.y > 0.0);
+
+    let before = distance(&camera);
+    assert!(controller.process_events(&WindowEvent::MouseWheel {
+        device_id,
+        delta: MouseScrollDelta::LineDelta(0.0, 1.0),
+        phase: TouchPhase::Moved,
+    }));
+    controller.update_camera(&mut camera, DT);
+    assert!(distance(&camera) < before);
+}
+
+#[test]
+fn fly_look_keeps_eye_and_target_distance() {
+    let mut camera = camera();
+    let mut controller = FlyController::new();
+    controller.look(100.0, 50.0);
+    controller.update_camera(&mut camera, DT);
+
+    assert_eq!(camera.eye, Point3::new(0.0, 0.0, 5.0));
+    assert_abs_diff_eq!(distance(&camera), 5.0, epsilon = 1e-4);
+    // Moving the mouse right and down looks right and down
+    assert!(camera.target.x > 0.0);
+    assert!(camera.target.y < 0.0);
+
+    for _ in 0..10 {
+        controller.look(0.0, -1000.0);
+        controller.update_camera(&mut camera, DT);
+        let forward = (camera.target - camera.eye).normalize();
+        assert!(forward.dot(camera.up).asin() <= controller.max_pitch.0 + 1e-4);
+        assert!(camera.target.z < camera.eye.z);
+    }
+}
+
+#[test]
+fn fly_moves_relative_to_view() {
+    let mut camera = camera();
+    let mut controller = FlyController::new();
+    // Face down the x axis
+    controller.look(-std::f32::consts::FRAC_PI_2 / controller.sensitivity, 0.0);
+    controller.update_camera(&mut camera, DT);
+    assert_abs_diff_eq!(camera.target, Point3::new(-5.0, 0.0, 5.0), epsilon = 1e-4);
+
+    assert!(controller.process_key(KeyCode::KeyW, true));
+    controller.update_camera(&mut camera, DT);
+    assert_abs_diff_eq!(
+        camera.eye,
+        Point3::new(-controller.speed * DT, 0.0, 5.0),
+        epsilon = 1e-5
+    );
+    assert_abs_diff_eq!(distance(&camera), 5.0, epsilon = 1e-4);
+
+    controller.process_key(KeyCode::KeyW, false);
+    controller.process_key(KeyCode::KeyD, true);
+    controller.update_camera(&mut camera, DT);
+    // Right of facing -x is -z
+    assert_abs_diff_eq!(camera.eye.z, 5.0 - controller.speed * DT, epsilon = 1e-5);
+
+    controller.process_key(KeyCode::KeyD, false);
+    controller.process_key(KeyCode::Space, true);
+    let before = camera.eye;
+    controller.update_camera(&mut camera, DT);
+    assert_abs_diff_eq!(
+        camera.eye - before,
+        camera.up * controller.speed * DT,
+        epsilon = 1e-5
+    );
+
+    assert!(!controller.process_key(KeyCode::KeyQ, true));
+}
+
+#[test]
+fn raw_mouse_motion_turns_fly_camera() {
+    let mut camera = camera();
+    let mut controller = CameraController::new();
+    let motion = DeviceEvent::MouseMotion { delta: (40.0, 0.0) };
+
+    // Ignored while orbiting
+    assert!(!controller.process_device_events(&motion));
+    controller.set_mode(CameraMode::Fly);
+    assert!(controller.mode_changed());
+    assert!(!controller.mode_changed());
+    assert!(controller.process_device_events(&motion));
+    controller.update_camera(&mut camera, DT);
+    assert!(camera.target.x > 0.0);
+}
+
+#[test]
+fn switching_modes_does_not_jump() {
+    let mut camera = camera();
+    let mut controller = CameraController::new();
+    controller.orbit.rotate(80.0, 40.0);
+    controller.update_camera(&mut camera, DT);
+    // Leave some of the orbit still to be applied
+    assert!(controller.orbit.is_moving());
+
+    let before = camera;
+    controller.set_mode(CameraMode::Fly);
+    controller.update_camera(&mut camera, DT);
+    assert_abs_diff_eq!(camera.eye, before.eye, epsilon = 1e-5);
+    assert_abs_diff_eq!(camera.target, before.target, epsilon = 1e-5);
+
+    controller.fly.look(30.0, 0.0);
+    controller.fly.process_key(KeyCode::KeyW, true);
+    controller.update_camera(&mut camera, DT);
+    let before = camera;
+    controller.set_mode(CameraMode::Orbit);
+    controller.update_camera(&mut camera, DT);
+    assert_abs_diff_eq!(camera.eye, before.eye, epsilon = 1e-5);
+    assert_abs_diff_eq!(camera.target, before.target, epsilon = 1e-5);
+
+    // Keys held in fly mode are forgotten on the way out
+    controller.set_mode(CameraMode::Fly);
+    controller.update_camera(&mut camera, DT);
+    assert_abs_diff_eq!(camera.eye, before.eye, epsilon = 1e-5);
+}
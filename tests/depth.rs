@@ -0,0 +1,110 @@
+mod common;
+
+use std::path::PathBuf;
+
+use cgmath::Vector3;
+use common::{glowing, mismatched_pixels, small_headless, square, SMALL_HEIGHT, SMALL_WIDTH};
+use rustgl::{Camera, DepthSettings, ProjectionMode};
+
+#[test]
+fn reverse_z_is_the_default() {
+    let headless = small_headless();
+    assert_eq!(
+        headless.renderer.camera.projection,
+        ProjectionMode::ReverseZ
+    );
+    assert_eq!(
+        headless.renderer.depth_settings(),
+        DepthSettings::reverse_z()
+    );
+    assert_eq!(DepthSettings::default(), DepthSettings::reverse_z());
+    assert_eq!(
+        DepthSettings::reverse_z().format,
+        wgpu::TextureFormat::Depth32Float
+    );
+}
+
+#[test]
+fn reversed_depth_settings() {
+    let reversed = DepthSettings::standard().reversed();
+    assert!(reversed.is_reverse_z());
+    assert_eq!(reversed.compare, wgpu::CompareFunction::Greater);
+    assert_eq!(reversed.clear_value, 0.0);
+    assert_eq!(reversed.reversed(), DepthSettings::standard());
+
+    let less_equal = DepthSettings {
+        compare: wgpu::CompareFunction::LessEqual,
+        ..DepthSettings::standard()
+    };
+    assert_eq!(
+        less_equal.reversed().compare,
+        wgpu::CompareFunction::GreaterEqual
+    );
+}
+
+#[test]
+fn depth_test_follows_camera() {
+    let mut headless = small_headless();
+    headless.renderer.camera.projection = ProjectionMode::Perspective;
+    headless.render().unwrap();
+    let depth = headless.renderer.depth_settings();
+    assert_eq!(depth.compare, wgpu::CompareFunction::Less);
+    assert_eq!(depth.clear_value, 1.0);
+
+    headless.renderer.camera.projection = ProjectionMode::ReverseZ;
+    headless.render().unwrap();
+    assert_eq!(
+        headless.renderer.depth_settings(),
+        DepthSettings::reverse_z()
+    );
+}
+
+#[test]
+fn reverse_z_matches_standard_depth() {
+    let mut headless = small_headless();
+    let cube = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets/cube.obj");
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, &[cube])
+        .unwrap();
+    headless.renderer.camera.eye = (1.5, 1.2, 2.5).into();
+
+    let reverse_z = headless.render().unwrap();
+    headless.renderer.camera.projection = ProjectionMode::Perspective;
+    let standard = headless.render().unwrap();
+
+    let mismatched = mismatched_pixels(&reverse_z, &standard);
+    assert!(
+        mismatched < (SMALL_WIDTH * SMALL_HEIGHT / 200) as usize,
+        "{mismatched}"
+    );
+}
+
+#[test]
+fn nearer_surface_wins_far_away() {
+    let mut headless = small_headless();
+    // Half a metre apart, a kilometre away, seen with a 10cm near plane.
+    // The back square is drawn first so it has to lose the depth test.
+    let front = square(&headless, 600.0, 0.0, -1000.0, glowing([1.0, 0.0, 0.0]));
+    let back = square(&headless, 600.0, 0.0, -1000.5, glowing([0.0, 1.0, 0.0]));
+    let renderer = &mut headless.renderer;
+    renderer.models = vec![back, front];
+    renderer.lights.clear();
+    renderer.lights.set_ambient([0.0; 3]);
+    renderer.camera = Camera {
+        eye: (0.0, 0.0, 0.0).into(),
+        target: (0.0, 0.0, -1.0).into(),
+        up: Vector3::unit_y(),
+        aspect: SMALL_WIDTH as f32 / SMALL_HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::ReverseZ,
+    };
+
+    let frame = headless.render().unwrap();
+    for pixel in frame.pixels() {
+        let [r, g, _, _] = pixel.0;
+        assert!(r > 200 && g < 10, "back square shows through: {pixel:?}");
+    }
+}
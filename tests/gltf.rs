@@ -0,0 +1,299 @@
+mod common;
+
+use std::path::PathBuf;
+
+use cgmath::{Matrix4, Quaternion, Rad, Rotation3, Vector3};
+use common::{assert_golden, headless, Tolerance, HEIGHT, WIDTH};
+use rustgl::{headless::Headless, scene::Scene, Camera, ProjectionMode};
+
+fn asset(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("assets")
+        .join(name)
+}
+
+fn load(headless: &Headless, name: &str) -> Scene {
+    Scene::load_gltf(
+        &headless.device,
+        &headless.queue,
+        &headless.renderer.material_layout,
+        asset(name),
+    )
+    .unwrap()
+}
+
+fn assert_matrix_eq(a: Matrix4<f32>, b: Matrix4<f32>) {
+    let a: &[f32; 16] = a.as_ref();
+    let b: &[f32; 16] = b.as_ref();
+    for (a, b) in a.iter().zip(b) {
+        assert!((a - b).abs() < 1e-5, "{a:?} != {b:?}");
+    }
+}
+
+#[test]
+fn gltf_and_glb_node_hierarchy() {
+    let headless = headless();
+    for file in ["quads.gltf", "quads.glb"] {
+        let scene = load(&headless, file);
+
+        let names: Vec<_> = scene.nodes.iter().map(|n| n.name.as_deref()).collect();
+        assert_eq!(
+            names,
+            [Some("root"), Some("left"), Some("right"), Some("right_top")],
+            "{file}"
+        );
+        assert_eq!(scene.roots, [0]);
+        assert_eq!(scene.nodes[0].children, [1, 2]);
+        assert_eq!(scene.nodes[2].children, [3]);
+
+        let root = Matrix4::from_translation(Vector3::new(0.0, -0.1, 0.0));
+        let right = Matrix4::from_translation(Vector3::new(0.5, 0.0, 0.0))
+            * Matrix4::from(Quaternion::from_angle_y(Rad(15f32.to_radians())));
+        let right_top =
+            Matrix4::from_translation(Vector3::new(0.0, 0.75, 0.0)) * Matrix4::from_scale(0.5);
+        assert_matrix_eq(scene.nodes[3].transform, right_top);
+        assert_matrix_eq(scene.nodes[3].world_transform, root * right * right_top);
+
+        // Three nodes reference the two-primitive mesh
+        assert_eq!(scene.model.meshes.len(), 6);
+        assert!(scene.nodes[0].meshes.is_empty());
+        for node in &scene.nodes[1..] {
+            let materials: Vec<_> = node
+                .meshes
+                .iter()
+                .map(|&m| scene.model.meshes[m].material)
+                .collect();
+            assert_eq!(materials, [0, 1], "{file}");
+        }
+    }
+}
+
+#[test]
+fn gltf_and_glb_materials() {
+    let headless = headless();
+    for file in ["quads.gltf", "quads.glb"] {
+        let scene = load(&headless, file);
+        let [tree, checker, default] = &scene.model.materials[..] else {
+            panic!("{file}: expected two materials and the default");
+        };
+
+        let size = |texture: &Option<rustgl::texture::Texture>| {
+            texture.as_ref().map(|t| t.texture.size().width)
+        };
+
+        assert_eq!(tree.name, "tree");
+        assert_eq!(tree.params().metallic, 0.0);
+        assert_eq!(tree.params().roughness, 0.8);
+        assert_eq!(tree.params().emissive, [0.2; 3]);
+        assert_eq!(size(&tree.maps.base_color), Some(256));
+        assert_eq!(size(&tree.maps.emissive), Some(256));
+        assert!(tree.maps.normal.is_none());
+        assert!(tree.maps.occlusion.is_none());
+
+        assert_eq!(checker.name, "checker");
+        assert_eq!(checker.params().base_color, [1.0; 4]);
+        assert_eq!(checker.params().metallic, 0.25);
+        assert_eq!(checker.params().roughness, 0.5);
+        assert_eq!(checker.params().normal_scale, 0.75);
+        assert_eq!(checker.params().occlusion_strength, 0.5);
+        assert_eq!(checker.params().emissive, [0.0; 3]);
+        assert_eq!(size(&checker.maps.base_color), Some(4));
+        assert_eq!(size(&checker.maps.normal), Some(1));
+        assert_eq!(size(&checker.maps.occlusion), Some(4));
+        assert!(checker.maps.metallic_roughness.is_none());
+        // Data maps are linear, colors sRGB
+        let format =
+            |texture: &Option<rustgl::texture::Texture>| texture.as_ref().unwrap().texture.format();
+        assert_eq!(
+            format(&checker.maps.base_color),
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        );
+        assert_eq!(
+            format(&checker.maps.normal),
+            wgpu::TextureFormat::Rgba8Unorm
+        );
+        assert_eq!(
+            format(&checker.maps.occlusion),
+            wgpu::TextureFormat::Rgba8Unorm
+        );
+
+        assert_eq!(default.name, "default");
+        assert_eq!(default.params().metallic, 1.0);
+    }
+}
+
+#[test]
+fn gltf_render() {
+    let camera = Camera {
+        eye: (0.0, 0.3, 2.0).into(),
+        target: (0.0, 0.1, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    };
+    for file in ["quads.gltf", "quads.glb"] {
+        let mut headless = headless();
+        headless
+            .renderer
+            .load_models(&headless.device, &headless.queue, &[asset(file)])
+            .unwrap();
+        headless.renderer.camera = camera;
+        let frame = headless.render().unwrap();
+        // Both files describe the same scene
+        assert_golden("quads_gltf", &frame, Tolerance::default());
+    }
+}
+
+/// Two quads side by side: the left one with a grayscale base color map,
+/// the right one with a grayscale metallic-roughness map, both gradients
+/// from black on the left to white on the right
+fn gray_maps_gltf() -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("gray_maps");
+    std::fs::create_dir_all(&dir).unwrap();
+    let gradient = image::GrayImage::from_fn(16, 16, |x, _| image::Luma([x as u8 * 17]));
+    gradient.save(dir.join("base_color.png")).unwrap();
+    gradient.save(dir.join("metallic_roughness.png")).unwrap();
+
+    let positions = [
+        [-0.5f32, -0.5, 0.0],
+        [0.5, -0.5, 0.0],
+        [0.5, 0.5, 0.0],
+        [-0.5, 0.5, 0.0],
+    ];
+    let normals = [[0.0f32, 0.0, 1.0]; 4];
+    let tex_coords = [[0.0f32, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+    let indices = [0u16, 1, 2, 0, 2, 3];
+    let mut bin = Vec::new();
+    bin.extend_from_slice(bytemuck::cast_slice(&positions));
+    bin.extend_from_slice(bytemuck::cast_slice(&normals));
+    bin.extend_from_slice(bytemuck::cast_slice(&tex_coords));
+    bin.extend_from_slice(bytemuck::cast_slice(&indices));
+    std::fs::write(dir.join("quad.bin"), &bin).unwrap();
+
+    let primitive = |material: u32| {
+        format!(
+            r#"{{"primitives": [{{"attributes": {{"POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2}},
+                "indices": 3, "material": {material}}}]}}"#
+        )
+    };
+    let gltf = format!(
+        r#"{{
+            "asset": {{"version": "2.0"}},
+            "scene": 0,
+            "scenes": [{{"nodes": [0, 1]}}],
+            "nodes": [
+                {{"mesh": 0, "translation": [-0.55, 0, 0]}},
+                {{"mesh": 1, "translation": [0.55, 0, 0]}}
+            ],
+            "meshes": [{}, {}],
+            "materials": [
+                {{"pbrMetallicRoughness": {{"baseColorTexture": {{"index": 0}}, "metallicFactor": 0}}}},
+                {{"pbrMetallicRoughness": {{"metallicRoughnessTexture": {{"index": 1}}}}}}
+            ],
+            "textures": [{{"source": 0}}, {{"source": 1}}],
+            "images": [{{"uri": "base_color.png"}}, {{"uri": "metallic_roughness.png"}}],
+            "buffers": [{{"uri": "quad.bin", "byteLength": {}}}],
+            "bufferViews": [
+                {{"buffer": 0, "byteOffset": 0, "byteLength": 128}},
+                {{"buffer": 0, "byteOffset": 128, "byteLength": 12}}
+            ],
+            "accessors": [
+                {{"bufferView": 0, "byteOffset": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                  "min": [-0.5, -0.5, 0], "max": [0.5, 0.5, 0]}},
+                {{"bufferView": 0, "byteOffset": 48, "componentType": 5126, "count": 4, "type": "VEC3"}},
+                {{"bufferView": 0, "byteOffset": 96, "componentType": 5126, "count": 4, "type": "VEC2"}},
+                {{"bufferView": 1, "componentType": 5123, "count": 6, "type": "SCALAR"}}
+            ]
+        }}"#,
+        primitive(0),
+        primitive(1),
+        bin.len()
+    );
+    let path = dir.join("gray_maps.gltf");
+    std::fs::write(&path, gltf).unwrap();
+    path
+}
+
+#[test]
+fn grayscale_material_maps() {
+    let path = gray_maps_gltf();
+    let mut headless = headless();
+    let scene = Scene::load_gltf(
+        &headless.device,
+        &headless.queue,
+        &headless.renderer.material_layout,
+        &path,
+    )
+    .unwrap();
+    // Gray is repeated in every color channel, so it's read the same from
+    // any of them
+    let format =
+        |texture: &Option<rustgl::texture::Texture>| texture.as_ref().unwrap().texture.format();
+    let materials = &scene.model.materials;
+    assert_eq!(
+        format(&materials[0].maps.base_color),
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    );
+    assert_eq!(
+        format(&materials[1].maps.metallic_roughness),
+        wgpu::TextureFormat::Rgba8Unorm
+    );
+
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, &[path])
+        .unwrap();
+    headless.renderer.camera.eye = (0.0, 0.0, 2.0).into();
+    headless.renderer.camera.target = (0.0, 0.0, 0.0).into();
+    let frame = headless.render().unwrap();
+    assert_golden("gray_maps_gltf", &frame, Tolerance::default());
+
+    // The base color stays gray rather than turning red
+    for x in (WIDTH / 8..WIDTH / 2 - WIDTH / 16).step_by(8) {
+        let [r, g, b, _] = frame.get_pixel(x, HEIGHT / 2).0;
+        assert!(r.abs_diff(g) <= 2 && g.abs_diff(b) <= 2, "{x}: {r} {g} {b}");
+    }
+}
+
+/// The quad of `mirrored.gltf` three times in a row, facing down towards the
+/// camera: as modelled, mirrored in x, and without normals. Returns the
+/// background and the color at the center of each.
+fn render_mirrored() -> ([u8; 4], [[u8; 4]; 3]) {
+    let mut headless = headless();
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, &[asset("mirrored.gltf")])
+        .unwrap();
+    headless.renderer.camera.eye = (0.0, 0.0, 2.0).into();
+    headless.renderer.camera.target = (0.0, 0.0, 0.0).into();
+    let frame = headless.render().unwrap();
+    // The quads are 0.7 apart, about 80 pixels at this distance
+    let centers =
+        [WIDTH / 2 - 80, WIDTH / 2, WIDTH / 2 + 80].map(|x| frame.get_pixel(x, HEIGHT / 2).0);
+    (frame.get_pixel(0, 0).0, centers)
+}
+
+fn assert_color_eq(a: [u8; 4], b: [u8; 4]) {
+    assert!(
+        a.iter().zip(b).all(|(a, b)| a.abs_diff(b) <= 2),
+        "{a:?} != {b:?}"
+    );
+}
+
+#[test]
+fn mirrored_nodes_face_outwards() {
+    let (background, [original, mirrored, _]) = render_mirrored();
+    assert_ne!(mirrored, background);
+    // Mirroring in x leaves the normal of a quad facing down alone
+    assert_color_eq(mirrored, original);
+}
+
+#[test]
+fn missing_normals_are_flat() {
+    let (_, [original, _, flat]) = render_mirrored();
+    // Lit the same as with the normals of the quad's face
+    assert_color_eq(flat, original);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{assert_golden, headless, Tolerance, HEIGHT, WIDTH};
+use rustgl::{Camera, ProjectionMode};
+
+fn camera(eye: (f32, f32, f32)) -> Camera {
+    Camera {
+        eye: eye.into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    }
+}
+
+fn render(camera: Camera) -> image::RgbaImage {
+    let mut headless = headless();
+    headless.renderer.camera = camera;
+    headless.render().unwrap()
+}
+
+#[test]
+fn pentagon_default_camera() {
+    let frame = render(camera((0.0, 1.0, 2.0)));
+    assert_golden("pentagon_default_camera", &frame, Tolerance::default());
+}
+
+#[test]
+fn pentagon_oblique_camera() {
+    let frame = render(camera((1.2, -0.4, 1.5)));
+    assert_golden("pentagon_oblique_camera", &frame, Tolerance::default());
+}
+
+#[test]
+fn pentagon_orthographic_camera() {
+    let frame = render(Camera {
+        projection: ProjectionMode::Orthographic,
+        ..camera((1.2, -0.4, 1.5))
+    });
+    assert_golden("pentagon_orthographic_camera", &frame, Tolerance::default());
+}
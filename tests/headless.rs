@@ -0,0 +1,32 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::{mismatched_pixels, SMALL_HEIGHT, SMALL_WIDTH};
+use rustgl::{run_headless, PostSettings};
+
+#[test]
+fn frames_animate() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("animated.png");
+    pollster::block_on(run_headless(
+        &path,
+        SMALL_WIDTH,
+        SMALL_HEIGHT,
+        2,
+        &[],
+        &[],
+        1,
+        PostSettings::default(),
+    ))
+    .unwrap();
+
+    let frame = |n: u32| {
+        image::open(path.with_file_name(format!("animated_{n:04}.png")))
+            .unwrap()
+            .into_rgba8()
+    };
+    // The pentagon turns and the sun moves between them
+    let (first, second) = (frame(0), frame(1));
+    assert_eq!(first.dimensions(), (SMALL_WIDTH, SMALL_HEIGHT));
+    assert!(mismatched_pixels(&first, &second) > 0);
+}
@@ -0,0 +1,284 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::headless_sized;
+use rustgl::{
+    headless::Headless,
+    ibl::{self, Environment, IblSettings},
+    texture::{Texture, TextureOptions},
+};
+
+/// Small and quick, for tests
+const SETTINGS: IblSettings = IblSettings {
+    irradiance_size: 8,
+    irradiance_samples: 256,
+    specular_size: 16,
+    specular_levels: 4,
+    specular_samples: 128,
+    brdf_lut_size: 32,
+    brdf_samples: 256,
+};
+
+fn headless() -> Headless {
+    headless_sized(64, 48)
+}
+
+/// A cube map with each face a single color, in wgpu's face order
+fn cube(headless: &Headless, colors: [[f32; 3]; 6]) -> Texture {
+    let faces = colors.map(|color| {
+        image::DynamicImage::ImageRgb32F(image::Rgb32FImage::from_pixel(32, 32, image::Rgb(color)))
+    });
+    Texture::cube_from_images(
+        &headless.device,
+        &headless.queue,
+        &faces,
+        Some("cube"),
+        TextureOptions::default(),
+    )
+    .unwrap()
+}
+
+/// 16-bit float channels as `f32`s
+fn halves(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks(2)
+        .map(|b| half::f16::from_le_bytes([b[0], b[1]]).to_f32())
+        .collect()
+}
+
+/// The texel in the middle of a cube map face
+fn face_center(headless: &Headless, texture: &Texture, face: u32, mip_level: u32) -> [f32; 3] {
+    let texels = halves(
+        &ibl::read_cube_face(&headless.device, &headless.queue, texture, face, mip_level).unwrap(),
+    );
+    let width = (texture.texture.width() >> mip_level).max(1) as usize;
+    let center = (width / 2 * width + width / 2) * 4;
+    [texels[center], texels[center + 1], texels[center + 2]]
+}
+
+/// Scale and bias for every texel of the BRDF lookup table
+fn read_lut(headless: &Headless, texture: &Texture) -> Vec<f32> {
+    let texture = &texture.texture;
+    let row = texture.width() * 4;
+    let padded_row = row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = headless.device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (padded_row * texture.height()) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = headless
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+    headless.queue.submit(Some(encoder.finish()));
+    buffer.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+    headless.device.poll(wgpu::Maintain::Wait);
+    let data = buffer.slice(..).get_mapped_range();
+    data.chunks(padded_row as usize)
+        .flat_map(|chunk| halves(&chunk[..row as usize]))
+        .collect()
+}
+
+#[test]
+fn uniform_environment_keeps_its_color() {
+    let headless = headless();
+    let color = [0.5, 0.25, 2.0];
+    let environment = Environment::compute(
+        &headless.device,
+        &headless.queue,
+        &cube(&headless, [color; 6]),
+        SETTINGS,
+    );
+    assert_eq!(environment.irradiance.texture.width(), 8);
+    assert_eq!(environment.specular.texture.mip_level_count(), 4);
+    assert_eq!(environment.specular_max_lod(), 3.0);
+
+    let close = |actual: [f32; 3]| {
+        actual
+            .iter()
+            .zip(color)
+            .all(|(a, c)| (a - c).abs() <= 0.02 * c)
+    };
+    for face in 0..6 {
+        let irradiance = face_center(&headless, &environment.irradiance, face, 0);
+        assert!(close(irradiance), "{irradiance:?}");
+        for level in 0..4 {
+            let specular = face_center(&headless, &environment.specular, face, level);
+            assert!(close(specular), "level {level}: {specular:?}");
+        }
+    }
+}
+
+#[test]
+fn irradiance_follows_the_light() {
+    let headless = headless();
+    // Light only from above
+    let mut colors = [[0.0; 3]; 6];
+    colors[2] = [1.0; 3];
+    let environment = Environment::compute(
+        &headless.device,
+        &headless.queue,
+        &cube(&headless, colors),
+        SETTINGS,
+    );
+    let up = face_center(&headless, &environment.irradiance, 2, 0)[0];
+    let side = face_center(&headless, &environment.irradiance, 0, 0)[0];
+    let down = face_center(&headless, &environment.irradiance, 3, 0)[0];
+    assert!(up > 0.5 && up < 1.0, "{up}");
+    assert!(side > 0.05 && side < up, "{side}");
+    assert!(down < 0.01, "{down}");
+
+    // Rough reflections spread the light further than sharp ones
+    let sharp = face_center(&headless, &environment.specular, 0, 0)[0];
+    let rough = face_center(&headless, &environment.specular, 0, 3)[0];
+    assert!(sharp < 0.01, "{sharp}");
+    assert!(rough > 0.01, "{rough}");
+}
+
+#[test]
+fn brdf_lut_conserves_energy() {
+    let headless = headless();
+    let environment = Environment::compute(
+        &headless.device,
+        &headless.queue,
+        &cube(&headless, [[1.0; 3]; 6]),
+        SETTINGS,
+    );
+    assert_eq!(
+        environment.brdf_lut.texture.format(),
+        Environment::LUT_FORMAT
+    );
+    let texels = read_lut(&headless, &environment.brdf_lut);
+    let size = SETTINGS.brdf_lut_size as usize;
+    let scale_bias = |x: usize, y: usize| {
+        let i = (y * size + x) * 2;
+        (texels[i], texels[i + 1])
+    };
+    for y in 0..size {
+        for x in 0..size {
+            let (scale, bias) = scale_bias(x, y);
+            assert!(scale >= 0.0 && bias >= 0.0, "{x} {y}: {scale} {bias}");
+            assert!(scale + bias <= 1.02, "{x} {y}: {scale} {bias}");
+        }
+    }
+    // A smooth surface reflects everything, seen head on mostly as F0
+    let (scale, bias) = scale_bias(size - 1, 0);
+    assert!(scale > 0.95 && bias < 0.02, "{scale} {bias}");
+    // Rough surfaces lose energy to light that bounces more than once
+    let (scale, bias) = scale_bias(size / 2, size - 1);
+    assert!(scale + bias < 0.6, "{scale} {bias}");
+    let (smooth_scale, smooth_bias) = scale_bias(size / 2, 0);
+    assert!(
+        smooth_scale + smooth_bias > 0.95,
+        "{smooth_scale} {smooth_bias}"
+    );
+}
+
+#[test]
+fn cache_round_trip() {
+    let headless = headless();
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ibl_cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("environment.ibl");
+    let cube = cube(&headless, [[0.2, 0.4, 0.8]; 6]);
+
+    let computed =
+        Environment::load_or_compute(&headless.device, &headless.queue, &cube, SETTINGS, &path);
+    let saved = std::fs::read(&path).unwrap();
+
+    // Loaded from the cache, which matches the original exactly
+    let loaded = Environment::load(&headless.device, &headless.queue, &path, SETTINGS).unwrap();
+    let copy = dir.join("copy.ibl");
+    loaded
+        .save(&headless.device, &headless.queue, &copy)
+        .unwrap();
+    assert_eq!(std::fs::read(&copy).unwrap(), saved);
+    assert_eq!(
+        face_center(&headless, &loaded.specular, 1, 2),
+        face_center(&headless, &computed.specular, 1, 2)
+    );
+
+    let other = IblSettings {
+        specular_samples: 64,
+        ..SETTINGS
+    };
+    let error = Environment::load(&headless.device, &headless.queue, &path, other)
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("other settings"), "{error}");
+
+    // Or by another version of the shader, whose number follows the magic
+    let mut stale = saved.clone();
+    stale[8] ^= 1;
+    std::fs::write(&path, &stale).unwrap();
+    let error = Environment::load(&headless.device, &headless.queue, &path, SETTINGS)
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().contains("version of the shader"),
+        "{error}"
+    );
+
+    // A damaged cache is replaced
+    std::fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+    assert!(Environment::load(&headless.device, &headless.queue, &path, SETTINGS).is_err());
+    Environment::load_or_compute(&headless.device, &headless.queue, &cube, SETTINGS, &path);
+    assert_eq!(std::fs::read(&path).unwrap(), saved);
+}
+
+#[test]
+fn cache_keys() {
+    let key = ibl::cache_key(&[b"sky"], SETTINGS);
+    assert_eq!(key, ibl::cache_key(&[b"sky"], SETTINGS));
+    assert_ne!(key, ibl::cache_key(&[b"sea"], SETTINGS));
+    // The same bytes split differently between sources
+    assert_ne!(
+        ibl::cache_key(&[b"ab", b"c"], SETTINGS),
+        ibl::cache_key(&[b"a", b"bc"], SETTINGS)
+    );
+    assert_ne!(key, ibl::cache_key(&[b"sky", b""], SETTINGS));
+    let other = IblSettings {
+        brdf_samples: 64,
+        ..SETTINGS
+    };
+    assert_ne!(key, ibl::cache_key(&[b"sky"], other));
+}
+
+#[test]
+fn skybox_lights_the_scene() {
+    let mut headless = headless();
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("skybox_ibl_cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ibl_sky.png");
+    image::RgbImage::from_fn(64, 32, |_, y| image::Rgb([40, 80, 255 - y as u8 * 4]))
+        .save(&path)
+        .unwrap();
+
+    let renderer = &mut headless.renderer;
+    assert!(renderer.lights.environment().is_none());
+    renderer.ibl_settings = SETTINGS;
+    renderer.ibl_cache_dir = Some(dir.clone());
+    renderer
+        .load_skybox(&headless.device, &headless.queue, &[path])
+        .unwrap();
+    let environment = renderer.lights.environment().unwrap();
+    assert_eq!(environment.settings(), SETTINGS);
+    let cached: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+    assert_eq!(cached.len(), 1);
+
+    renderer.set_environment(&headless.device, None);
+    assert!(renderer.lights.environment().is_none());
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::time::Duration;
+
+use cgmath::{Deg, One, Quaternion, Rad, Rotation3, Vector3};
+use common::{assert_golden, Tolerance, HEIGHT, WIDTH};
+use rustgl::{
+    headless::Headless,
+    instance::{Instance, InstanceBuffer},
+    Camera, FrameClock, ProjectionMode,
+};
+
+fn headless() -> Headless {
+    let mut headless = common::headless();
+    headless.renderer.camera = Camera {
+        eye: (0.0, 0.0, 4.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    };
+    headless
+}
+
+/// A 4x3 grid of pentagons, each rotated and tinted differently
+fn grid() -> Vec<Instance> {
+    (0..12)
+        .map(|i| {
+            let (x, y) = ((i % 4) as f32, (i / 4) as f32);
+            Instance {
+                position: Vector3::new(x - 1.5, y - 1.0, 0.0),
+                rotation: Quaternion::from_angle_z(Deg(i as f32 * 30.0)),
+                scale: Vector3::new(0.8, 0.8, 0.8),
+                color: [1.0 - x / 4.0, 0.5 + y / 4.0, x / 3.0, 1.0],
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn instance_grid_render() {
+    let mut headless = headless();
+    headless.renderer.models[0].instances = InstanceBuffer::new(&headless.device, grid());
+
+    let frame = headless.render().unwrap();
+    assert_golden("instance_grid", &frame, Tolerance::default());
+}
+
+#[test]
+fn edit_instances_at_runtime() {
+    let mut headless = headless();
+    let grid = grid();
+    {
+        let instances = &mut headless.renderer.models[0].instances;
+        instances.clear();
+        for instance in &grid[..4] {
+            instances.push(*instance);
+        }
+    }
+    // Upload the first row so later edits only touch parts of the buffer
+    headless.render().unwrap();
+
+    let instances = &mut headless.renderer.models[0].instances;
+    // Grows the buffer past its original capacity
+    for instance in &grid[4..] {
+        instances.push(*instance);
+    }
+    let stray = instances.push(Instance::default());
+    assert_eq!(instances.remove(stray), Instance::default());
+    // The last instance moves into the removed slot
+    assert_eq!(instances.remove(0), grid[0]);
+    assert_eq!(instances.get(0), Some(&grid[11]));
+    instances.set(0, grid[0]);
+    instances.push(grid[11]);
+    instances.update(1, |i| i.color = [0.0; 4]);
+    headless.render().unwrap();
+
+    let instances = &mut headless.renderer.models[0].instances;
+    instances.update(1, |i| i.color = grid[1].color);
+    assert_eq!(instances.iter().copied().collect::<Vec<_>>(), grid);
+
+    let frame = headless.render().unwrap();
+    assert_golden("instance_grid", &frame, Tolerance::default());
+}
+
+#[test]
+fn simulation_steps_move_instances() {
+    let mut headless = headless();
+    // Each one a second of movement away from where it is in the grid
+    let moving: Vec<Instance> = grid()
+        .into_iter()
+        .enumerate()
+        .map(|(i, target)| {
+            let velocity = Vector3::new(0.0, 0.5, 0.0);
+            let angular_velocity = Vector3::unit_z() * Rad::from(Deg(i as f32 * 30.0)).0;
+            Instance {
+                position: target.position - velocity,
+                rotation: Quaternion::one(),
+                velocity,
+                angular_velocity,
+                ..target
+            }
+        })
+        .collect();
+    headless.renderer.models[0].instances = InstanceBuffer::new(&headless.device, moving.clone());
+    headless.render().unwrap();
+
+    let mut clock = FrameClock::new();
+    clock.fixed_step = Duration::from_millis(100);
+    clock.max_delta = Duration::from_secs(1);
+    clock.set_paused(true);
+    assert_eq!(clock.advance(Duration::from_secs(1)).fixed_steps, 0);
+    let instances = &headless.renderer.models[0].instances;
+    assert_eq!(instances.iter().copied().collect::<Vec<_>>(), moving);
+
+    clock.set_paused(false);
+    let time = clock.advance(Duration::from_secs(1));
+    for _ in 0..time.fixed_steps {
+        headless.renderer.step(time.fixed_delta);
+    }
+    let frame = headless.render().unwrap();
+    assert_golden("instance_grid", &frame, Tolerance::default());
+}
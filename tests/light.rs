@@ -0,0 +1,112 @@
+mod common;
+
+use std::path::PathBuf;
+
+use cgmath::{Deg, Vector3};
+use common::{assert_golden, Tolerance, HEIGHT, WIDTH};
+use rustgl::{
+    headless::Headless,
+    instance::{Instance, InstanceBuffer},
+    light::{Attenuation, Light, MAX_LIGHTS},
+    Camera, ProjectionMode,
+};
+
+/// A cube standing on a flattened cube as floor, lit only by the given lights
+fn headless(lights: &[Light]) -> Headless {
+    let mut headless = common::headless();
+    let cube = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets/cube.obj");
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, &[cube])
+        .unwrap();
+    headless.renderer.models[0].instances = InstanceBuffer::new(
+        &headless.device,
+        vec![
+            Instance::default(),
+            Instance {
+                position: Vector3::new(0.0, -0.55, 0.0),
+                scale: Vector3::new(6.0, 0.1, 6.0),
+                ..Default::default()
+            },
+        ],
+    );
+    headless.renderer.camera = Camera {
+        eye: (2.0, 2.0, 3.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    };
+
+    let scene_lights = &mut headless.renderer.lights;
+    scene_lights.clear();
+    scene_lights.set_ambient([0.05; 3]);
+    for light in lights {
+        scene_lights.add(*light).unwrap();
+    }
+    headless
+}
+
+fn point() -> Light {
+    Light::Point {
+        position: Vector3::new(-1.2, 0.2, 1.0),
+        color: [1.0, 0.2, 0.1],
+        intensity: 2.0,
+        attenuation: Attenuation::default(),
+    }
+}
+
+fn spot() -> Light {
+    Light::Spot {
+        position: Vector3::new(1.2, 2.0, 0.5),
+        direction: Vector3::new(0.0, -1.0, 0.0),
+        color: [0.2, 1.0, 0.3],
+        intensity: 3.0,
+        attenuation: Attenuation::default(),
+        inner_angle: Deg(15.0),
+        outer_angle: Deg(25.0),
+        cast_shadows: false,
+    }
+}
+
+fn sun() -> Light {
+    Light::Directional {
+        direction: Vector3::new(0.5, -1.0, -0.2),
+        color: [0.3, 0.4, 1.0],
+        intensity: 0.4,
+        cast_shadows: false,
+    }
+}
+
+#[test]
+fn point_spot_and_directional_lights() {
+    let mut headless = headless(&[point(), spot(), sun()]);
+    let frame = headless.render().unwrap();
+    assert_golden("lights_mixed", &frame, Tolerance::default());
+}
+
+#[test]
+fn lights_move_at_runtime() {
+    let mut headless = headless(&[sun(), spot()]);
+    headless.render().unwrap();
+
+    headless.renderer.lights.add(point()).unwrap();
+    let spot = headless.renderer.lights.get_mut(1).unwrap();
+    spot.set_position(Vector3::new(-1.0, 2.0, -0.8));
+    let frame = headless.render().unwrap();
+    assert_golden("lights_moved", &frame, Tolerance::default());
+}
+
+#[test]
+fn light_count_is_limited() {
+    let mut headless = headless(&[]);
+    for _ in 0..MAX_LIGHTS {
+        assert!(headless.renderer.lights.add(sun()).is_some());
+    }
+    assert_eq!(headless.renderer.lights.add(sun()), None);
+    headless.renderer.lights.remove(0);
+    assert_eq!(headless.renderer.lights.add(sun()), Some(MAX_LIGHTS - 1));
+}
@@ -0,0 +1,247 @@
+mod common;
+
+use std::f32::consts::PI;
+
+use cgmath::Vector3;
+use common::{assert_golden, headless, Tolerance, HEIGHT, WIDTH};
+use rustgl::{
+    headless::Headless,
+    ibl::{Environment, IblSettings},
+    instance::Instance,
+    light::Light,
+    model::{Material, MaterialMaps, MaterialParams, Mesh, Model},
+    skybox::Skybox,
+    texture::{Texture, TextureOptions},
+    Camera, ProjectionMode, Vertex,
+};
+
+/// A UV sphere of radius 0.4 around the origin
+fn sphere(device: &wgpu::Device) -> Mesh {
+    const RINGS: u32 = 16;
+    const SEGMENTS: u32 = 32;
+    let mut vertices = Vec::new();
+    for ring in 0..=RINGS {
+        let theta = ring as f32 / RINGS as f32 * PI;
+        for segment in 0..=SEGMENTS {
+            let phi = segment as f32 / SEGMENTS as f32 * 2.0 * PI;
+            let normal = [
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            ];
+            vertices.push(Vertex {
+                position: normal.map(|n| n * 0.4),
+                tex_coords: [segment as f32 / SEGMENTS as f32, ring as f32 / RINGS as f32],
+                normal,
+            });
+        }
+    }
+    let mut indices = Vec::new();
+    for ring in 0..RINGS {
+        for segment in 0..SEGMENTS {
+            let a = ring * (SEGMENTS + 1) + segment;
+            let b = a + SEGMENTS + 1;
+            indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+        }
+    }
+    Mesh::new(device, "sphere", &vertices, &indices, 0)
+}
+
+/// Two rows of spheres, dielectric above metallic, with roughness increasing
+/// from left to right
+fn sphere_grid() -> Headless {
+    let mut headless = headless();
+    let renderer = &mut headless.renderer;
+    renderer.models.clear();
+    for (row, metallic) in [0.0, 1.0].into_iter().enumerate() {
+        for column in 0..5 {
+            let params = MaterialParams {
+                base_color: [0.9, 0.5, 0.2, 1.0],
+                metallic,
+                roughness: 0.1 + column as f32 * 0.225,
+                ..Default::default()
+            };
+            let material = Material::new(
+                &headless.device,
+                "sphere",
+                MaterialMaps::default(),
+                params,
+                &renderer.material_layout,
+            );
+            let mut model = Model::new(
+                &headless.device,
+                vec![sphere(&headless.device)],
+                vec![material],
+            );
+            model.instances.set(
+                0,
+                Instance {
+                    position: Vector3::new(column as f32 - 2.0, 0.5 - row as f32, 0.0),
+                    ..Default::default()
+                },
+            );
+            renderer.models.push(model);
+        }
+    }
+    renderer.camera = Camera {
+        eye: (0.0, 0.0, 5.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    };
+    renderer.lights.clear();
+    renderer.lights.set_ambient([0.03; 3]);
+    renderer.lights.add(Light::Directional {
+        direction: Vector3::new(-0.5, -0.5, -1.0),
+        color: [1.0; 3],
+        intensity: 3.0,
+        cast_shadows: false,
+    });
+    headless
+}
+
+#[test]
+fn metallic_roughness_render() {
+    let mut headless = sphere_grid();
+    let frame = headless.render().unwrap();
+    assert_golden("pbr_spheres", &frame, Tolerance::default());
+}
+
+#[test]
+fn emissive_params_at_runtime() {
+    let mut headless = sphere_grid();
+    for model in &mut headless.renderer.models {
+        let material = &mut model.materials[0];
+        let params = MaterialParams {
+            emissive: [0.0, 0.2, 0.4],
+            ..material.params()
+        };
+        material.set_params(&headless.queue, params);
+    }
+    let frame = headless.render().unwrap();
+    assert_golden("pbr_spheres_emissive", &frame, Tolerance::default());
+}
+
+#[test]
+fn image_based_lighting() {
+    let mut headless = sphere_grid();
+    // A blue sky over dark ground, with a bright patch of sun up and to the
+    // left
+    let panorama = image::Rgb32FImage::from_fn(128, 64, |x, y| {
+        let (u, v) = (x as f32 / 128.0, y as f32 / 64.0);
+        let color = if (u - 0.375).abs() < 0.04 && (v - 0.3).abs() < 0.06 {
+            [40.0, 36.0, 30.0]
+        } else if v < 0.5 {
+            [0.3, 0.5, 1.0 - v]
+        } else {
+            [0.1, 0.08, 0.05]
+        };
+        image::Rgb(color)
+    });
+    let panorama = Texture::from_image_with(
+        &headless.device,
+        &headless.queue,
+        &image::DynamicImage::ImageRgb32F(panorama),
+        Some("panorama"),
+        TextureOptions::default(),
+    )
+    .unwrap();
+    let skybox = Skybox::from_equirectangular(&headless.device, &headless.queue, &panorama, 64);
+    let settings = IblSettings {
+        irradiance_size: 16,
+        irradiance_samples: 256,
+        specular_size: 64,
+        specular_levels: 5,
+        specular_samples: 256,
+        brdf_lut_size: 64,
+        brdf_samples: 256,
+    };
+    let environment =
+        Environment::compute(&headless.device, &headless.queue, &skybox.texture, settings);
+
+    let renderer = &mut headless.renderer;
+    renderer.lights.clear();
+    renderer.set_skybox(&headless.device, Some(skybox));
+    renderer.set_environment(&headless.device, Some(environment));
+    let frame = headless.render().unwrap();
+    assert_golden("ibl_spheres", &frame, Tolerance::default());
+}
+
+#[test]
+fn obj_pbr_extension() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("obj_pbr");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("tri.obj"),
+        "mtllib tri.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl shiny\nf 1 2 3\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("tri.mtl"),
+        "newmtl shiny\nKd 0.5 0.5 0.5\nPr 0.3\nPm 0.9\nKe 1 0.5 0\n",
+    )
+    .unwrap();
+
+    let headless = headless();
+    let model = Model::load_obj(
+        &headless.device,
+        &headless.queue,
+        &headless.renderer.material_layout,
+        dir.join("tri.obj"),
+    )
+    .unwrap();
+    let params = model.materials[0].params();
+    assert_eq!(params.base_color, [0.5, 0.5, 0.5, 1.0]);
+    assert_eq!(params.roughness, 0.3);
+    assert_eq!(params.metallic, 0.9);
+    assert_eq!(params.emissive, [1.0, 0.5, 0.0]);
+    assert!(model.materials[0].maps.base_color.is_none());
+}
+
+#[test]
+fn sixteen_bit_maps_fit_materials() {
+    let headless = headless();
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::ImageBuffer::<image::Rgba<u16>, _>::from_pixel(4, 4, image::Rgba([40000; 4]))
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    // Colors are decoded from sRGB, which no 16-bit integer format can do
+    let base_color = Texture::from_bytes(
+        &headless.device,
+        &headless.queue,
+        png.get_ref(),
+        "base_color",
+        true,
+    )
+    .unwrap();
+    let normal = Texture::from_bytes(
+        &headless.device,
+        &headless.queue,
+        png.get_ref(),
+        "normal",
+        false,
+    )
+    .unwrap();
+
+    headless
+        .device
+        .push_error_scope(wgpu::ErrorFilter::Validation);
+    let maps = MaterialMaps {
+        base_color: Some(base_color),
+        normal: Some(normal),
+        ..Default::default()
+    };
+    Material::new(
+        &headless.device,
+        "sixteen_bit",
+        maps,
+        MaterialParams::default(),
+        &headless.renderer.material_layout,
+    );
+    let error = pollster::block_on(headless.device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use std::{fmt::Write, path::PathBuf};
+
+use common::{assert_golden, headless, Tolerance, HEIGHT, WIDTH};
+use rustgl::{Camera, ProjectionMode};
+
+fn asset(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("assets")
+        .join(name)
+}
+
+#[test]
+fn obj_groups_and_materials() {
+    let mut headless = headless();
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, &[asset("cube.obj")])
+        .unwrap();
+
+    let [cube] = &headless.renderer.models[..] else {
+        panic!("expected the cube to replace the pentagon");
+    };
+    let meshes: Vec<_> = cube
+        .meshes
+        .iter()
+        .map(|m| (m.name.as_str(), m.num_elements, m.index_format))
+        .collect();
+    assert_eq!(
+        meshes,
+        [
+            ("sides", 24, wgpu::IndexFormat::Uint16),
+            ("caps", 12, wgpu::IndexFormat::Uint16),
+        ]
+    );
+    let materials: Vec<_> = cube
+        .meshes
+        .iter()
+        .map(|m| cube.materials[m.material].name.as_str())
+        .collect();
+    assert_eq!(materials, ["tree", "plain"]);
+}
+
+#[test]
+fn large_mesh_uses_u32_indices() {
+    // A 300x300 vertex grid, more than a u16 index can address
+    const N: u32 = 300;
+    let mut obj = String::new();
+    for y in 0..N {
+        for x in 0..N {
+            writeln!(obj, "v {x} {y} 0").unwrap();
+        }
+    }
+    for y in 0..N - 1 {
+        for x in 0..N - 1 {
+            let i = y * N + x + 1;
+            writeln!(obj, "f {} {} {} {}", i, i + 1, i + N + 1, i + N).unwrap();
+        }
+    }
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("grid.obj");
+    std::fs::write(&path, obj).unwrap();
+
+    let mut headless = headless();
+    headless
+        .renderer
+        .load_obj(&headless.device, &headless.queue, &path)
+        .unwrap();
+
+    let grid = &headless.renderer.models[1].meshes[0];
+    assert_eq!(grid.index_format, wgpu::IndexFormat::Uint32);
+    assert_eq!(grid.num_elements, (N - 1) * (N - 1) * 6);
+}
+
+fn cube_camera() -> Camera {
+    Camera {
+        eye: (1.5, 1.2, 2.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    }
+}
+
+#[test]
+fn cube_obj_render() {
+    let mut headless = headless();
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, &[asset("cube.obj")])
+        .unwrap();
+    headless.renderer.camera = cube_camera();
+
+    let frame = headless.render().unwrap();
+    assert_golden("cube_obj", &frame, Tolerance::default());
+}
+
+#[test]
+fn depth_hides_models_drawn_later() {
+    let mut headless = headless();
+    headless
+        .renderer
+        .load_obj(&headless.device, &headless.queue, asset("cube.obj"))
+        .unwrap();
+    // Draw the pentagon, which lies inside the cube, after the cube
+    headless.renderer.models.reverse();
+    headless.renderer.camera = cube_camera();
+
+    let frame = headless.render().unwrap();
+    assert_golden("cube_obj", &frame, Tolerance::default());
+}
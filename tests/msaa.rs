@@ -0,0 +1,74 @@
+mod common;
+
+use common::{
+    assert_golden, blended_pixels, glowing, show_alone, small_headless, square, Tolerance,
+    SMALL_HEIGHT, SMALL_WIDTH,
+};
+use rustgl::{
+    skybox::Skybox,
+    texture::{Texture, TextureOptions},
+    ProjectionMode,
+};
+
+#[test]
+fn sample_counts() {
+    let headless = small_headless();
+    let counts = headless.supported_sample_counts();
+    assert_eq!(counts.first(), Some(&1));
+    // Guaranteed for the formats used by every adapter
+    assert!(counts.contains(&4), "{counts:?}");
+    assert_eq!(headless.renderer.sample_count(), 1);
+}
+
+#[test]
+fn msaa_smooths_edges() {
+    let mut headless = small_headless();
+    // A white square turned a little, so its edges cut across pixels at an
+    // angle
+    let square = square(&headless, 0.5, 0.3, 0.0, glowing([1.0; 3]));
+    show_alone(&mut headless, square);
+
+    let aliased = headless.render().unwrap();
+    assert_eq!(blended_pixels(&aliased), 0);
+
+    headless.renderer.set_sample_count(&headless.device, 4);
+    assert_eq!(headless.renderer.sample_count(), 4);
+    let smooth = headless.render().unwrap();
+    assert!(blended_pixels(&smooth) > 50, "{}", blended_pixels(&smooth));
+
+    // Back to one sample gives the same frame as before
+    headless.renderer.set_sample_count(&headless.device, 1);
+    assert_eq!(headless.render().unwrap(), aliased);
+}
+
+#[test]
+fn msaa_survives_target_changes() {
+    let mut headless = small_headless();
+    headless.renderer.set_sample_count(&headless.device, 4);
+    let frame = headless.render().unwrap();
+    assert_golden("msaa_pentagon", &frame, Tolerance::default());
+
+    // Flipping the depth test and resizing rebuild the targets
+    headless.renderer.camera.projection = ProjectionMode::Perspective;
+    headless
+        .renderer
+        .resize(&headless.device, SMALL_WIDTH, SMALL_HEIGHT);
+    let standard = headless.render().unwrap();
+    assert_golden("msaa_pentagon", &standard, Tolerance::default());
+
+    // The sky is drawn with the same number of samples
+    let faces = [0; 6].map(|_| image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4)));
+    let cube = Texture::cube_from_images(
+        &headless.device,
+        &headless.queue,
+        &faces,
+        Some("black sky"),
+        TextureOptions::default(),
+    )
+    .unwrap();
+    headless
+        .renderer
+        .set_skybox(&headless.device, Some(Skybox::new(cube)));
+    let sky = headless.render().unwrap();
+    assert_eq!(sky.get_pixel(0, 0).0, [0, 0, 0, 255]);
+}
@@ -0,0 +1,364 @@
+mod common;
+
+use cgmath::{
+    assert_abs_diff_eq, EuclideanSpace, InnerSpace, Point3, Quaternion, Rotation3, Transform,
+    Vector3,
+};
+use common::{headless, square, HEIGHT, WIDTH};
+use rustgl::{
+    headless::Headless,
+    instance::Instance,
+    model::MaterialParams,
+    picking::{Bvh, PickId, PickRect, Ray},
+    Camera, ProjectionMode,
+};
+
+/// Small deterministic generator, so failures can be reproduced
+struct Lcg(u64);
+
+impl Lcg {
+    /// Uniform in -1..1
+    fn next(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+
+    fn point(&mut self, scale: f32) -> Point3<f32> {
+        Point3::new(self.next(), self.next(), self.next()) * scale
+    }
+}
+
+/// Random small triangles in a cube of half size 5
+fn triangle_soup(count: usize) -> (Vec<Point3<f32>>, Vec<u32>) {
+    let mut rng = Lcg(7);
+    let mut positions = Vec::new();
+    for _ in 0..count {
+        let center = rng.point(5.0);
+        for _ in 0..3 {
+            positions.push(center + rng.point(0.6).to_vec());
+        }
+    }
+    let indices = (0..positions.len() as u32).collect();
+    (positions, indices)
+}
+
+fn camera(projection: ProjectionMode) -> Camera {
+    Camera {
+        eye: (2.0, 3.0, 6.0).into(),
+        target: (0.0, 0.5, 0.0).into(),
+        up: Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection,
+    }
+}
+
+/// Distance from `point` to the line through `ray`
+fn distance_to_ray(ray: &Ray, point: Point3<f32>) -> f32 {
+    let offset = point - ray.origin;
+    (offset - ray.direction * offset.dot(ray.direction)).magnitude()
+}
+
+#[test]
+fn bvh_matches_brute_force() {
+    let (positions, indices) = triangle_soup(500);
+    let bvh = Bvh::new(&positions, &indices);
+    // One hierarchy per triangle stands in for testing them one by one
+    let singles: Vec<Bvh> = indices.chunks(3).map(|t| Bvh::new(&positions, t)).collect();
+
+    let mut rng = Lcg(42);
+    let mut hits = 0;
+    for _ in 0..300 {
+        let origin = rng.point(8.0);
+        let direction = (rng.point(3.0) - origin).normalize();
+        let ray = Ray::new(origin, direction);
+
+        let expected = singles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bvh)| Some((i, bvh.intersect(&ray, f32::INFINITY)?.distance)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let actual = bvh.intersect(&ray, f32::INFINITY);
+        match (expected, actual) {
+            (None, None) => {}
+            (Some((triangle, distance)), Some(hit)) => {
+                hits += 1;
+                assert_abs_diff_eq!(hit.distance, distance, epsilon = 1e-4);
+                // Ties between overlapping triangles may go either way
+                if hit.triangle != triangle {
+                    assert_abs_diff_eq!(hit.distance, distance, epsilon = 1e-6);
+                }
+            }
+            (expected, actual) => panic!("expected {expected:?}, got {actual:?}"),
+        }
+    }
+    assert!(hits > 30, "only {hits} rays hit anything");
+}
+
+#[test]
+fn bvh_respects_max_distance_and_bounds() {
+    let (positions, indices) = triangle_soup(100);
+    let bvh = Bvh::new(&positions, &indices);
+    let bounds = bvh.bounds();
+    for p in &positions {
+        assert!(bounds.min.x <= p.x && p.x <= bounds.max.x);
+    }
+
+    let ray = Ray::new(Point3::new(-20.0, 0.1, 0.2), Vector3::unit_x());
+    if let Some(hit) = bvh.intersect(&ray, f32::INFINITY) {
+        assert!(bvh.intersect(&ray, hit.distance * 0.99).is_none());
+    }
+    assert!(Bvh::new(&[], &[]).intersect(&ray, f32::INFINITY).is_none());
+}
+
+#[test]
+fn barycentrics_rebuild_the_hit() {
+    let positions = [
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(2.0, 0.0, 0.0),
+        Point3::new(0.0, 2.0, 0.0),
+        Point3::new(2.0, 2.0, 0.0),
+    ];
+    let bvh = Bvh::new(&positions, &[0, 1, 2, 2, 1, 3]);
+    // Hits from both sides
+    for (origin, direction) in [
+        (Point3::new(1.5, 1.2, 3.0), -Vector3::unit_z()),
+        (Point3::new(0.5, 0.3, -3.0), Vector3::unit_z()),
+    ] {
+        let ray = Ray::new(origin, direction);
+        let hit = bvh.intersect(&ray, f32::INFINITY).unwrap();
+        assert_abs_diff_eq!(hit.distance, 3.0, epsilon = 1e-5);
+
+        let corners = if hit.triangle == 0 {
+            [0, 1, 2]
+        } else {
+            [2, 1, 3]
+        };
+        let rebuilt = corners
+            .iter()
+            .zip(hit.barycentrics)
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, (&i, w)| {
+                sum + positions[i].to_vec() * w
+            });
+        assert_abs_diff_eq!(
+            Point3::from_vec(rebuilt),
+            ray.at(hit.distance),
+            epsilon = 1e-5
+        );
+        assert_abs_diff_eq!(hit.barycentrics.iter().sum::<f32>(), 1.0, epsilon = 1e-5);
+    }
+    assert_eq!(
+        bvh.intersect(
+            &Ray::new(Point3::new(1.5, 1.2, 3.0), -Vector3::unit_z()),
+            f32::INFINITY
+        )
+        .unwrap()
+        .triangle,
+        1
+    );
+}
+
+#[test]
+fn screen_rays_go_through_projected_points() {
+    for mode in [
+        ProjectionMode::Perspective,
+        ProjectionMode::Orthographic,
+        ProjectionMode::ReverseZ,
+    ] {
+        let camera = camera(mode);
+        let view_projection = camera.view_projection_matrix();
+
+        // The middle of the screen looks at the target
+        let ray = Ray::from_screen(
+            &camera,
+            WIDTH as f32 / 2.0,
+            HEIGHT as f32 / 2.0,
+            WIDTH,
+            HEIGHT,
+        );
+        assert!(distance_to_ray(&ray, camera.target) < 1e-4, "{mode:?}");
+        let forward = (camera.target - camera.eye).normalize();
+        assert_abs_diff_eq!(ray.direction, forward, epsilon = 1e-5);
+
+        let mut rng = Lcg(3);
+        for _ in 0..20 {
+            let point = rng.point(2.0);
+            let ndc = view_projection.transform_point(point);
+            let x = (ndc.x + 1.0) / 2.0 * WIDTH as f32;
+            let y = (1.0 - ndc.y) / 2.0 * HEIGHT as f32;
+            let ray = Ray::from_screen(&camera, x, y, WIDTH, HEIGHT);
+            assert!(distance_to_ray(&ray, point) < 1e-3, "{mode:?} {point:?}");
+            assert!((point - ray.origin).dot(ray.direction) > 0.0);
+            assert_abs_diff_eq!(ray.direction.magnitude(), 1.0, epsilon = 1e-5);
+            if mode == ProjectionMode::Orthographic {
+                assert_abs_diff_eq!(ray.direction, forward, epsilon = 1e-5);
+            }
+        }
+    }
+}
+
+#[test]
+fn rays_start_on_the_near_plane() {
+    for mode in [ProjectionMode::Perspective, ProjectionMode::ReverseZ] {
+        let camera = camera(mode);
+        let forward = (camera.target - camera.eye).normalize();
+        for (x, y) in [(0.0, 0.0), (-0.5, 0.8), (1.0, -1.0)] {
+            let ray = Ray::from_ndc(&camera, x, y);
+            assert_abs_diff_eq!(
+                (ray.origin - camera.eye).dot(forward),
+                camera.znear,
+                epsilon = 1e-4
+            );
+        }
+    }
+}
+
+/// The pentagon, with a big square behind it and a smaller, turned one in
+/// front, both instances of one model
+fn squares_scene() -> Headless {
+    let mut headless = headless();
+    // A unit square in the xy plane, facing +z
+    let mut squares = square(&headless, 0.5, 0.0, 0.0, MaterialParams::default());
+    squares.instances.set(
+        0,
+        Instance {
+            position: Vector3::new(0.0, 0.0, -2.0),
+            scale: Vector3::new(4.0, 4.0, 1.0),
+            ..Default::default()
+        },
+    );
+    squares.instances.push(Instance {
+        position: Vector3::new(0.3, 0.0, 0.5),
+        rotation: Quaternion::from_angle_y(cgmath::Deg(30.0)),
+        ..Default::default()
+    });
+    let renderer = &mut headless.renderer;
+    renderer.models.push(squares);
+    renderer.camera.eye = (0.0, 0.0, 3.0).into();
+    renderer.camera.target = (0.0, 0.0, 0.0).into();
+    headless
+}
+
+#[test]
+fn pick_nearest_instance() {
+    let mut headless = squares_scene();
+    let renderer = &mut headless.renderer;
+
+    // The rotated square in front covers the middle of the screen
+    let hit = renderer
+        .pick(WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0)
+        .unwrap();
+    assert_eq!((hit.model, hit.instance, hit.mesh), (1, 1, 0));
+    assert_abs_diff_eq!(hit.position.x, 0.0, epsilon = 1e-4);
+    assert_abs_diff_eq!(hit.position.y, 0.0, epsilon = 1e-4);
+    // On the plane of the square, rotated around its center at x = 0.3
+    let along = Quaternion::from_angle_y(cgmath::Deg(30.0)) * Vector3::unit_x();
+    let expected = Point3::new(0.3, 0.0, 0.5) - along * (0.3 / along.x);
+    assert_abs_diff_eq!(hit.position, expected, epsilon = 1e-4);
+    assert_abs_diff_eq!(
+        hit.distance,
+        (hit.position - renderer.camera.eye).magnitude() - renderer.camera.znear,
+        epsilon = 1e-3
+    );
+
+    // Near the top only the big square behind is left; the pentagon at the
+    // origin is hidden behind the rotated square or misses
+    let hit = renderer.pick(WIDTH as f32 / 2.0, 10.0).unwrap();
+    assert_eq!((hit.model, hit.instance), (1, 0));
+    assert_abs_diff_eq!(hit.position.z, -2.0, epsilon = 1e-4);
+
+    // Nothing in the corner
+    renderer.models.truncate(1);
+    assert!(renderer.pick(1.0, 1.0).is_none());
+}
+
+#[test]
+fn gpu_picking_is_optional() {
+    let mut headless = squares_scene();
+    assert!(!headless.renderer.gpu_picking());
+    assert!(headless.renderer.request_pick(0, 0).is_none());
+    assert!(headless.renderer.poll_picks(&headless.device).is_empty());
+}
+
+#[test]
+fn gpu_picks_known_objects() {
+    let mut headless = squares_scene();
+    headless.renderer.set_gpu_picking(&headless.device, true);
+    let renderer = &mut headless.renderer;
+
+    let center = renderer.request_pick(WIDTH / 2, HEIGHT / 2).unwrap();
+    let top = renderer.request_pick(WIDTH / 2, 10).unwrap();
+    let corner = renderer.request_pick(1, 1).unwrap();
+    let outside = renderer
+        .request_pick_rect(PickRect::from_corners((WIDTH + 5, 0), (WIDTH + 9, 3)))
+        .unwrap();
+    // Nothing happens before the next update
+    assert!(renderer.poll_picks(&headless.device).is_empty());
+
+    headless.render().unwrap();
+    headless.device.poll(wgpu::Maintain::Wait);
+    let picks = headless.renderer.poll_picks(&headless.device);
+    let requests: Vec<_> = picks.iter().map(|pick| pick.request).collect();
+    assert_eq!(requests, [center, top, corner, outside]);
+
+    let front = PickId {
+        model: 1,
+        instance: 1,
+    };
+    let back = PickId {
+        model: 1,
+        instance: 0,
+    };
+    assert_eq!(picks[0].at(WIDTH / 2, HEIGHT / 2), Some(front));
+    assert_eq!(picks[1].objects(), [back]);
+    assert_eq!(picks[2].pixels, [None]);
+    assert!(picks[3].rect.is_empty() && picks[3].pixels.is_empty());
+    // Answered once only
+    assert!(headless.renderer.poll_picks(&headless.device).is_empty());
+}
+
+#[test]
+fn gpu_box_select_matches_ray_casts() {
+    let mut headless = squares_scene();
+    headless.renderer.set_gpu_picking(&headless.device, true);
+    let rect = PickRect::from_corners((WIDTH - 1, HEIGHT - 1), (0, 0));
+    assert_eq!((rect.width, rect.height), (WIDTH, HEIGHT));
+    headless.renderer.request_pick_rect(rect).unwrap();
+    headless.render().unwrap();
+    headless.device.poll(wgpu::Maintain::Wait);
+    let [pick] = &headless.renderer.poll_picks(&headless.device)[..] else {
+        panic!("expected a single pick");
+    };
+    assert_eq!(pick.pixels.len(), (WIDTH * HEIGHT) as usize);
+
+    // Every instance shows somewhere, and pixel centers agree with the CPU
+    // except along a few edges
+    let renderer = &headless.renderer;
+    let mut mismatched = 0;
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let expected = renderer
+                .pick(x as f32 + 0.5, y as f32 + 0.5)
+                .map(|hit| PickId {
+                    model: hit.model,
+                    instance: hit.instance,
+                });
+            mismatched += (pick.at(x, y) != expected) as u32;
+        }
+    }
+    assert!(mismatched < WIDTH * HEIGHT / 100, "{mismatched}");
+    let objects = pick.objects();
+    assert!(objects.contains(&PickId {
+        model: 1,
+        instance: 0
+    }));
+    assert!(objects.contains(&PickId {
+        model: 1,
+        instance: 1
+    }));
+}
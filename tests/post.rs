@@ -0,0 +1,216 @@
+mod common;
+
+use common::{
+    assert_golden, glowing, show_alone, small_headless, square, Tolerance, SMALL_HEIGHT,
+    SMALL_WIDTH,
+};
+use rustgl::{headless::Headless, BloomSettings, PostSettings, Tonemapping};
+
+/// A square glowing with `emissive` in front of a black background, filling
+/// the middle third of the frame
+fn glowing_square(emissive: f32) -> Headless {
+    let mut headless = small_headless();
+    let square = square(&headless, 0.3, 0.0, 0.0, glowing([emissive; 3]));
+    show_alone(&mut headless, square);
+    headless
+}
+
+fn set_post(headless: &mut Headless, settings: PostSettings) {
+    headless
+        .renderer
+        .post
+        .set_settings(&headless.device, settings);
+}
+
+fn center(frame: &image::RgbaImage) -> u8 {
+    frame.get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 2)[0]
+}
+
+#[test]
+fn tonemapping_compresses_highlights() {
+    let mut headless = glowing_square(4.0);
+    let mut centers = Vec::new();
+    for tonemapping in Tonemapping::ALL {
+        set_post(
+            &mut headless,
+            PostSettings {
+                tonemapping,
+                ..Default::default()
+            },
+        );
+        centers.push(center(&headless.render().unwrap()));
+    }
+    // Without tonemapping everything above 1 clips
+    assert_eq!(centers[0], 255);
+    for &value in &centers[1..] {
+        assert!((128..255).contains(&value), "{centers:?}");
+    }
+    // Every curve has its own shape
+    for (i, a) in centers.iter().enumerate() {
+        assert!(centers[i + 1..].iter().all(|b| a != b), "{centers:?}");
+    }
+}
+
+#[test]
+fn exposure_scales_before_tonemapping() {
+    let mut headless = glowing_square(1.0);
+    assert_eq!(center(&headless.render().unwrap()), 255);
+
+    // A stop down halves the light, which is 188 in sRGB
+    set_post(
+        &mut headless,
+        PostSettings {
+            exposure: -1.0,
+            ..Default::default()
+        },
+    );
+    assert!(center(&headless.render().unwrap()).abs_diff(188) <= 1);
+}
+
+#[test]
+fn bloom_spreads_bright_light() {
+    let mut headless = glowing_square(8.0);
+    let plain = headless.render().unwrap();
+    // Halfway between the square's edge and the border of the frame
+    let outside = |frame: &image::RgbaImage| frame.get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 10)[0];
+    assert_eq!(outside(&plain), 0);
+
+    let settings = PostSettings {
+        tonemapping: Tonemapping::Aces,
+        bloom: Some(BloomSettings::default()),
+        ..Default::default()
+    };
+    set_post(&mut headless, settings);
+    let bloom = headless.render().unwrap();
+    assert!(outside(&bloom) > 0);
+    assert_golden("post_bloom", &bloom, Tolerance::default());
+
+    // Lights below the threshold don't glow
+    let mut dim = glowing_square(0.5);
+    set_post(&mut dim, settings);
+    assert_eq!(outside(&dim.render().unwrap()), 0);
+
+    // Resizing and turning bloom off and on again leaves it working
+    headless
+        .renderer
+        .resize(&headless.device, SMALL_WIDTH, SMALL_HEIGHT);
+    set_post(&mut headless, PostSettings::default());
+    set_post(&mut headless, settings);
+    assert_eq!(headless.render().unwrap(), bloom);
+}
+
+#[test]
+fn dither_changes_pixels_by_at_most_one_step() {
+    let mut headless = small_headless();
+    let plain = headless.render().unwrap();
+    set_post(
+        &mut headless,
+        PostSettings {
+            dither: true,
+            ..Default::default()
+        },
+    );
+    let dithered = headless.render().unwrap();
+
+    let mut changed = 0;
+    for (a, b) in plain.pixels().zip(dithered.pixels()) {
+        for channel in 0..3 {
+            assert!(a[channel].abs_diff(b[channel]) <= 1, "{a:?} {b:?}");
+            changed += (a[channel] != b[channel]) as usize;
+        }
+    }
+    assert!(changed > 0);
+}
+
+#[test]
+fn user_effects_run_in_order() {
+    let mut headless = glowing_square(1.0);
+    let post = &mut headless.renderer.post;
+    post.add_effect(
+        &headless.device,
+        "tint",
+        "@fragment
+         fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+             let color = textureSample(t_input, s_input, in.uv);
+             return vec4<f32>(color.rgb * effect.params.rgb, color.a);
+         }",
+    )
+    .unwrap();
+    assert!(post.set_effect_params("tint", [1.0, 0.0, 0.5, 0.0]));
+    let tinted = headless.render().unwrap();
+    let pixel = tinted.get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 2).0;
+    assert_eq!(pixel[..2], [255, 0]);
+    assert!(pixel[2].abs_diff(188) <= 1, "{pixel:?}");
+
+    // Effects see the output of the ones before them
+    let post = &mut headless.renderer.post;
+    post.add_effect(
+        &headless.device,
+        "invert",
+        "@fragment
+         fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+             let color = textureSample(t_input, s_input, in.uv);
+             return vec4<f32>(1.0 - color.rgb, color.a);
+         }",
+    )
+    .unwrap();
+    assert_eq!(post.effects().collect::<Vec<_>>(), ["tint", "invert"]);
+    let inverted = headless.render().unwrap();
+    assert_eq!(
+        inverted.get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 2).0[..2],
+        [0, 255]
+    );
+    assert_eq!(inverted.get_pixel(0, 0).0, [255, 255, 255, 255]);
+
+    let post = &mut headless.renderer.post;
+    assert!(post.remove_effect("tint"));
+    assert!(post.remove_effect("invert"));
+    assert!(!post.remove_effect("invert"));
+    assert!(!post.set_effect_params("invert", [0.0; 4]));
+    assert_eq!(
+        headless
+            .render()
+            .unwrap()
+            .get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 2)
+            .0,
+        [255; 4]
+    );
+}
+
+#[test]
+fn invalid_effects_are_rejected() {
+    let mut headless = glowing_square(1.0);
+    let post = &mut headless.renderer.post;
+    let broken = post.add_effect(&headless.device, "broken", "fn fs_main( {");
+    assert!(broken.is_err());
+    let wrong_entry = post.add_effect(
+        &headless.device,
+        "wrong_entry",
+        "@fragment fn main() -> @location(0) vec4<f32> { return vec4<f32>(1.0); }",
+    );
+    assert!(wrong_entry.is_err());
+
+    let source = "@fragment
+                  fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+                      return textureSample(t_input, s_input, in.uv);
+                  }";
+    post.add_effect(&headless.device, "copy", source).unwrap();
+    assert!(post.add_effect(&headless.device, "copy", source).is_err());
+    assert_eq!(post.effects().collect::<Vec<_>>(), ["copy"]);
+    assert_eq!(
+        headless
+            .render()
+            .unwrap()
+            .get_pixel(SMALL_WIDTH / 2, SMALL_HEIGHT / 2)
+            .0,
+        [255; 4]
+    );
+}
+
+#[test]
+fn tonemapping_names() {
+    assert_eq!("AgX".parse::<Tonemapping>().unwrap(), Tonemapping::Agx);
+    assert_eq!("aces".parse::<Tonemapping>().unwrap(), Tonemapping::Aces);
+    assert!("filmic".parse::<Tonemapping>().is_err());
+    assert_eq!(Tonemapping::Agx.next(), Tonemapping::None);
+}
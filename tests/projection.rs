@@ -0,0 +1,154 @@
+use cgmath::{assert_abs_diff_eq, Deg, InnerSpace, Point3, Transform, Vector3, Vector4};
+use rustgl::{Camera, Projection, ProjectionMode};
+
+fn camera(projection: ProjectionMode) -> Camera {
+    Camera {
+        eye: (1.0, 2.0, 4.0).into(),
+        target: (0.0, 0.5, 0.0).into(),
+        up: Vector3::unit_y(),
+        aspect: 1.5,
+        fovy: 50.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection,
+    }
+}
+
+/// Projects a world space point to normalized device coordinates
+fn ndc(camera: &Camera, point: Point3<f32>) -> Point3<f32> {
+    camera.view_projection_matrix().transform_point(point)
+}
+
+/// Projects a view space point at `distance` in front of the camera
+fn depth(projection: &Projection, distance: f32) -> f32 {
+    let clip = projection.matrix() * Vector4::new(0.0, 0.0, -distance, 1.0);
+    clip.z / clip.w
+}
+
+#[test]
+fn orthographic_keeps_framing_of_target() {
+    let perspective = camera(ProjectionMode::Perspective);
+    let orthographic = camera(ProjectionMode::Orthographic);
+
+    // Points on the plane through the target facing the camera land in the
+    // same place on screen either way
+    let forward = (perspective.target - perspective.eye).normalize();
+    let right = forward.cross(perspective.up).normalize();
+    let up = right.cross(forward);
+    for (x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, -0.7), (-1.3, 0.4)] {
+        let point = perspective.target + right * x + up * y;
+        let a = ndc(&perspective, point);
+        let b = ndc(&orthographic, point);
+        assert_abs_diff_eq!(a.x, b.x, epsilon = 1e-5);
+        assert_abs_diff_eq!(a.y, b.y, epsilon = 1e-5);
+    }
+
+    // But unlike perspective, orthographic doesn't shrink things with distance
+    let near = perspective.target + right - forward;
+    let far = perspective.target + right + forward;
+    assert!(ndc(&perspective, near).x > ndc(&perspective, far).x);
+    assert_abs_diff_eq!(
+        ndc(&orthographic, near).x,
+        ndc(&orthographic, far).x,
+        epsilon = 1e-5
+    );
+}
+
+#[test]
+fn depth_ranges() {
+    let perspective = Projection::Perspective {
+        fovy: Deg(60.0).into(),
+        aspect: 1.0,
+        znear: 0.5,
+        zfar: 50.0,
+    };
+    assert_abs_diff_eq!(depth(&perspective, 0.5), 0.0, epsilon = 1e-6);
+    assert_abs_diff_eq!(depth(&perspective, 50.0), 1.0, epsilon = 1e-6);
+
+    let orthographic = Projection::orthographic(4.0, 3.0, 0.5, 50.0);
+    assert_abs_diff_eq!(depth(&orthographic, 0.5), 0.0, epsilon = 1e-6);
+    assert_abs_diff_eq!(depth(&orthographic, 25.25), 0.5, epsilon = 1e-6);
+    assert_abs_diff_eq!(depth(&orthographic, 50.0), 1.0, epsilon = 1e-6);
+    let corner = orthographic.matrix() * Vector4::new(2.0, 1.5, -10.0, 1.0);
+    assert_abs_diff_eq!(corner.x / corner.w, 1.0, epsilon = 1e-6);
+    assert_abs_diff_eq!(corner.y / corner.w, 1.0, epsilon = 1e-6);
+
+    let reverse_z = Projection::ReverseZ {
+        fovy: Deg(60.0).into(),
+        aspect: 1.0,
+        znear: 0.5,
+    };
+    assert_abs_diff_eq!(depth(&reverse_z, 0.5), 1.0, epsilon = 1e-6);
+    assert!(reverse_z.zfar().is_infinite());
+    assert!(reverse_z.is_reverse_z());
+}
+
+#[test]
+fn camera_builds_the_selected_projection() {
+    assert!(matches!(
+        camera(ProjectionMode::Perspective).projection(),
+        Projection::Perspective { zfar, .. } if zfar == 100.0
+    ));
+    assert!(camera(ProjectionMode::Orthographic)
+        .projection()
+        .is_orthographic());
+    assert!(camera(ProjectionMode::ReverseZ).projection().is_reverse_z());
+}
+
+#[test]
+fn reverse_z_depth() {
+    let projection = Projection::ReverseZ {
+        fovy: Deg(60.0).into(),
+        aspect: 2.0,
+        znear: 0.1,
+    };
+    // Depth is znear / distance: 1 at the near plane, halving with each
+    // doubling of the distance and never reaching 0
+    for distance in [0.1, 0.2, 1.0, 10.0, 1e3, 1e6] {
+        assert_abs_diff_eq!(depth(&projection, distance), 0.1 / distance, epsilon = 1e-9);
+    }
+    let mut last = f32::INFINITY;
+    for distance in (1..100).map(|i| i as f32 * 37.0) {
+        let depth = depth(&projection, distance);
+        assert!(depth > 0.0 && depth < last);
+        last = depth;
+    }
+    // Beyond the near plane is clipped
+    assert!(depth(&projection, 0.05) > 1.0);
+
+    // x and y are projected the same as with a standard perspective
+    let standard = Projection::Perspective {
+        fovy: Deg(60.0).into(),
+        aspect: 2.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+    let point = Vector4::new(3.0, -2.0, -7.0, 1.0);
+    let a = projection.matrix() * point;
+    let b = standard.matrix() * point;
+    assert_abs_diff_eq!(a.x / a.w, b.x / b.w, epsilon = 1e-6);
+    assert_abs_diff_eq!(a.y / a.w, b.y / b.w, epsilon = 1e-6);
+    assert_abs_diff_eq!(a.w, 7.0);
+}
+
+#[test]
+fn reverse_z_keeps_precision_far_away() {
+    let reverse_z = Projection::ReverseZ {
+        fovy: Deg(45.0).into(),
+        aspect: 1.0,
+        znear: 0.1,
+    };
+    let standard = Projection::Perspective {
+        fovy: Deg(45.0).into(),
+        aspect: 1.0,
+        znear: 0.1,
+        zfar: 10_000.0,
+    };
+    // Surfaces a quarter metre apart at five kilometres
+    let (front, back) = (5000.0, 5000.25);
+
+    // Float depth near 0 is fine grained, so reverse-Z tells them apart...
+    assert!(depth(&reverse_z, front) > depth(&reverse_z, back));
+    // ...while near 1 standard depth rounds both to the same value
+    assert_eq!(depth(&standard, front), depth(&standard, back));
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use std::path::PathBuf;
+
+use cgmath::{Deg, Vector3};
+use common::{assert_golden, Tolerance, HEIGHT, WIDTH};
+use rustgl::{
+    headless::Headless,
+    instance::{Instance, InstanceBuffer},
+    light::{Attenuation, Light, MAX_SHADOWS},
+    Camera, ProjectionMode, ShadowSettings, MAX_CASCADES,
+};
+
+/// Two cubes on a flattened cube as floor, lit by a sun and a spot light
+fn headless() -> Headless {
+    let mut headless = common::headless();
+    let cube = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets/cube.obj");
+    headless
+        .renderer
+        .load_models(&headless.device, &headless.queue, &[cube])
+        .unwrap();
+    headless.renderer.models[0].instances = InstanceBuffer::new(
+        &headless.device,
+        vec![
+            Instance::default(),
+            Instance {
+                position: Vector3::new(-1.5, -0.25, 0.5),
+                scale: Vector3::new(0.5, 0.5, 0.5),
+                ..Default::default()
+            },
+            Instance {
+                position: Vector3::new(0.0, -0.55, 0.0),
+                scale: Vector3::new(6.0, 0.1, 6.0),
+                ..Default::default()
+            },
+        ],
+    );
+    headless.renderer.camera = Camera {
+        eye: (2.0, 2.5, 3.5).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    };
+
+    let lights = &mut headless.renderer.lights;
+    lights.clear();
+    lights.set_ambient([0.05; 3]);
+    lights.add(sun()).unwrap();
+    lights
+        .add(Light::Spot {
+            position: Vector3::new(-2.5, 2.0, -0.5),
+            direction: Vector3::new(0.5, -1.0, 0.5),
+            color: [1.0, 0.6, 0.2],
+            intensity: 12.0,
+            attenuation: Attenuation::default(),
+            inner_angle: Deg(20.0),
+            outer_angle: Deg(30.0),
+            cast_shadows: true,
+        })
+        .unwrap();
+    headless
+}
+
+fn sun() -> Light {
+    Light::Directional {
+        direction: Vector3::new(1.0, -1.5, -0.5),
+        color: [1.0; 3],
+        intensity: 1.0,
+        cast_shadows: true,
+    }
+}
+
+#[test]
+fn directional_and_spot_shadows() {
+    let mut headless = headless();
+    let frame = headless.render().unwrap();
+    assert_golden("shadows", &frame, Tolerance::default());
+}
+
+#[test]
+fn shadow_settings_at_runtime() {
+    let mut headless = headless();
+    headless.render().unwrap();
+
+    let settings = ShadowSettings {
+        resolution: 256,
+        pcf_kernel_size: 5,
+        ..headless.renderer.shadow_settings()
+    };
+    headless
+        .renderer
+        .set_shadow_settings(&headless.device, settings);
+    assert_eq!(headless.renderer.shadow_settings(), settings);
+    let frame = headless.render().unwrap();
+    assert_golden("shadows_low_res_soft", &frame, Tolerance::default());
+}
+
+#[test]
+fn shadow_casters_are_limited() {
+    let mut headless = headless();
+    let lights = &mut headless.renderer.lights;
+    lights.add(Light::Point {
+        position: Vector3::new(0.0, 1.0, 0.0),
+        color: [1.0; 3],
+        intensity: 1.0,
+        attenuation: Attenuation::default(),
+    });
+    assert_eq!(lights.shadow_casters().count(), 2);
+
+    for _ in 0..MAX_SHADOWS {
+        lights.add(sun()).unwrap();
+    }
+    assert_eq!(lights.shadow_casters().count(), MAX_SHADOWS);
+    // Lights beyond the limit still light the scene, just without shadows
+    headless.render().unwrap();
+}
+
+/// Rows of cubes on a 200x200 floor, seen from a low camera reaching 150 units
+fn terrain() -> Headless {
+    let mut headless = headless();
+    let mut instances = vec![Instance {
+        position: Vector3::new(0.0, -0.55, 0.0),
+        scale: Vector3::new(200.0, 0.1, 200.0),
+        ..Default::default()
+    }];
+    for row in 0..12 {
+        let distance = 3.0 + (row * row) as f32 * 0.9;
+        for column in -2..=2 {
+            instances.push(Instance {
+                position: Vector3::new(column as f32 * (distance * 0.3).max(2.0), 0.0, -distance),
+                ..Default::default()
+            });
+        }
+    }
+    headless.renderer.models[0].instances = InstanceBuffer::new(&headless.device, instances);
+    headless.renderer.camera = Camera {
+        eye: (0.0, 1.5, 4.0).into(),
+        target: (0.0, 0.5, -10.0).into(),
+        zfar: 150.0,
+        projection: ProjectionMode::Perspective,
+        ..headless.renderer.camera
+    };
+    let lights = &mut headless.renderer.lights;
+    lights.clear();
+    lights.set_ambient([0.05; 3]);
+    lights.add(Light::Directional {
+        direction: Vector3::new(1.0, -0.7, 0.6),
+        color: [1.0; 3],
+        intensity: 2.0,
+        cast_shadows: true,
+    });
+    headless
+}
+
+#[test]
+fn cascaded_shadows_far_scene() {
+    let mut headless = terrain();
+    let frame = headless.render().unwrap();
+    assert_golden("cascades", &frame, Tolerance::default());
+}
+
+#[test]
+fn cascade_debug_colors() {
+    let mut headless = terrain();
+    let settings = ShadowSettings {
+        debug_cascades: true,
+        ..headless.renderer.shadow_settings()
+    };
+    headless
+        .renderer
+        .set_shadow_settings(&headless.device, settings);
+    let frame = headless.render().unwrap();
+    assert_golden("cascades_debug", &frame, Tolerance::default());
+}
+
+#[test]
+fn cascade_count_is_clamped() {
+    let mut headless = terrain();
+    for (cascades, expected) in [(0, 1), (2, 2), (9, MAX_CASCADES as u32)] {
+        let settings = ShadowSettings {
+            cascades,
+            ..headless.renderer.shadow_settings()
+        };
+        headless
+            .renderer
+            .set_shadow_settings(&headless.device, settings);
+        assert_eq!(headless.renderer.shadow_settings().cascades, expected);
+        headless.render().unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use std::path::PathBuf;
+
+use cgmath::{Point3, Vector3};
+use common::{assert_golden, Tolerance, HEIGHT, WIDTH};
+use rustgl::{
+    headless::Headless,
+    skybox::Skybox,
+    texture::{Texture, TextureOptions},
+    ProjectionMode,
+};
+
+/// Color of each cube map face, in wgpu's order: +X, -X, +Y, -Y, +Z, -Z
+const FACE_COLORS: [[u8; 3]; 6] = [
+    [255, 0, 0],
+    [0, 255, 0],
+    [0, 0, 255],
+    [255, 255, 0],
+    [255, 0, 255],
+    [0, 255, 255],
+];
+
+/// Directions to look in, the world space axis each one's face is seen
+/// along, and an up vector for the camera
+const VIEWS: [(Vector3<f32>, usize, Vector3<f32>); 6] = [
+    (Vector3::new(1.0, 0.0, 0.0), 0, Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(-1.0, 0.0, 0.0), 1, Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(0.0, 1.0, 0.0), 2, Vector3::new(0.0, 0.0, -1.0)),
+    (
+        Vector3::new(0.0, -1.0, 0.0),
+        3,
+        Vector3::new(0.0, 0.0, -1.0),
+    ),
+    // Cube maps are left-handed, so +Z is straight ahead of the default
+    // camera looking down -Z
+    (Vector3::new(0.0, 0.0, -1.0), 4, Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(0.0, 0.0, 1.0), 5, Vector3::new(0.0, 1.0, 0.0)),
+];
+
+/// A renderer with nothing in the scene
+fn headless() -> Headless {
+    let mut headless = common::headless();
+    headless.renderer.models.clear();
+    headless
+}
+
+fn face_paths() -> [PathBuf; 6] {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    std::array::from_fn(|face| {
+        let path = dir.join(format!("sky_face_{face}.png"));
+        let [r, g, b] = FACE_COLORS[face];
+        image::RgbImage::from_pixel(16, 16, image::Rgb([r, g, b]))
+            .save(&path)
+            .unwrap();
+        path
+    })
+}
+
+/// A panorama colored by the axis closest to each texel's direction, the
+/// way the faces of `FACE_COLORS` would look from the inside
+fn panorama() -> image::Rgb32FImage {
+    use std::f32::consts::PI;
+    let (width, height) = (256, 128);
+    image::Rgb32FImage::from_fn(width, height, |x, y| {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (y as f32 + 0.5) / height as f32;
+        let (azimuth, polar) = ((u - 0.5) * 2.0 * PI, v * PI);
+        // The middle of the panorama is straight ahead, down -Z
+        let direction = [
+            polar.sin() * azimuth.sin(),
+            polar.cos(),
+            -polar.sin() * azimuth.cos(),
+        ];
+        let (axis, value) = direction
+            .into_iter()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .unwrap();
+        // Back to the left-handed cube convention
+        let positive = if axis == 2 { value < 0.0 } else { value > 0.0 };
+        let face = axis * 2 + if positive { 0 } else { 1 };
+        image::Rgb(FACE_COLORS[face].map(|c| c as f32 / 255.0))
+    })
+}
+
+fn look(headless: &mut Headless, direction: Vector3<f32>, up: Vector3<f32>) -> image::RgbaImage {
+    let camera = &mut headless.renderer.camera;
+    camera.eye = Point3::new(0.0, 0.0, 0.0);
+    camera.target = camera.eye + direction;
+    camera.up = up;
+    headless.render().unwrap()
+}
+
+fn center(frame: &image::RgbaImage) -> [u8; 3] {
+    let [r, g, b, _] = frame.get_pixel(WIDTH / 2, HEIGHT / 2).0;
+    [r, g, b]
+}
+
+fn assert_color(actual: [u8; 3], expected: [u8; 3]) {
+    assert!(
+        actual.iter().zip(expected).all(|(a, e)| a.abs_diff(e) <= 2),
+        "{actual:?} != {expected:?}"
+    );
+}
+
+/// Looks along every axis and at the edges of a wide view down -Z, so
+/// flipped or mirrored faces show up.
+fn assert_faces(headless: &mut Headless) {
+    for (direction, face, up) in VIEWS {
+        let frame = look(headless, direction, up);
+        assert_color(center(&frame), FACE_COLORS[face]);
+    }
+
+    headless.renderer.camera.fovy = 100.0;
+    let frame = look(headless, -Vector3::unit_z(), Vector3::unit_y());
+    let pixel = |x, y| {
+        let [r, g, b, _] = frame.get_pixel(x, y).0;
+        [r, g, b]
+    };
+    assert_color(pixel(WIDTH - 1, HEIGHT / 2), FACE_COLORS[0]);
+    assert_color(pixel(0, HEIGHT / 2), FACE_COLORS[1]);
+    assert_color(pixel(WIDTH / 2, 0), FACE_COLORS[2]);
+    assert_color(pixel(WIDTH / 2, HEIGHT - 1), FACE_COLORS[3]);
+    headless.renderer.camera.fovy = 45.0;
+}
+
+#[test]
+fn skybox_is_optional() {
+    let mut headless = headless();
+    assert!(headless.renderer.skybox().is_none());
+    headless.renderer.clear_color = wgpu::Color::RED;
+    let frame = headless.render().unwrap();
+    assert_eq!(center(&frame), [255, 0, 0]);
+}
+
+#[test]
+fn skybox_from_faces() {
+    let mut headless = headless();
+    headless
+        .renderer
+        .load_skybox(&headless.device, &headless.queue, &face_paths())
+        .unwrap();
+    let cube = &headless.renderer.skybox().unwrap().texture.texture;
+    assert_eq!(cube.depth_or_array_layers(), 6);
+    assert_eq!(cube.format(), wgpu::TextureFormat::Rgba8UnormSrgb);
+    assert_faces(&mut headless);
+
+    // The same with the depth test the other way round
+    headless.renderer.camera.projection = ProjectionMode::Perspective;
+    assert_faces(&mut headless);
+}
+
+#[test]
+fn skybox_from_equirectangular_panorama() {
+    let mut headless = headless();
+    let panorama = Texture::from_image_with(
+        &headless.device,
+        &headless.queue,
+        &image::DynamicImage::ImageRgb32F(panorama()),
+        Some("panorama"),
+        TextureOptions::default(),
+    )
+    .unwrap();
+    let skybox = Skybox::from_equirectangular(&headless.device, &headless.queue, &panorama, 64);
+    let cube = &skybox.texture.texture;
+    assert_eq!(cube.size().width, 64);
+    assert_eq!(cube.mip_level_count(), 7);
+    assert_eq!(cube.format(), Skybox::FORMAT);
+    headless.renderer.set_skybox(&headless.device, Some(skybox));
+    assert_faces(&mut headless);
+}
+
+#[test]
+fn skybox_behind_the_scene() {
+    let mut headless = common::headless();
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("sky_panorama.hdr");
+    let file = std::fs::File::create(&path).unwrap();
+    let panorama = panorama();
+    image::codecs::hdr::HdrEncoder::new(file)
+        .encode(
+            &panorama.pixels().copied().collect::<Vec<_>>(),
+            panorama.width() as usize,
+            panorama.height() as usize,
+        )
+        .unwrap();
+    headless
+        .renderer
+        .load_skybox(&headless.device, &headless.queue, &[path])
+        .unwrap();
+    assert_eq!(
+        headless.renderer.skybox().unwrap().texture.texture.width(),
+        64
+    );
+
+    let frame = headless.render().unwrap();
+    assert_golden("skybox_pentagon", &frame, Tolerance::default());
+}
+
+#[test]
+fn skybox_needs_one_or_six_images() {
+    let mut headless = headless();
+    let paths = face_paths();
+    let error = headless
+        .renderer
+        .load_skybox(&headless.device, &headless.queue, &paths[..2])
+        .unwrap_err();
+    assert!(error.to_string().contains("2 images"), "{error}");
+    assert!(headless.renderer.skybox().is_none());
+}
@@ -0,0 +1,545 @@
+mod common;
+
+use std::path::PathBuf;
+
+use cgmath::Transform;
+use common::{assert_golden, headless, Tolerance, HEIGHT, WIDTH};
+use rustgl::{
+    headless::Headless,
+    model::{Material, MaterialMaps, MaterialParams, Mesh, Model},
+    texture::{
+        generate_mipmaps, mip_level_count, Channels, Filtering, MipPolicy, Precision, Texture,
+        TextureOptions,
+    },
+    Camera, ProjectionMode, Vertex,
+};
+
+/// Black and white squares of `cell` texels
+fn checker(size: u32, cell: u32) -> image::RgbaImage {
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        if (x / cell + y / cell).is_multiple_of(2) {
+            image::Rgba([255, 255, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+#[test]
+fn mip_chain_sizes() {
+    assert_eq!(mip_level_count(1, 1), 1);
+    assert_eq!(mip_level_count(256, 128), 9);
+    assert_eq!(mip_level_count(5, 3), 3);
+    assert_eq!(mip_level_count(1, 1000), 10);
+
+    let levels = generate_mipmaps(&checker(5, 1), true);
+    let sizes: Vec<_> = levels.iter().map(|level| level.dimensions()).collect();
+    assert_eq!(sizes, [(5, 5), (2, 2), (1, 1)]);
+    assert_eq!(levels[0], checker(5, 1));
+}
+
+#[test]
+fn mipmaps_average_in_linear_space() {
+    let img = checker(64, 1);
+    // Half the light of white, which sRGB stores as 188 rather than 128
+    let srgb = generate_mipmaps(&img, true);
+    assert_eq!(srgb.len(), 7);
+    for level in &srgb[1..] {
+        for pixel in level.pixels() {
+            assert_eq!(pixel.0, [188, 188, 188, 255]);
+        }
+    }
+    let linear = generate_mipmaps(&img, false);
+    assert_eq!(
+        linear.last().unwrap().get_pixel(0, 0).0,
+        [128, 128, 128, 255]
+    );
+
+    // Alpha is averaged as is
+    let mut transparent = image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255]));
+    transparent.put_pixel(1, 1, image::Rgba([255, 0, 0, 0]));
+    let levels = generate_mipmaps(&transparent, true);
+    assert_eq!(levels[1].get_pixel(0, 0).0, [255, 0, 0, 191]);
+}
+
+#[test]
+fn filtering_decides_mip_levels() {
+    let headless = headless();
+    let img = image::DynamicImage::ImageRgba8(checker(64, 4));
+    let levels = |filtering| {
+        Texture::from_image_filtered(
+            &headless.device,
+            &headless.queue,
+            &img,
+            None,
+            true,
+            filtering,
+        )
+        .unwrap()
+        .texture
+        .mip_level_count()
+    };
+    assert_eq!(levels(Filtering::Mipmapped), 7);
+    assert_eq!(levels(Filtering::Linear), 1);
+    assert_eq!(levels(Filtering::Nearest), 1);
+    let default = Texture::from_image(&headless.device, &headless.queue, &img, None, true).unwrap();
+    assert_eq!(default.texture.mip_level_count(), 7);
+}
+
+/// A long floor with a fine checker texture, seen at a grazing angle
+fn floor_render(filtering: Filtering) -> image::RgbaImage {
+    let mut headless = headless();
+    let img = image::DynamicImage::ImageRgba8(checker(512, 1));
+    let texture = Texture::from_image_filtered(
+        &headless.device,
+        &headless.queue,
+        &img,
+        Some("checker"),
+        true,
+        filtering,
+    )
+    .unwrap();
+    let vertices: Vec<Vertex> = [(-2.0, 0.0), (2.0, 0.0), (2.0, -40.0), (-2.0, -40.0)]
+        .into_iter()
+        .map(|(x, z)| Vertex {
+            position: [x, 0.0, z],
+            tex_coords: [(x + 2.0) / 4.0, -z / 40.0],
+            normal: [0.0, 1.0, 0.0],
+        })
+        .collect();
+    let mesh = Mesh::new(&headless.device, "floor", &vertices, &[0, 1, 2, 0, 2, 3], 0);
+    let material = Material::new(
+        &headless.device,
+        "floor",
+        MaterialMaps {
+            base_color: Some(texture),
+            ..Default::default()
+        },
+        MaterialParams::default(),
+        &headless.renderer.material_layout,
+    );
+    let renderer = &mut headless.renderer;
+    renderer.models = vec![Model::new(&headless.device, vec![mesh], vec![material])];
+    renderer.lights.clear();
+    renderer.lights.set_ambient([1.0; 3]);
+    renderer.camera = Camera {
+        eye: (0.0, 0.4, 0.5).into(),
+        target: (0.0, 0.0, -6.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+        projection: ProjectionMode::Perspective,
+    };
+    headless.render().unwrap()
+}
+
+/// Standard deviation of the red channel in a band of rows from `y` down,
+/// where the floor is far away
+fn far_noise(frame: &image::RgbaImage, y: u32) -> f32 {
+    let values: Vec<f32> = (y..y + 8)
+        .flat_map(|y| (WIDTH / 2 - 20..WIDTH / 2 + 20).map(move |x| (x, y)))
+        .map(|(x, y)| frame.get_pixel(x, y).0[0] as f32)
+        .collect();
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32;
+    variance.sqrt()
+}
+
+#[test]
+fn mipmaps_stop_distant_shimmer() {
+    let mipmapped = floor_render(Filtering::Mipmapped);
+    let nearest = floor_render(Filtering::Nearest);
+    assert_golden("floor_mipmapped", &mipmapped, Tolerance::default());
+
+    // Just below the horizon many texels fall into each pixel. Without mips
+    // the pixels pick one of them at random; with them they blend to grey.
+    let y = HEIGHT / 2 - 2;
+    let (smooth, noisy) = (far_noise(&mipmapped, y), far_noise(&nearest, y));
+    assert!(smooth < 10.0, "{smooth}");
+    assert!(noisy > 4.0 * smooth, "{noisy} vs {smooth}");
+}
+
+#[test]
+fn option_presets() {
+    let color = TextureOptions::default();
+    assert_eq!(color.format(), wgpu::TextureFormat::Rgba8UnormSrgb);
+    assert_eq!(color.mipmaps, MipPolicy::Full);
+    assert_eq!(color.anisotropy, 16);
+    assert_eq!(TextureOptions::from(Filtering::Mipmapped), color);
+
+    assert_eq!(
+        TextureOptions::data().format(),
+        wgpu::TextureFormat::Rgba8Unorm
+    );
+
+    let pixel_art = TextureOptions::pixel_art();
+    assert_eq!(pixel_art.mag_filter, wgpu::FilterMode::Nearest);
+    assert_eq!(pixel_art.min_filter, wgpu::FilterMode::Nearest);
+    assert_eq!(pixel_art.mipmaps, MipPolicy::None);
+    assert!(pixel_art.srgb);
+
+    let tiled = TextureOptions::data().with_address_mode(wgpu::AddressMode::Repeat);
+    assert_eq!(tiled.address_mode_u, wgpu::AddressMode::Repeat);
+    assert_eq!(tiled.address_mode_v, wgpu::AddressMode::Repeat);
+    assert!(!tiled.srgb);
+}
+
+#[test]
+fn load_from_path() {
+    let headless = headless();
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("checker.png");
+    checker(32, 2).save(&path).unwrap();
+
+    let options = TextureOptions {
+        srgb: false,
+        mipmaps: MipPolicy::MaxLevels(3),
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        ..Default::default()
+    };
+    let texture = Texture::from_path(&headless.device, &headless.queue, &path, options).unwrap();
+    assert_eq!(texture.texture.size().width, 32);
+    assert_eq!(texture.texture.mip_level_count(), 3);
+    assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba8Unorm);
+    assert_eq!(
+        texture.texture.usage(),
+        wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST
+    );
+
+    let missing = path.with_file_name("missing.png");
+    let error = Texture::from_path(
+        &headless.device,
+        &headless.queue,
+        &missing,
+        TextureOptions::default(),
+    )
+    .err()
+    .unwrap();
+    assert!(error.to_string().contains("missing.png"), "{error}");
+}
+
+/// Renders a square with texture coordinates from 0 to 2 using a 2x2
+/// texture of red, green, blue and white texels, and returns the color at
+/// the given texture coordinates.
+fn sample_square<const N: usize>(options: TextureOptions, uvs: [(f32, f32); N]) -> [[u8; 3]; N] {
+    let mut headless = headless();
+    let mut img = image::RgbaImage::new(2, 2);
+    img.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+    img.put_pixel(1, 0, image::Rgba([0, 255, 0, 255]));
+    img.put_pixel(0, 1, image::Rgba([0, 0, 255, 255]));
+    img.put_pixel(1, 1, image::Rgba([255, 255, 255, 255]));
+    let texture = Texture::from_image_with(
+        &headless.device,
+        &headless.queue,
+        &image::DynamicImage::ImageRgba8(img),
+        None,
+        options,
+    )
+    .unwrap();
+
+    // x and y from -1 to 1 map to u and v from 0 to 2, v pointing down
+    let vertices: Vec<Vertex> = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+        .into_iter()
+        .map(|(x, y)| Vertex {
+            position: [x, y, 0.0],
+            tex_coords: [x + 1.0, 1.0 - y],
+            normal: [0.0, 0.0, 1.0],
+        })
+        .collect();
+    let mesh = Mesh::new(
+        &headless.device,
+        "square",
+        &vertices,
+        &[0, 1, 2, 0, 2, 3],
+        0,
+    );
+    let material = Material::new(
+        &headless.device,
+        "square",
+        MaterialMaps {
+            base_color: Some(texture),
+            ..Default::default()
+        },
+        MaterialParams::default(),
+        &headless.renderer.material_layout,
+    );
+    let renderer = &mut headless.renderer;
+    renderer.models = vec![Model::new(&headless.device, vec![mesh], vec![material])];
+    renderer.lights.clear();
+    renderer.lights.set_ambient([1.0; 3]);
+    renderer.camera.eye = (0.0, 0.0, 2.0).into();
+    let view_projection = renderer.camera.view_projection_matrix();
+    let frame = headless.render().unwrap();
+
+    uvs.map(|(u, v)| {
+        let ndc = view_projection.transform_point((u - 1.0, 1.0 - v, 0.0).into());
+        let x = (ndc.x + 1.0) / 2.0 * WIDTH as f32;
+        let y = (1.0 - ndc.y) / 2.0 * HEIGHT as f32;
+        let [r, g, b, _] = frame.get_pixel(x as u32, y as u32).0;
+        [r, g, b]
+    })
+}
+
+/// Which of red, green and blue are clearly present
+fn channels([r, g, b]: [u8; 3]) -> [bool; 3] {
+    let max = r.max(g).max(b) as f32;
+    [r, g, b].map(|c| c as f32 > 0.3 * max)
+}
+
+#[test]
+fn address_modes_and_filters() {
+    const RED: [bool; 3] = [true, false, false];
+    const GREEN: [bool; 3] = [false, true, false];
+    const WHITE: [bool; 3] = [true, true, true];
+    let uvs = [(0.25, 0.25), (1.25, 0.25), (1.75, 1.75), (0.5, 0.25)];
+
+    let repeat = TextureOptions::pixel_art().with_address_mode(wgpu::AddressMode::Repeat);
+    let colors = sample_square(repeat, uvs).map(channels);
+    assert_eq!(colors[..3], [RED, RED, WHITE]);
+
+    let clamp = TextureOptions::pixel_art().with_address_mode(wgpu::AddressMode::ClampToEdge);
+    let colors = sample_square(clamp, uvs).map(channels);
+    assert_eq!(colors[..3], [RED, GREEN, WHITE]);
+    // Nearest filtering keeps the border between texels sharp
+    assert!(colors[3] == RED || colors[3] == GREEN);
+
+    // Linear filtering blends red and green between their texel centers
+    let linear = TextureOptions::default().with_address_mode(wgpu::AddressMode::ClampToEdge);
+    let colors = sample_square(linear, uvs);
+    assert_eq!(channels(colors[3]), [true, true, false], "{colors:?}");
+}
+
+/// The bytes of the first mip level, without row padding.
+fn read_texels(headless: &Headless, texture: &Texture) -> Vec<u8> {
+    let size = texture.texture.size();
+    let block_size = texture.texture.format().block_copy_size(None).unwrap();
+    let row = size.width * block_size;
+    let padded_row = row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = headless.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texel Readback"),
+        size: (padded_row * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = headless
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        texture.texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            depth_or_array_layers: 1,
+            ..size
+        },
+    );
+    headless.queue.submit(Some(encoder.finish()));
+    buffer.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+    headless.device.poll(wgpu::Maintain::Wait);
+    let data = buffer.slice(..).get_mapped_range();
+    data.chunks(padded_row as usize)
+        .flat_map(|chunk| &chunk[..row as usize])
+        .copied()
+        .collect()
+}
+
+fn load(headless: &Headless, name: &str, options: TextureOptions) -> (Texture, Vec<u8>) {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let options = TextureOptions {
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        ..options
+    };
+    let texture = Texture::from_path(&headless.device, &headless.queue, &path, options).unwrap();
+    let texels = read_texels(headless, &texture);
+    (texture, texels)
+}
+
+fn halves(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks(2)
+        .map(|b| half::f16::from_le_bytes([b[0], b[1]]).to_f32())
+        .collect()
+}
+
+/// Brightness well past 1, as in the sky of an environment map
+fn bright(x: u32, y: u32) -> [f32; 3] {
+    [x as f32 * 10.0, y as f32 * 0.25, 1000.0]
+}
+
+#[test]
+fn high_dynamic_range_images() {
+    let headless = headless();
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let rgb = image::Rgb32FImage::from_fn(8, 4, |x, y| image::Rgb(bright(x, y)));
+    let file = std::fs::File::create(dir.join("bright.hdr")).unwrap();
+    image::codecs::hdr::HdrEncoder::new(file)
+        .encode(&rgb.pixels().copied().collect::<Vec<_>>(), 8, 4)
+        .unwrap();
+    let rgba = image::Rgba32FImage::from_fn(8, 4, |x, y| {
+        let [r, g, b] = bright(x, y);
+        image::Rgba([r, g, b, 0.5])
+    });
+    image::DynamicImage::ImageRgba32F(rgba.clone())
+        .save(dir.join("bright.exr"))
+        .unwrap();
+
+    // .hdr shares an exponent between channels, so each is good to about
+    // 1% of the brightest
+    let (texture, texels) = load(&headless, "bright.hdr", TextureOptions::default());
+    assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba16Float);
+    assert_eq!(texture.texture.mip_level_count(), 4);
+    for (texel, expected) in halves(&texels).chunks(4).zip(rgb.pixels()) {
+        let tolerance = 0.01 * expected.0.iter().copied().fold(0.0, f32::max);
+        for c in 0..3 {
+            assert!(
+                (texel[c] - expected[c]).abs() <= tolerance,
+                "{texel:?} {expected:?}"
+            );
+        }
+        assert_eq!(texel[3], 1.0);
+    }
+
+    let (texture, texels) = load(&headless, "bright.exr", TextureOptions::default());
+    assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba16Float);
+    for (texel, expected) in halves(&texels).chunks(4).zip(rgba.pixels()) {
+        for c in 0..4 {
+            assert_eq!(half::f16::from_f32(expected[c]).to_f32(), texel[c]);
+        }
+    }
+
+    let options = TextureOptions {
+        precision: Precision::Full,
+        mipmaps: MipPolicy::None,
+        ..Default::default()
+    };
+    let (texture, texels) = load(&headless, "bright.exr", options);
+    assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba32Float);
+    assert_eq!(texture.texture.mip_level_count(), 1);
+    assert_eq!(
+        bytemuck::cast_slice::<u8, f32>(&texels),
+        rgba.as_raw().as_slice()
+    );
+}
+
+/// 16-bit texels as they were in the image, whether they were stored as
+/// 16-bit integers or as 32-bit floats
+fn texels_16bit(texture: &Texture, texels: &[u8]) -> Vec<u16> {
+    use wgpu::TextureFormat::*;
+    match texture.texture.format() {
+        R16Unorm | Rg16Unorm | Rgba16Unorm => bytemuck::pod_collect_to_vec(texels),
+        R32Float | Rg32Float | Rgba32Float => bytemuck::pod_collect_to_vec::<u8, f32>(texels)
+            .iter()
+            .map(|&t| (t * 65535.0).round() as u16)
+            .collect(),
+        format => panic!("{format:?} loses precision"),
+    }
+}
+
+#[test]
+fn precise_and_single_channel_images() {
+    let headless = headless();
+    let norm16 = headless
+        .device
+        .features()
+        .contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM);
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    // Steps too fine for 8 bits, or for the 11 of a 16-bit float
+    let fine = |x: u32| 60000 + x as u16;
+    image::ImageBuffer::<image::Rgba<u16>, _>::from_fn(16, 2, |x, _| {
+        image::Rgba([fine(x), fine(x), 65535, 65535])
+    })
+    .save(dir.join("fine.png"))
+    .unwrap();
+    image::ImageBuffer::<image::Luma<u16>, _>::from_fn(16, 2, |x, _| image::Luma([fine(x)]))
+        .save(dir.join("fine_gray.png"))
+        .unwrap();
+    image::GrayImage::from_fn(16, 2, |x, _| image::Luma([x as u8 * 16]))
+        .save(dir.join("gray.png"))
+        .unwrap();
+
+    // Every bit is kept where asked for
+    let lossless = TextureOptions {
+        precision: Precision::Lossless,
+        ..TextureOptions::data()
+    };
+    let (texture, texels) = load(&headless, "fine.png", lossless);
+    let expected = if norm16 {
+        wgpu::TextureFormat::Rgba16Unorm
+    } else {
+        wgpu::TextureFormat::Rgba32Float
+    };
+    assert_eq!(texture.texture.format(), expected);
+    let reds: Vec<u16> = texels_16bit(&texture, &texels)
+        .chunks(4)
+        .take(16)
+        .map(|t| t[0])
+        .collect();
+    assert_eq!(reds, (0..16).map(fine).collect::<Vec<_>>());
+
+    // By default only as far as filterable formats allow, so the texture
+    // fits a material
+    let (texture, _) = load(&headless, "fine.png", TextureOptions::data());
+    let expected = if norm16 {
+        wgpu::TextureFormat::Rgba16Unorm
+    } else {
+        wgpu::TextureFormat::Rgba16Float
+    };
+    assert_eq!(texture.texture.format(), expected);
+
+    // Color is decoded from sRGB to linear
+    let (texture, _) = load(&headless, "fine.png", TextureOptions::default());
+    assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba16Float);
+    let options = TextureOptions {
+        precision: Precision::Lossless,
+        ..Default::default()
+    };
+    let (texture, texels) = load(&headless, "fine.png", options);
+    assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba32Float);
+    let texel = &bytemuck::pod_collect_to_vec::<u8, f32>(&texels)[..4];
+    let expected = ((fine(0) as f32 / 65535.0 + 0.055) / 1.055).powf(2.4);
+    assert!((texel[0] - expected).abs() < 1e-6, "{texel:?}");
+    assert_eq!(texel[2], 1.0);
+
+    // Gray is repeated in red, green and blue, as materials expect
+    let (texture, texels) = load(&headless, "fine_gray.png", lossless);
+    assert_eq!(texture.texture.mip_level_count(), 5);
+    let texels = texels_16bit(&texture, &texels);
+    assert_eq!(texels.len(), 16 * 2 * 4);
+    assert_eq!(texels[4..8], [fine(1), fine(1), fine(1), 65535]);
+
+    let (texture, texels) = load(&headless, "gray.png", TextureOptions::default());
+    assert_eq!(
+        texture.texture.format(),
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    );
+    assert_eq!(texels[4..8], [16, 16, 16, 255]);
+
+    // Or kept as a single channel
+    let options = TextureOptions {
+        channels: Channels::Source,
+        ..lossless
+    };
+    let (texture, texels) = load(&headless, "fine_gray.png", options);
+    let expected = if norm16 {
+        wgpu::TextureFormat::R16Unorm
+    } else {
+        wgpu::TextureFormat::R32Float
+    };
+    assert_eq!(texture.texture.format(), expected);
+    let grays = texels_16bit(&texture, &texels);
+    assert_eq!(grays.len(), 32);
+    assert_eq!(grays[..16], (0..16).map(fine).collect::<Vec<_>>());
+
+    let (texture, texels) = load(&headless, "gray.png", options);
+    assert_eq!(texture.texture.format(), wgpu::TextureFormat::R8Unorm);
+    assert_eq!(&texels[..16], (0..16).map(|x| x * 16).collect::<Vec<u8>>());
+}